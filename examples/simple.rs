@@ -9,13 +9,18 @@ extern crate trips;
 fn main() {
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
-    let mut renderer = trips::Renderer::new(&window);
+    let mut renderer = trips::Renderer::new(&window, trips::RendererConfig::default()).unwrap();
+    renderer.set_sample_count(4).unwrap();
     
     let mut mesh_obj = renderer.load_mesh("res/Box.gltf");
     let mut monkey = renderer.load_mesh("res/monkey.gltf");
 
     mesh_obj.material.render_properties.albedo = glam::Vec4::new(1.0,0.0,1.0,1.0);
     monkey.material.render_properties.albedo = glam::Vec4::new(1.0,1.0,1.0,1.0);
+
+    let mut scene = trips::Scene::new();
+    scene.insert(mesh_obj);
+    scene.insert(monkey);
     event_loop.run(move |event, _, control_flow| {
         match event {
             Event::WindowEvent {
@@ -34,18 +39,13 @@ fn main() {
                 }
             }
             Event::RedrawRequested(_) => {
-                let mut scene = trips::Scene {
-                    meshes: Vec::new()
-                };
-                scene.meshes.push(&mesh_obj);
-                scene.meshes.push(&monkey);
                 renderer.update();
-                match renderer.draw(&scene) {
+                match renderer.draw(&mut scene) {
                     Ok(_) => {}
                     // Recreate the swap_chain if lost
-                    Err(wgpu::SwapChainError::Lost) => renderer.rebuild_swapchain(),
+                    Err(trips::RendererError::SwapChain(wgpu::SwapChainError::Lost)) => renderer.rebuild_swapchain(),
                     // The system is out of memory, we should probably quit
-                    Err(wgpu::SwapChainError::OutOfMemory) => *control_flow = ControlFlow::Exit,
+                    Err(trips::RendererError::SwapChain(wgpu::SwapChainError::OutOfMemory)) => *control_flow = ControlFlow::Exit,
                     // All other errors (Outdated, Timeout) should be resolved by the next frame
                     Err(e) => eprintln!("{:?}", e),
                 }
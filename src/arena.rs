@@ -0,0 +1,174 @@
+// Generational arena. Slots are reused after a remove, but every reuse bumps the
+// slot's generation so an old Index can't reach the new occupant.
+
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+pub struct Index {
+    index: u32,
+    generation: u32
+}
+
+impl Index {
+    pub(crate) fn slot(&self) -> usize {
+        self.index as usize
+    }
+}
+
+enum Entry<T> {
+    Occupied { generation: u32, value: T },
+    Free { generation: u32 }
+}
+
+pub struct Arena<T> {
+    entries: Vec<Entry<T>>,
+    free_list: Vec<u32>,
+    len: usize
+}
+
+impl<T> Arena<T> {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            free_list: Vec::new(),
+            len: 0
+        }
+    }
+
+    pub fn insert(&mut self, value: T) -> Index {
+        self.len += 1;
+
+        if let Some(index) = self.free_list.pop() {
+            let entry = &mut self.entries[index as usize];
+            let generation = match entry {
+                Entry::Free { generation } => *generation,
+                Entry::Occupied { .. } => unreachable!("occupied slot in the free list")
+            };
+            *entry = Entry::Occupied { generation, value };
+            return Index { index, generation };
+        }
+
+        let index = self.entries.len() as u32;
+        self.entries.push(Entry::Occupied { generation: 0, value });
+        Index { index, generation: 0 }
+    }
+
+    pub fn remove(&mut self, index: Index) -> Option<T> {
+        if !self.contains(index) {
+            return None;
+        }
+
+        let next = Entry::Free { generation: index.generation.wrapping_add(1) };
+        let old = std::mem::replace(&mut self.entries[index.slot()], next);
+        self.free_list.push(index.index);
+        self.len -= 1;
+
+        match old {
+            Entry::Occupied { value, .. } => Some(value),
+            Entry::Free { .. } => None
+        }
+    }
+
+    pub fn contains(&self, index: Index) -> bool {
+        self.get(index).is_some()
+    }
+
+    pub fn get(&self, index: Index) -> Option<&T> {
+        match self.entries.get(index.slot()) {
+            Some(Entry::Occupied { generation, value }) if *generation == index.generation => Some(value),
+            _ => None
+        }
+    }
+
+    pub fn get_mut(&mut self, index: Index) -> Option<&mut T> {
+        match self.entries.get_mut(index.slot()) {
+            Some(Entry::Occupied { generation, value }) if *generation == index.generation => Some(value),
+            _ => None
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Number of slots ever handed out, free or not. Slot numbers stay below this.
+    pub fn capacity(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Index, &T)> {
+        self.entries.iter().enumerate().filter_map(|(i, entry)| match entry {
+            Entry::Occupied { generation, value } => Some((Index { index: i as u32, generation: *generation }, value)),
+            Entry::Free { .. } => None
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Index, &mut T)> {
+        self.entries.iter_mut().enumerate().filter_map(|(i, entry)| match entry {
+            Entry::Occupied { generation, value } => Some((Index { index: i as u32, generation: *generation }, value)),
+            Entry::Free { .. } => None
+        })
+    }
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removed_slots_are_reused_with_a_new_generation() {
+        let mut arena = Arena::new();
+        let first = arena.insert("first");
+        let kept = arena.insert("kept");
+        assert_eq!(arena.remove(first), Some("first"));
+        assert_eq!(arena.len(), 1);
+
+        let second = arena.insert("second");
+        assert_eq!(second.slot(), first.slot());
+        assert_ne!(second, first);
+        assert_eq!(arena.capacity(), 2);
+
+        // The old index can't reach whatever took its slot
+        assert_eq!(arena.get(first), None);
+        assert_eq!(arena.get_mut(first), None);
+        assert!(!arena.contains(first));
+        assert_eq!(arena.remove(first), None);
+        assert_eq!(arena.get(second), Some(&"second"));
+        assert_eq!(arena.get(kept), Some(&"kept"));
+    }
+
+    #[test]
+    fn removing_twice_only_frees_once() {
+        let mut arena = Arena::new();
+        let index = arena.insert(1);
+        assert_eq!(arena.remove(index), Some(1));
+        assert_eq!(arena.remove(index), None);
+        assert!(arena.is_empty());
+
+        // The slot went on the free list once, so both of these get their own
+        let a = arena.insert(2);
+        let b = arena.insert(3);
+        assert_ne!(a.slot(), b.slot());
+        assert_eq!(arena.len(), 2);
+    }
+
+    #[test]
+    fn iteration_skips_free_slots() {
+        let mut arena = Arena::new();
+        let indices: Vec<_> = (0..4).map(|value| arena.insert(value)).collect();
+        arena.remove(indices[1]);
+        for (_, value) in arena.iter_mut() {
+            *value *= 10;
+        }
+        let values: Vec<_> = arena.iter().map(|(index, value)| (index, *value)).collect();
+        assert_eq!(values, vec![(indices[0], 0), (indices[2], 20), (indices[3], 30)]);
+    }
+}
@@ -0,0 +1,227 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use crate::error::RendererError;
+use crate::geometry::GeometryHandle;
+use crate::gltf_loader::UriResolver;
+use crate::mesh::Mesh;
+
+// What a load is cached under. The loader's name is part of it so the same bytes read
+// as two different formats don't collide.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub(crate) enum AssetKey {
+    // Canonical, so different spellings of the same file match
+    Path(&'static str, PathBuf),
+    // Loads from memory go by the bytes themselves, compared in full so two different files
+    // can never share an entry. What they fetched through a resolver is checked separately.
+    Content(&'static str, Vec<u8>)
+}
+
+impl AssetKey {
+    // None when the file can't be found, the load itself reports that
+    pub fn path(loader: &'static str, path: &Path) -> Option<Self> {
+        std::fs::canonicalize(path).ok().map(|path| AssetKey::Path(loader, path))
+    }
+
+    pub fn content(loader: &'static str, bytes: &[u8]) -> Self {
+        AssetKey::Content(loader, bytes.to_vec())
+    }
+}
+
+// Passes everything through, remembering what each URI resolved to
+pub(crate) struct RecordingResolver<'a> {
+    resolver: &'a dyn UriResolver,
+    resolved: RefCell<Vec<(String, Vec<u8>)>>
+}
+
+impl<'a> RecordingResolver<'a> {
+    pub fn new(resolver: &'a dyn UriResolver) -> Self {
+        Self {
+            resolver,
+            resolved: RefCell::new(Vec::new())
+        }
+    }
+
+    pub fn into_resolved(self) -> Vec<(String, Vec<u8>)> {
+        self.resolved.into_inner()
+    }
+}
+
+impl UriResolver for RecordingResolver<'_> {
+    fn resolve(&self, uri: &str) -> Result<Vec<u8>, RendererError> {
+        let data = self.resolver.resolve(uri)?;
+        self.resolved.borrow_mut().push((uri.to_string(), data.clone()));
+        Ok(data)
+    }
+}
+
+struct CachedFile {
+    meshes: Vec<Mesh>,
+    // External data the load fetched through its resolver, by URI
+    resolved: Vec<(String, Vec<u8>)>
+}
+
+// Meshes already loaded from each file, and how many users each geometry has. Every mesh
+// handed out is one user, until it's released.
+pub(crate) struct AssetCache {
+    files: HashMap<AssetKey, CachedFile>,
+    references: HashMap<GeometryHandle, usize>
+}
+
+impl AssetCache {
+    pub fn new() -> Self {
+        Self {
+            files: HashMap::new(),
+            references: HashMap::new()
+        }
+    }
+
+    // Copies of the meshes loaded before, which count as new users. Only if everything the
+    // load fetched through its resolver still resolves to the same data, otherwise it's a
+    // different asset and the new load replaces this one.
+    pub fn get(&mut self, key: &AssetKey, resolver: &dyn UriResolver) -> Option<Vec<Mesh>> {
+        let file = self.files.get(key)?;
+        if !file.resolved.iter().all(|(uri, data)| resolver.resolve(uri).map_or(false, |current| current == *data)) {
+            return None;
+        }
+        let meshes = file.meshes.clone();
+        for mesh in &meshes {
+            *self.references.entry(mesh.geometry).or_default() += 1;
+        }
+        Some(meshes)
+    }
+
+    pub fn insert(&mut self, key: AssetKey, meshes: &[Mesh], resolved: Vec<(String, Vec<u8>)>) {
+        for mesh in meshes {
+            *self.references.entry(mesh.geometry).or_default() += 1;
+        }
+        self.files.insert(key, CachedFile { meshes: meshes.to_vec(), resolved });
+    }
+
+    // None when the geometry didn't come through the cache, otherwise whether that was its
+    // last user. The file is dropped from the cache then, the next load reads it again.
+    pub fn release(&mut self, geometry: GeometryHandle) -> Option<bool> {
+        let references = self.references.get_mut(&geometry)?;
+        *references -= 1;
+        if *references > 0 {
+            return Some(false);
+        }
+
+        self.forget(geometry);
+        Some(true)
+    }
+
+    // Drops the files using the geometry so the next load reads them again, the meshes
+    // already handed out keep their users
+    pub fn evict(&mut self, geometry: GeometryHandle) {
+        self.files.retain(|_, file| file.meshes.iter().all(|mesh| mesh.geometry != geometry));
+    }
+
+    // For geometry unloaded directly, without going through release
+    pub fn forget(&mut self, geometry: GeometryHandle) {
+        self.references.remove(&geometry);
+        self.evict(geometry);
+    }
+
+    pub fn references(&self, geometry: GeometryHandle) -> usize {
+        self.references.get(&geometry).copied().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::GeometryEntry;
+    use crate::handles::Pool;
+    use crate::materials::{Material, MaterialBuffers, MaterialType, RenderProperties};
+
+    fn meshes(count: usize) -> Vec<Mesh> {
+        let mut geometries: Pool<(), GeometryEntry> = Pool::new(0);
+        let mut materials: Pool<(), MaterialBuffers> = Pool::new(0);
+        (0..count).map(|_| Mesh::new(
+            geometries.insert(()),
+            Material::new(materials.insert(()), MaterialType::SolidColorMaterial, RenderProperties::default())
+        )).collect()
+    }
+
+    fn no_files(uri: &str) -> Result<Vec<u8>, RendererError> {
+        Err(RendererError::InvalidAsset(format!("no {}", uri)))
+    }
+
+    #[test]
+    fn content_keys_compare_the_bytes() {
+        let mut cache = AssetCache::new();
+        let loaded = meshes(1);
+        cache.insert(AssetKey::content("stl", b"first file"), &loaded, Vec::new());
+
+        assert!(cache.get(&AssetKey::content("stl", b"first file"), &no_files).is_some());
+        assert!(cache.get(&AssetKey::content("stl", b"first filf"), &no_files).is_none());
+        // The same bytes read as another format are another asset
+        assert!(cache.get(&AssetKey::content("ply", b"first file"), &no_files).is_none());
+        assert_eq!(cache.references(loaded[0].geometry), 2);
+    }
+
+    #[test]
+    fn cache_hits_share_geometry_and_material() {
+        let mut cache = AssetCache::new();
+        let key = AssetKey::content("obj", b"o a\no b");
+        let loaded = meshes(2);
+        cache.insert(key.clone(), &loaded, Vec::new());
+
+        // A copy is another user of the same GPU resources, changing its properties doesn't
+        // give it a material of its own
+        let mut copies = cache.get(&key, &no_files).unwrap();
+        copies[0].material.render_properties.albedo = glam::Vec4::new(1.0, 0.0, 0.0, 1.0);
+        for (copy, original) in copies.iter().zip(loaded.iter()) {
+            assert_eq!(copy.geometry, original.geometry);
+            assert_eq!(copy.material.material_handle, original.material.material_handle);
+        }
+    }
+
+    #[test]
+    fn resolved_data_has_to_match() {
+        let mut cache = AssetCache::new();
+        let key = AssetKey::content("gltf", b"{}");
+        let loaded = meshes(1);
+
+        let first = |_: &str| Ok(vec![1, 2, 3]);
+        let recording = RecordingResolver::new(&first);
+        assert_eq!(recording.resolve("mesh.bin").unwrap(), vec![1, 2, 3]);
+        cache.insert(key.clone(), &loaded, recording.into_resolved());
+
+        assert!(cache.get(&key, &first).is_some());
+        // Same file, different buffer behind it
+        let second = |_: &str| Ok(vec![1, 2, 4]);
+        assert!(cache.get(&key, &second).is_none());
+        assert!(cache.get(&key, &no_files).is_none());
+    }
+
+    #[test]
+    fn released_files_are_forgotten() {
+        let mut cache = AssetCache::new();
+        let key = AssetKey::content("obj", b"o a");
+        let loaded = meshes(2);
+        cache.insert(key.clone(), &loaded, Vec::new());
+        cache.get(&key, &no_files).unwrap();
+
+        assert_eq!(cache.release(loaded[0].geometry), Some(false));
+        assert_eq!(cache.release(loaded[0].geometry), Some(true));
+        // One of its meshes is gone, so the next load reads the file again
+        assert!(cache.get(&key, &no_files).is_none());
+        assert_eq!(cache.references(loaded[1].geometry), 2);
+    }
+
+    #[test]
+    fn evicted_files_keep_their_users() {
+        let mut cache = AssetCache::new();
+        let key = AssetKey::content("background", b"broken");
+        let loaded = meshes(1);
+        cache.insert(key.clone(), &loaded, Vec::new());
+        cache.get(&key, &no_files).unwrap();
+
+        cache.evict(loaded[0].geometry);
+        assert!(cache.get(&key, &no_files).is_none());
+        assert_eq!(cache.references(loaded[0].geometry), 2);
+        assert_eq!(cache.release(loaded[0].geometry), Some(false));
+    }
+}
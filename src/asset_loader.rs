@@ -0,0 +1,409 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use crate::error::RendererError;
+use crate::geometry::{Geometry, GeometryHandle};
+use crate::gltf_loader;
+use crate::hot_reload::DependencyResolver;
+use crate::materials::{AlphaMode, RasterizerState, RenderProperties};
+use crate::{obj_loader, ply_loader, stl_loader};
+
+const MAX_WORKERS: usize = 4;
+// Files are read in chunks this size so the progress moves while they come in
+const READ_CHUNK_SIZE: usize = 1 << 16;
+// Share of an asset's progress that's reading the file, the rest is parsing it
+const READ_PROGRESS: f32 = 0.8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadState {
+    // 0 to 1, still showing the placeholder
+    Loading { progress: f32 },
+    Loaded,
+    // Why it failed. The placeholder stays.
+    Failed(String)
+}
+
+// Everything still loading in the background
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct LoadProgress {
+    pub loading: usize,
+    // Average progress of what's loading, 1 when nothing is
+    pub progress: f32
+}
+
+// The material a file asked for, for meshes still drawn with the placeholder one
+#[derive(Debug, Copy, Clone, Default)]
+pub(crate) struct LoadedMaterial {
+    // None when the file doesn't have a color of its own
+    pub render_properties: Option<RenderProperties>,
+    pub alpha_mode: AlphaMode,
+    pub rasterizer: RasterizerState
+}
+
+// A parsed file, ready for the render thread to upload
+pub(crate) struct LoadedAsset {
+    pub geometry: Geometry,
+    pub material: LoadedMaterial,
+    // Files it pulled in, for hot reloading
+    pub dependencies: Vec<PathBuf>
+}
+
+// Anything that should stay off the render thread, loads and hot reloads alike
+pub(crate) type Job = Box<dyn FnOnce() + Send>;
+
+struct Finished {
+    geometry: GeometryHandle,
+    result: Result<LoadedAsset, RendererError>
+}
+
+// Parses files on worker threads. Each load owns a geometry handle that shows the placeholder
+// until the render thread swaps the real geometry in behind it.
+pub(crate) struct AssetLoader {
+    // None until the first load, the workers are only started when something needs them
+    jobs: Option<mpsc::Sender<Job>>,
+    finished_sender: mpsc::Sender<Finished>,
+    finished: mpsc::Receiver<Finished>,
+    workers: Vec<thread::JoinHandle<()>>,
+    // Progress as f32 bits, written by the workers
+    pending: HashMap<GeometryHandle, Arc<AtomicU32>>,
+    failed: HashMap<GeometryHandle, String>,
+    pub loaded_materials: HashMap<GeometryHandle, LoadedMaterial>,
+    // Shared by every load in flight, made on the first one
+    pub placeholder: Option<GeometryHandle>
+}
+
+impl AssetLoader {
+    pub fn new() -> Self {
+        let (finished_sender, finished) = mpsc::channel();
+        Self {
+            jobs: None,
+            finished_sender,
+            finished,
+            workers: Vec::new(),
+            pending: HashMap::new(),
+            failed: HashMap::new(),
+            loaded_materials: HashMap::new(),
+            placeholder: None
+        }
+    }
+
+    pub fn request(&mut self, geometry: GeometryHandle, path: PathBuf) {
+        let progress = Arc::new(AtomicU32::new(0.0f32.to_bits()));
+        self.pending.insert(geometry, progress.clone());
+        self.failed.remove(&geometry);
+        let finished = self.finished_sender.clone();
+        self.run(Box::new(move || {
+            let result = load(&path, &progress);
+            progress.store(1.0f32.to_bits(), Ordering::Relaxed);
+            // Only fails once the loader is gone, and then nobody wants the result
+            let _ = finished.send(Finished { geometry, result });
+        }));
+    }
+
+    // Runs the job on one of the workers
+    pub fn run(&mut self, job: Job) {
+        // The workers only stop once the sender is dropped, so this can't fail
+        let _ = self.jobs().send(job);
+    }
+
+    // Loads the workers have finished since the last call, None for the ones that failed.
+    // Failures are logged and kept for state.
+    pub fn poll(&mut self) -> Vec<(GeometryHandle, Option<LoadedAsset>)> {
+        let mut loaded = Vec::new();
+        while let Ok(finished) = self.finished.try_recv() {
+            // Unloaded while it was still being parsed
+            if self.pending.remove(&finished.geometry).is_none() {
+                continue;
+            }
+            match finished.result {
+                Ok(asset) => {
+                    self.loaded_materials.insert(finished.geometry, asset.material);
+                    loaded.push((finished.geometry, Some(asset)));
+                },
+                Err(err) => {
+                    log::error!("background load failed: {}", err);
+                    self.failed.insert(finished.geometry, err.to_string());
+                    loaded.push((finished.geometry, None));
+                }
+            }
+        }
+
+        loaded
+    }
+
+    pub fn state(&self, geometry: GeometryHandle) -> LoadState {
+        if let Some(progress) = self.pending.get(&geometry) {
+            LoadState::Loading { progress: f32::from_bits(progress.load(Ordering::Relaxed)) }
+        }
+        else if let Some(reason) = self.failed.get(&geometry) {
+            LoadState::Failed(reason.clone())
+        }
+        else {
+            LoadState::Loaded
+        }
+    }
+
+    pub fn progress(&self) -> LoadProgress {
+        if self.pending.is_empty() {
+            return LoadProgress { loading: 0, progress: 1.0 };
+        }
+
+        let total: f32 = self.pending.values().map(|progress| f32::from_bits(progress.load(Ordering::Relaxed))).sum();
+        LoadProgress {
+            loading: self.pending.len(),
+            progress: total / self.pending.len() as f32
+        }
+    }
+
+    // Whatever finishes for the handle from now on is dropped
+    pub fn forget(&mut self, geometry: GeometryHandle) {
+        self.pending.remove(&geometry);
+        self.failed.remove(&geometry);
+        self.loaded_materials.remove(&geometry);
+    }
+
+    fn jobs(&mut self) -> &mpsc::Sender<Job> {
+        if self.jobs.is_none() {
+            let (sender, receiver) = mpsc::channel::<Job>();
+            let receiver = Arc::new(Mutex::new(receiver));
+            // Leave a core for the render thread
+            let count = thread::available_parallelism().map_or(1, |count| count.get().saturating_sub(1)).clamp(1, MAX_WORKERS);
+            for index in 0..count {
+                let receiver = receiver.clone();
+                let worker = thread::Builder::new()
+                    .name(format!("asset loader {}", index))
+                    .spawn(move || loop {
+                        // The lock is only held while waiting, not while the job runs
+                        let job = match receiver.lock() {
+                            Ok(receiver) => receiver.recv(),
+                            Err(_) => break
+                        };
+                        match job {
+                            Ok(job) => job(),
+                            Err(_) => break
+                        }
+                    })
+                    .expect("couldn't start an asset loader thread");
+                self.workers.push(worker);
+            }
+            self.jobs = Some(sender);
+        }
+
+        self.jobs.as_ref().unwrap()
+    }
+}
+
+impl Drop for AssetLoader {
+    // Closing the queue lets the workers finish what they're on and stop
+    fn drop(&mut self) {
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+// Runs on a worker. Everything up to the upload happens here, normals included.
+fn load(path: &Path, progress: &AtomicU32) -> Result<LoadedAsset, RendererError> {
+    let bytes = read_file(path, progress)?;
+    let resolver = DependencyResolver::new(path);
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("").to_ascii_lowercase();
+    let (geometry, material) = match extension.as_str() {
+        "gltf" | "glb" => {
+            let primitive = gltf_loader::read_first_primitive(&bytes, &resolver)?;
+            (primitive.geometry, LoadedMaterial {
+                render_properties: primitive.render_properties,
+                alpha_mode: primitive.alpha_mode,
+                rasterizer: primitive.rasterizer
+            })
+        },
+        // One mesh, so every object in the file goes into it. Textures aren't applied.
+        "obj" => {
+            let primitive = obj_loader::read_merged(&bytes, &resolver)?;
+            let albedo = primitive.material.map(|material| material.albedo);
+            (primitive.geometry, LoadedMaterial {
+                render_properties: albedo.map(|albedo| RenderProperties { albedo, ..RenderProperties::default() }),
+                alpha_mode: if albedo.map_or(false, |albedo| albedo.w < 1.0) { AlphaMode::Blend } else { AlphaMode::Opaque },
+                rasterizer: RasterizerState::default()
+            })
+        },
+        "stl" => (stl_loader::read(&bytes)?, LoadedMaterial::default()),
+        "ply" => (ply_loader::read(&bytes)?, LoadedMaterial::default()),
+        _ => return Err(RendererError::InvalidAsset(format!("{} isn't a format that can be loaded in the background", path.display())))
+    };
+    let mut asset = LoadedAsset { geometry, material, dependencies: resolver.into_dependencies() };
+
+    if asset.geometry.vertex_normals.len() != asset.geometry.vertex_positions.len() {
+        asset.geometry.compute_normals();
+    }
+    Ok(asset)
+}
+
+fn read_file(path: &Path, progress: &AtomicU32) -> Result<Vec<u8>, RendererError> {
+    let mut file = std::fs::File::open(path)?;
+    let size = file.metadata()?.len() as usize;
+    let mut bytes = Vec::with_capacity(size);
+    let mut chunk = vec![0; READ_CHUNK_SIZE];
+    loop {
+        let read = file.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+        bytes.extend_from_slice(&chunk[..read]);
+        let fraction = if size > 0 { (bytes.len() as f32 / size as f32).min(1.0) } else { 1.0 };
+        progress.store((fraction * READ_PROGRESS).to_bits(), Ordering::Relaxed);
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::geometry::GeometryEntry;
+    use crate::handles::Pool;
+
+    const TRIANGLE_PLY: &str = "ply\nformat ascii 1.0\nelement vertex 3\n\
+        property float x\nproperty float y\nproperty float z\n\
+        element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+        0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n";
+    const TRIANGLE_OBJ: &str = "mtllib triangle.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3\n";
+    const MATERIALS: &str = "newmtl red\nKd 1 0 0\nd 0.5\n";
+
+    // Writes the file into a fresh directory and returns its path
+    fn file(test: &str, name: &str, contents: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("trips-asset-loader-{}-{}", std::process::id(), test));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join(name), contents).unwrap();
+        directory.join(name)
+    }
+
+    fn handles(count: usize) -> Vec<GeometryHandle> {
+        let mut geometries: Pool<(), GeometryEntry> = Pool::new(0);
+        (0..count).map(|_| geometries.insert(())).collect()
+    }
+
+    fn wait_for_poll(loader: &mut AssetLoader) -> Vec<(GeometryHandle, Option<LoadedAsset>)> {
+        for _ in 0..500 {
+            let loaded = loader.poll();
+            if !loaded.is_empty() {
+                return loaded;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("nothing finished loading");
+    }
+
+    fn set_progress(loader: &mut AssetLoader, geometry: GeometryHandle, progress: f32) {
+        loader.pending.insert(geometry, Arc::new(AtomicU32::new(progress.to_bits())));
+    }
+
+    #[test]
+    fn load_parses_on_the_spot_and_fills_in_normals() {
+        let path = file("load", "triangle.ply", TRIANGLE_PLY);
+        let progress = AtomicU32::new(0);
+        let asset = load(&path, &progress).unwrap();
+        assert_eq!(asset.geometry.vertex_positions.len(), 3);
+        assert_eq!(asset.geometry.vertex_normals, vec![glam::Vec3::Z; 3]);
+        assert!(asset.material.render_properties.is_none());
+        assert!(asset.dependencies.is_empty());
+        // Reading is done, parsing is what's left
+        assert_eq!(f32::from_bits(progress.load(Ordering::Relaxed)), READ_PROGRESS);
+    }
+
+    #[test]
+    fn load_keeps_the_obj_material_and_its_files() {
+        let path = file("load-obj", "triangle.obj", TRIANGLE_OBJ);
+        file("load-obj", "triangle.mtl", MATERIALS);
+        let asset = load(&path, &AtomicU32::new(0)).unwrap();
+        let albedo = asset.material.render_properties.unwrap().albedo;
+        assert_eq!(albedo, glam::Vec4::new(1.0, 0.0, 0.0, 0.5));
+        assert_eq!(asset.material.alpha_mode, AlphaMode::Blend);
+        assert_eq!(asset.dependencies, vec![std::fs::canonicalize(path.with_file_name("triangle.mtl")).unwrap()]);
+    }
+
+    #[test]
+    fn load_rejects_unknown_and_missing_files() {
+        let path = file("load-unknown", "triangle.xyz", TRIANGLE_PLY);
+        assert!(matches!(load(&path, &AtomicU32::new(0)), Err(RendererError::InvalidAsset(_))));
+        assert!(load(&path.with_file_name("missing.ply"), &AtomicU32::new(0)).is_err());
+    }
+
+    #[test]
+    fn loads_go_from_loading_to_loaded() {
+        let path = file("loaded", "triangle.ply", TRIANGLE_PLY);
+        let geometry = handles(1)[0];
+        let mut loader = AssetLoader::new();
+        loader.request(geometry, path);
+        assert!(matches!(loader.state(geometry), LoadState::Loading { .. }));
+
+        let loaded = wait_for_poll(&mut loader);
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].0, geometry);
+        assert!(loaded[0].1.is_some());
+        assert_eq!(loader.state(geometry), LoadState::Loaded);
+        assert!(loader.loaded_materials.contains_key(&geometry));
+        assert_eq!(loader.progress(), LoadProgress { loading: 0, progress: 1.0 });
+    }
+
+    #[test]
+    fn unknown_extensions_end_up_failed() {
+        let path = file("failed", "triangle.xyz", TRIANGLE_PLY);
+        let geometry = handles(1)[0];
+        let mut loader = AssetLoader::new();
+        loader.request(geometry, path.clone());
+
+        let loaded = wait_for_poll(&mut loader);
+        assert_eq!(loaded[0].0, geometry);
+        assert!(loaded[0].1.is_none());
+        match loader.state(geometry) {
+            LoadState::Failed(reason) => assert!(reason.contains("triangle.xyz"), "{}", reason),
+            state => panic!("expected a failure, got {:?}", state)
+        }
+        assert!(!loader.loaded_materials.contains_key(&geometry));
+
+        // Asking again starts over
+        loader.request(geometry, path.with_file_name("triangle.ply"));
+        assert!(matches!(loader.state(geometry), LoadState::Loading { .. }));
+    }
+
+    #[test]
+    fn progress_is_the_average_of_whats_loading() {
+        let geometries = handles(3);
+        let mut loader = AssetLoader::new();
+        assert_eq!(loader.progress(), LoadProgress { loading: 0, progress: 1.0 });
+
+        set_progress(&mut loader, geometries[0], 0.2);
+        set_progress(&mut loader, geometries[1], 0.6);
+        set_progress(&mut loader, geometries[2], 1.0);
+        let progress = loader.progress();
+        assert_eq!(progress.loading, 3);
+        assert!((progress.progress - 0.6).abs() < 1e-6);
+        assert_eq!(loader.state(geometries[0]), LoadState::Loading { progress: 0.2 });
+    }
+
+    #[test]
+    fn forgotten_loads_are_dropped_when_they_finish() {
+        let geometries = handles(2);
+        let mut loader = AssetLoader::new();
+        set_progress(&mut loader, geometries[0], 0.5);
+        set_progress(&mut loader, geometries[1], 0.5);
+        loader.forget(geometries[0]);
+        assert_eq!(loader.progress().loading, 1);
+
+        // Both finish after the first was forgotten, only the second comes out
+        for geometry in geometries.iter() {
+            let result = Err(RendererError::InvalidAsset("test".to_string()));
+            loader.finished_sender.send(Finished { geometry: *geometry, result }).unwrap();
+        }
+        let loaded = loader.poll();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].0, geometries[1]);
+        assert_eq!(loader.state(geometries[0]), LoadState::Loaded);
+        assert!(matches!(loader.state(geometries[1]), LoadState::Failed(_)));
+    }
+}
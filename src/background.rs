@@ -0,0 +1,626 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use crate::WGPUState;
+use crate::camera::Camera;
+use crate::error::RendererError;
+use crate::hdr;
+use crate::handles::next_resource_id;
+use crate::pipelines::TargetState;
+use crate::shaders::{ShaderStore, ShaderType};
+use crate::texture::Texture;
+
+// What's behind the geometry. Everything but Color and Transparent is drawn with its own
+// pipeline before the opaque meshes.
+#[derive(Debug, Clone)]
+pub enum Background {
+    Color(glam::Vec4),
+    // top is the top of the screen, bottom the bottom
+    Gradient { top: glam::Vec4, bottom: glam::Vec4 },
+    Equirectangular(EnvironmentImage),
+    Cubemap(CubemapImage),
+    // The scene's environment cubemap, nothing is drawn when the scene has none
+    Skybox,
+    // Cleared to zero alpha, for images that get composited over something else
+    Transparent
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Color(glam::Vec4::new(0.1, 0.2, 0.3, 1.0))
+    }
+}
+
+impl Background {
+    pub(crate) fn clear_color(&self) -> wgpu::Color {
+        match self {
+            Background::Color(color) => wgpu::Color {
+                r: color.x as f64,
+                g: color.y as f64,
+                b: color.z as f64,
+                a: color.w as f64
+            },
+            Background::Transparent => wgpu::Color::TRANSPARENT,
+            _ => wgpu::Color::BLACK
+        }
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    }
+    else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn rgba8_to_linear(pixels: &[u8]) -> Vec<[f32; 4]> {
+    pixels.chunks_exact(4)
+        .map(|p| [srgb_to_linear(p[0]), srgb_to_linear(p[1]), srgb_to_linear(p[2]), p[3] as f32 / 255.0])
+        .collect()
+}
+
+// Linear RGBA panorama, longitude across and latitude down
+#[derive(Debug, Clone)]
+pub struct EnvironmentImage {
+    width: u32,
+    height: u32,
+    data: Vec<[f32; 4]>,
+    id: u64,
+    // The file it was loaded from, for hot reloading
+    source: Option<PathBuf>
+}
+
+impl EnvironmentImage {
+    // sRGB encoded RGBA8, the usual LDR image
+    pub fn from_rgba8(width: u32, height: u32, pixels: &[u8]) -> Option<Self> {
+        let len = (width as usize).checked_mul(height as usize).and_then(|len| len.checked_mul(4));
+        if width == 0 || height == 0 || len != Some(pixels.len()) {
+            return None;
+        }
+
+        Some(Self {
+            width,
+            height,
+            data: rgba8_to_linear(pixels),
+            id: next_resource_id(),
+            source: None
+        })
+    }
+
+    // Radiance .hdr file contents
+    pub fn from_hdr(bytes: &[u8]) -> Result<Self, RendererError> {
+        let (width, height, pixels) = hdr::decode(bytes)?;
+        Ok(Self {
+            width,
+            height,
+            data: pixels,
+            id: next_resource_id(),
+            source: None
+        })
+    }
+
+    pub fn load_hdr<P: AsRef<Path>>(path: P) -> Result<Self, RendererError> {
+        let path = path.as_ref();
+        let mut image = EnvironmentImage::from_hdr(&std::fs::read(path)?)?;
+        image.source = Some(std::fs::canonicalize(path)?);
+        Ok(image)
+    }
+
+    // Linear float RGBA, for HDR panoramas
+    pub fn from_rgba32f(width: u32, height: u32, pixels: Vec<[f32; 4]>) -> Option<Self> {
+        if width == 0 || height == 0 || (width as usize).checked_mul(height as usize) != Some(pixels.len()) {
+            return None;
+        }
+
+        Some(Self {
+            width,
+            height,
+            data: pixels,
+            id: next_resource_id(),
+            source: None
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[[f32; 4]] {
+        &self.data
+    }
+
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub(crate) fn source(&self) -> Option<&Path> {
+        self.source.as_deref()
+    }
+}
+
+// Six square linear RGBA faces in +X, -X, +Y, -Y, +Z, -Z order
+#[derive(Debug, Clone)]
+pub struct CubemapImage {
+    size: u32,
+    data: Vec<[f32; 4]>,
+    id: u64
+}
+
+impl CubemapImage {
+    pub fn from_faces_rgba8(size: u32, faces: [&[u8]; 6]) -> Option<Self> {
+        let len = (size as usize).checked_mul(size as usize).and_then(|len| len.checked_mul(4));
+        if size == 0 || faces.iter().any(|face| len != Some(face.len())) {
+            return None;
+        }
+
+        Some(Self {
+            size,
+            data: faces.iter().flat_map(|face| rgba8_to_linear(face)).collect(),
+            id: next_resource_id()
+        })
+    }
+
+    pub fn from_faces_rgba32f(size: u32, faces: [Vec<[f32; 4]>; 6]) -> Option<Self> {
+        let len = (size as usize).checked_mul(size as usize);
+        if size == 0 || faces.iter().any(|face| len != Some(face.len())) {
+            return None;
+        }
+
+        Some(Self {
+            size,
+            data: faces.concat(),
+            id: next_resource_id()
+        })
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    // All six faces back to back
+    pub fn pixels(&self) -> &[[f32; 4]] {
+        &self.data
+    }
+
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BackgroundUniforms {
+    inv_view_proj: [[f32; 4]; 4],
+    top: [f32; 4],
+    bottom: [f32; 4]
+}
+
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+enum BackgroundKind {
+    Gradient,
+    Equirectangular,
+    Cubemap
+}
+
+impl BackgroundKind {
+    fn frag_shader(&self) -> ShaderType {
+        match self {
+            BackgroundKind::Gradient => ShaderType::BackgroundGradientFrag,
+            BackgroundKind::Equirectangular => ShaderType::BackgroundEquirectFrag,
+            BackgroundKind::Cubemap => ShaderType::BackgroundCubeFrag
+        }
+    }
+}
+
+pub(crate) struct BackgroundPass {
+    gradient_layout: wgpu::BindGroupLayout,
+    equirect_layout: wgpu::BindGroupLayout,
+    cube_layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    pipelines: HashMap<(BackgroundKind, TargetState), wgpu::RenderPipeline>,
+    // The uploaded image and the id it came from
+    texture: Option<(u64, Texture)>,
+    bind_group: Option<(BackgroundKind, u64, wgpu::BindGroup)>,
+    // What the last prepare asked for, None when there's nothing to draw
+    active: Option<(BackgroundKind, TargetState)>
+}
+
+impl BackgroundPass {
+    pub fn new(renderer_state: &WGPUState) -> Self {
+        let device = &renderer_state.device;
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Background Uniform Buffer"),
+            size: std::mem::size_of::<BackgroundUniforms>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false
+        });
+
+        // Panoramas wrap around in u, cubemaps don't care
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Background Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            gradient_layout: create_bind_group_layout(device, None, "background_gradient_bind_group_layout"),
+            equirect_layout: create_bind_group_layout(device, Some(wgpu::TextureViewDimension::D2), "background_equirect_bind_group_layout"),
+            cube_layout: create_bind_group_layout(device, Some(wgpu::TextureViewDimension::Cube), "background_cube_bind_group_layout"),
+            uniform_buffer,
+            sampler,
+            pipelines: HashMap::new(),
+            texture: None,
+            bind_group: None,
+            active: None
+        }
+    }
+
+    fn layout(&self, kind: BackgroundKind) -> &wgpu::BindGroupLayout {
+        match kind {
+            BackgroundKind::Gradient => &self.gradient_layout,
+            BackgroundKind::Equirectangular => &self.equirect_layout,
+            BackgroundKind::Cubemap => &self.cube_layout
+        }
+    }
+
+    // skybox is the id and cubemap view of the scene's processed environment, if it has one
+    #[allow(clippy::too_many_arguments)]
+    pub fn prepare(&mut self,
+                   renderer_state: &WGPUState,
+                   shader_store: &ShaderStore,
+                   background: &Background,
+                   skybox: Option<(u64, &wgpu::TextureView)>,
+                   camera: &Camera,
+                   aspect: f32,
+                   target: &TargetState)
+    {
+        let (kind, image_id, top, bottom) = match (background, skybox) {
+            (Background::Color(_), _) | (Background::Transparent, _) | (Background::Skybox, None) => {
+                self.active = None;
+                return;
+            },
+            (Background::Gradient { top, bottom }, _) => (BackgroundKind::Gradient, 0, *top, *bottom),
+            (Background::Equirectangular(image), _) => (BackgroundKind::Equirectangular, image.id(), glam::Vec4::ZERO, glam::Vec4::ZERO),
+            (Background::Cubemap(image), _) => (BackgroundKind::Cubemap, image.id(), glam::Vec4::ZERO, glam::Vec4::ZERO),
+            (Background::Skybox, Some((id, _))) => (BackgroundKind::Cubemap, id, glam::Vec4::ZERO, glam::Vec4::ZERO)
+        };
+
+        let uniforms = BackgroundUniforms {
+            inv_view_proj: camera.view_projection_matrix(aspect).inverse().to_cols_array_2d(),
+            top: top.into(),
+            bottom: bottom.into()
+        };
+        renderer_state.queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniforms]));
+
+        let uploaded = matches!(&self.texture, Some((id, _)) if *id == image_id);
+        if !uploaded {
+            match background {
+                Background::Equirectangular(image) => {
+                    let (width, height, pixels) = fit_to_limit(image.width(), image.height(), 1, image.pixels(), renderer_state.limits.max_texture_dimension_2d);
+                    let texture = create_background_texture(renderer_state, width, height, 1, &pixels);
+                    self.texture = Some((image_id, texture));
+                },
+                Background::Cubemap(image) => {
+                    let (size, _, pixels) = fit_to_limit(image.size(), image.size(), 6, image.pixels(), renderer_state.limits.max_texture_dimension_2d);
+                    let texture = create_background_texture(renderer_state, size, size, 6, &pixels);
+                    self.texture = Some((image_id, texture));
+                },
+                _ => {}
+            }
+        }
+
+        let up_to_date = matches!(&self.bind_group, Some((bound_kind, id, _)) if *bound_kind == kind && *id == image_id);
+        if !up_to_date {
+            let mut entries = vec![
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                }
+            ];
+            let texture_view = match (background, skybox) {
+                (Background::Skybox, Some((_, view))) => Some(view),
+                (Background::Equirectangular(_), _) | (Background::Cubemap(_), _) => self.texture.as_ref().map(|(_, texture)| &texture.view),
+                _ => None
+            };
+            if let Some(texture_view) = texture_view {
+                entries.push(wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(texture_view),
+                });
+                entries.push(wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                });
+            }
+
+            let bind_group = renderer_state.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: self.layout(kind),
+                entries: &entries,
+                label: Some("background_bind_group"),
+            });
+            self.bind_group = Some((kind, image_id, bind_group));
+        }
+
+        if !self.pipelines.contains_key(&(kind, *target)) {
+            let pipeline = create_pipeline(renderer_state, shader_store, self.layout(kind), kind, target);
+            self.pipelines.insert((kind, *target), pipeline);
+        }
+
+        self.active = Some((kind, *target));
+    }
+
+    // Called first thing in the opaque pass. Doesn't touch depth, so everything drawn after covers it.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        let key = match &self.active {
+            Some(key) => key,
+            None => return
+        };
+        let (_, _, bind_group) = self.bind_group.as_ref().expect("background drawn before prepare");
+
+        render_pass.set_pipeline(&self.pipelines[key]);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_bind_group_layout(device: &wgpu::Device, texture: Option<wgpu::TextureViewDimension>, label: &str) -> wgpu::BindGroupLayout {
+    let mut entries = vec![
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    ];
+    if let Some(view_dimension) = texture {
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        });
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::Sampler {
+                filtering: true,
+                comparison: false,
+            },
+            count: None,
+        });
+    }
+
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &entries,
+        label: Some(label),
+    })
+}
+
+fn create_pipeline(renderer_state: &WGPUState,
+                   shader_store: &ShaderStore,
+                   layout: &wgpu::BindGroupLayout,
+                   kind: BackgroundKind,
+                   target: &TargetState) -> wgpu::RenderPipeline
+{
+    let device = &renderer_state.device;
+
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Background Pipeline Layout"),
+        bind_group_layouts: &[layout],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Background Pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: shader_store.get(ShaderType::FullscreenVert),
+            entry_point: "main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader_store.get(kind.frag_shader()),
+            entry_point: "main",
+            targets: &[target.format.into()],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        // Has to match the opaque pass's depth attachment, but never tests or writes it
+        depth_stencil: target.depth_format.map(|format| wgpu::DepthStencilState {
+            format,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: target.sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+    })
+}
+
+// Images bigger than the device takes are box filtered down by a whole factor until they fit,
+// every layer the same way. 16k panoramas are common and most adapters stop at 8k.
+pub(crate) fn fit_to_limit(width: u32, height: u32, layers: u32, pixels: &[[f32; 4]], max_dimension: u32) -> (u32, u32, Cow<'_, [[f32; 4]]>) {
+    if width <= max_dimension && height <= max_dimension {
+        return (width, height, Cow::Borrowed(pixels));
+    }
+
+    let factor = (width.max(height) + max_dimension - 1) / max_dimension;
+    let (fitted_width, fitted_height) = ((width + factor - 1) / factor, (height + factor - 1) / factor);
+    log::warn!("{}x{} image is bigger than the adapter's {} limit, using it at {}x{}", width, height, max_dimension, fitted_width, fitted_height);
+
+    let (width, height, factor) = (width as usize, height as usize, factor as usize);
+    let mut fitted = Vec::with_capacity(fitted_width as usize * fitted_height as usize * layers as usize);
+    for layer in pixels.chunks_exact(width * height) {
+        for y in 0..fitted_height as usize {
+            for x in 0..fitted_width as usize {
+                // Blocks on the right and bottom edges can be cut short
+                let (rows, columns) = (y * factor..((y + 1) * factor).min(height), x * factor..((x + 1) * factor).min(width));
+                let mut sum = [0.0; 4];
+                for row in rows.clone() {
+                    for pixel in &layer[row * width + columns.start..row * width + columns.end] {
+                        for (total, channel) in sum.iter_mut().zip(pixel) {
+                            *total += channel;
+                        }
+                    }
+                }
+                let count = (rows.len() * columns.len()) as f32;
+                fitted.push(sum.map(|total| total / count));
+            }
+        }
+    }
+
+    (fitted_width, fitted_height, Cow::Owned(fitted))
+}
+
+// Rgba16Float so HDR images keep their range. layers is 1 for panoramas and 6 for cubemaps,
+// which get a cube view.
+pub(crate) fn create_background_texture(renderer_state: &WGPUState, width: u32, height: u32, layers: u32, pixels: &[[f32; 4]]) -> Texture {
+    let size = wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: layers
+    };
+
+    let texture = renderer_state.device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("background_texture"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba16Float,
+        usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST
+    });
+
+    let halves: Vec<u16> = pixels.iter().flat_map(|pixel| pixel.iter().map(|c| half::f16::from_f32(*c).to_bits())).collect();
+    renderer_state.queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        bytemuck::cast_slice(&halves),
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: NonZeroU32::new(8 * width),
+            rows_per_image: NonZeroU32::new(height),
+        },
+        size
+    );
+    let view = texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: if layers == 6 { Some(wgpu::TextureViewDimension::Cube) } else { None },
+        ..Default::default()
+    });
+
+    Texture {
+        texture,
+        view
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_that_overflow_u32_are_rejected() {
+        // 65536 * 65536 wraps to 0 in u32, which an empty buffer used to match
+        assert!(EnvironmentImage::from_rgba8(0x10000, 0x10000, &[]).is_none());
+        assert!(EnvironmentImage::from_rgba8(0x8000, 0x8000, &[]).is_none());
+        assert!(EnvironmentImage::from_rgba32f(0x10000, 0x10000, Vec::new()).is_none());
+    }
+
+    #[test]
+    fn oversized_panoramas_are_scaled_to_fit() {
+        let pixels: Vec<[f32; 4]> = (0..16).map(|i| [i as f32, 0.0, 0.0, 1.0]).collect();
+        let (width, height, fitted) = fit_to_limit(4, 4, 1, &pixels, 2);
+        assert_eq!((width, height), (2, 2));
+        assert_eq!(fitted.iter().map(|pixel| pixel[0]).collect::<Vec<_>>(), vec![2.5, 4.5, 10.5, 12.5]);
+        assert!(fitted.iter().all(|pixel| pixel[3] == 1.0));
+
+        // A 16k x 8k panorama against an 8k limit, scaled down
+        let pixels = vec![[1.0; 4]; 64 * 32];
+        let (width, height, fitted) = fit_to_limit(64, 32, 1, &pixels, 32);
+        assert_eq!((width, height), (32, 16));
+        assert!(fitted.iter().all(|pixel| *pixel == [1.0; 4]));
+    }
+
+    #[test]
+    fn panoramas_that_fit_are_left_alone() {
+        let pixels = vec![[0.5; 4]; 8];
+        let (width, height, fitted) = fit_to_limit(4, 2, 1, &pixels, 4);
+        assert_eq!((width, height), (4, 2));
+        assert!(matches!(fitted, Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn factors_that_dont_divide_keep_the_edges() {
+        // 5 wide by a factor of 2 leaves a 1 pixel block on the right
+        let pixels: Vec<[f32; 4]> = (0..5).map(|i| [i as f32; 4]).collect();
+        let (width, height, fitted) = fit_to_limit(5, 1, 1, &pixels, 3);
+        assert_eq!((width, height), (3, 1));
+        assert_eq!(fitted.iter().map(|pixel| pixel[0]).collect::<Vec<_>>(), vec![0.5, 2.5, 4.0]);
+    }
+
+    #[test]
+    fn oversized_cubemaps_are_scaled_face_by_face() {
+        // Each face is a flat color, which has to survive being averaged
+        let faces: Vec<[f32; 4]> = (0..6).flat_map(|face| vec![[face as f32; 4]; 16]).collect();
+        let (width, height, fitted) = fit_to_limit(4, 4, 6, &faces, 2);
+        assert_eq!((width, height), (2, 2));
+        assert_eq!(fitted.len(), 6 * 4);
+        for (face, pixels) in fitted.chunks_exact(4).enumerate() {
+            assert!(pixels.iter().all(|pixel| *pixel == [face as f32; 4]));
+        }
+    }
+
+    #[test]
+    fn cubemap_sizes_that_overflow_u32_are_rejected() {
+        let empty: [&[u8]; 6] = [&[]; 6];
+        assert!(CubemapImage::from_faces_rgba8(0x10000, empty).is_none());
+        assert!(CubemapImage::from_faces_rgba8(0x8000, empty).is_none());
+        assert!(CubemapImage::from_faces_rgba32f(0x10000, Default::default()).is_none());
+    }
+
+    #[test]
+    fn cubemap_faces_must_all_match() {
+        let face = [0u8; 16];
+        assert!(CubemapImage::from_faces_rgba8(2, [&face; 6]).is_some());
+        assert!(CubemapImage::from_faces_rgba8(2, [&face, &face, &face, &face, &face, &face[..12]]).is_none());
+
+        let faces: [Vec<[f32; 4]>; 6] = Default::default();
+        assert!(CubemapImage::from_faces_rgba32f(1, faces).is_none());
+        let faces = [vec![[0.0; 4]; 4], vec![[0.0; 4]; 4], vec![[0.0; 4]; 4], vec![[0.0; 4]; 4], vec![[0.0; 4]; 4], vec![[0.0; 4]; 4]];
+        assert_eq!(CubemapImage::from_faces_rgba32f(2, faces).map(|image| image.pixels().len()), Some(24));
+    }
+
+    #[test]
+    fn pixel_counts_must_match() {
+        assert!(EnvironmentImage::from_rgba8(2, 1, &[0; 8]).is_some());
+        assert!(EnvironmentImage::from_rgba8(2, 1, &[0; 4]).is_none());
+        assert!(EnvironmentImage::from_rgba32f(2, 1, vec![[0.0; 4]; 2]).is_some());
+        assert!(EnvironmentImage::from_rgba32f(2, 1, vec![[0.0; 4]; 3]).is_none());
+    }
+}
@@ -0,0 +1,155 @@
+#[derive(Debug, Copy, Clone)]
+pub struct Camera {
+    pub eye: glam::Vec3,
+    pub target: glam::Vec3,
+    pub up: glam::Vec3,
+    pub fovy: f32, // radians
+    pub znear: f32,
+    pub zfar: f32
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct CameraUniforms {
+    view_proj: [[f32; 4]; 4],
+    eye: [f32; 4]
+}
+
+impl Camera {
+    pub fn view_matrix(&self) -> glam::Mat4 {
+        glam::Mat4::look_at_rh(self.eye, self.target, self.up)
+    }
+
+    // glam's perspective_rh already maps depth to wgpu's 0..1
+    pub fn projection_matrix(&self, aspect: f32) -> glam::Mat4 {
+        glam::Mat4::perspective_rh(self.fovy, aspect, self.znear, self.zfar)
+    }
+
+    pub fn view_projection_matrix(&self, aspect: f32) -> glam::Mat4 {
+        self.projection_matrix(aspect) * self.view_matrix()
+    }
+
+    pub(crate) fn uniforms(&self, aspect: f32) -> CameraUniforms {
+        CameraUniforms {
+            view_proj: self.view_projection_matrix(aspect).to_cols_array_2d(),
+            eye: self.eye.extend(1.0).to_array()
+        }
+    }
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            eye: glam::Vec3::new(0.0, 0.0, 4.0),
+            target: glam::Vec3::ZERO,
+            up: glam::Vec3::Y,
+            fovy: std::f32::consts::FRAC_PI_4,
+            znear: 0.1,
+            zfar: 100.0
+        }
+    }
+}
+
+pub(crate) struct CameraBuffers {
+    buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup
+}
+
+impl CameraBuffers {
+    pub fn new(device: &wgpu::Device, camera_layout: &wgpu::BindGroupLayout) -> Self {
+        let buffer = device.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("Camera Buffer"),
+                size: std::mem::size_of::<CameraUniforms>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+                mapped_at_creation: false
+            }
+        );
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: camera_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }
+            ],
+            label: Some("camera_bind_group"),
+        });
+
+        Self {
+            buffer,
+            bind_group
+        }
+    }
+
+    pub fn write(&self, queue: &wgpu::Queue, camera: &Camera, aspect: f32) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[camera.uniforms(aspect)]));
+    }
+}
+
+pub(crate) fn create_camera_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }
+        ],
+        label: Some("camera_bind_group_layout"),
+    })
+}
+
+// Orders items furthest from the camera first, by where their transform puts the origin.
+// The view looks down -z so the furthest item has the smallest z.
+pub(crate) fn sort_back_to_front<T>(view: glam::Mat4, items: &mut [T], transform: impl Fn(&T) -> glam::Mat4) {
+    items.sort_by(|a, b| {
+        let a_z = view.transform_point3(transform(a).w_axis.truncate()).z;
+        let b_z = view.transform_point3(transform(b).w_axis.truncate()).z;
+        a_z.partial_cmp(&b_z).unwrap_or(std::cmp::Ordering::Equal)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted_positions(camera: &Camera, positions: &[glam::Vec3]) -> Vec<glam::Vec3> {
+        let mut transforms: Vec<_> = positions.iter().map(|p| glam::Mat4::from_translation(*p)).collect();
+        sort_back_to_front(camera.view_matrix(), &mut transforms, |t| *t);
+        transforms.iter().map(|t| t.w_axis.truncate()).collect()
+    }
+
+    #[test]
+    fn transparent_meshes_are_sorted_back_to_front() {
+        // The default camera sits at z = 4 looking at the origin
+        let camera = Camera::default();
+        let positions = [
+            glam::Vec3::new(0.0, 0.0, 1.0),
+            glam::Vec3::new(0.0, 0.0, -5.0),
+            glam::Vec3::new(2.0, 1.0, 3.0),
+            glam::Vec3::new(0.0, 0.0, 0.0)
+        ];
+        let sorted: Vec<_> = sorted_positions(&camera, &positions).iter().map(|p| p.z).collect();
+        assert_eq!(sorted, vec![-5.0, 0.0, 1.0, 3.0]);
+    }
+
+    #[test]
+    fn sorting_follows_the_camera() {
+        // Looking down -x from x = 4 makes the x axis the depth
+        let camera = Camera { eye: glam::Vec3::new(4.0, 0.0, 0.0), ..Camera::default() };
+        let positions = [
+            glam::Vec3::new(2.0, 0.0, -3.0),
+            glam::Vec3::new(-3.0, 0.0, 2.0),
+            glam::Vec3::new(0.0, 0.0, 0.0)
+        ];
+        let sorted: Vec<_> = sorted_positions(&camera, &positions).iter().map(|p| p.x).collect();
+        assert_eq!(sorted, vec![-3.0, 0.0, 2.0]);
+    }
+}
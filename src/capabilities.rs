@@ -0,0 +1,72 @@
+use crate::error::RendererError;
+use crate::wgpu_state::WGPUState;
+
+// Things the renderer can do that depend on the adapter
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RendererFeature {
+    // Any sample count above 1
+    Msaa,
+    // Line polygon mode, materials asking for it are drawn filled without this
+    Wireframe,
+    // Several indirect draws in one call. Single indirect draws always work.
+    MultiDrawIndirect,
+    TimestampQuery
+}
+
+impl RendererFeature {
+    // The device features this needs, for RendererConfig::required_features or optional_features
+    pub fn wgpu_features(&self) -> wgpu::Features {
+        match self {
+            RendererFeature::Msaa => wgpu::Features::empty(),
+            RendererFeature::Wireframe => wgpu::Features::NON_FILL_POLYGON_MODE,
+            RendererFeature::MultiDrawIndirect => wgpu::Features::MULTI_DRAW_INDIRECT,
+            RendererFeature::TimestampQuery => wgpu::Features::TIMESTAMP_QUERY
+        }
+    }
+}
+
+// The adapter the renderer ended up on and what was enabled on its device
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    pub adapter: wgpu::AdapterInfo,
+    // Only what was actually enabled, the adapter may support more
+    pub features: wgpu::Features,
+    pub limits: wgpu::Limits,
+    // Everything set_sample_count accepts, smallest first
+    pub sample_counts: Vec<u32>
+}
+
+impl Capabilities {
+    pub(crate) fn new(renderer_state: &WGPUState) -> Self {
+        Self {
+            adapter: renderer_state.adapter_info.clone(),
+            features: renderer_state.features,
+            limits: renderer_state.limits.clone(),
+            sample_counts: [1, 4].iter()
+                .copied()
+                .filter(|count| renderer_state.supports_sample_count(*count))
+                .collect()
+        }
+    }
+
+    pub fn supports(&self, feature: RendererFeature) -> bool {
+        match feature {
+            RendererFeature::Msaa => self.max_sample_count() > 1,
+            _ => self.features.contains(feature.wgpu_features())
+        }
+    }
+
+    // Err(UnsupportedFeature) for things the app can't do without
+    pub fn require(&self, feature: RendererFeature) -> Result<(), RendererError> {
+        if self.supports(feature) {
+            Ok(())
+        }
+        else {
+            Err(RendererError::UnsupportedFeature(feature))
+        }
+    }
+
+    pub fn max_sample_count(&self) -> u32 {
+        self.sample_counts.iter().copied().max().unwrap_or(1)
+    }
+}
@@ -0,0 +1,139 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::num::NonZeroU32;
+use std::path::Path;
+use futures::executor::block_on;
+use crate::WGPUState;
+use crate::error::RendererError;
+
+// Tightly packed 8 bit RGBA, top row first
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>
+}
+
+impl Image {
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * self.width + x) * 4) as usize;
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2], self.pixels[i + 3]]
+    }
+
+    // 8 bit PNGs of any color type, expanded to RGBA
+    pub fn load_png<P: AsRef<Path>>(path: P) -> Result<Image, RendererError> {
+        Image::from_png(&std::fs::read(path)?)
+    }
+
+    // PNG file contents, see load_png
+    pub fn from_png(bytes: &[u8]) -> Result<Image, RendererError> {
+        let invalid = |err: png::DecodingError| RendererError::InvalidImage(format!("png: {}", err));
+
+        let mut decoder = png::Decoder::new(bytes);
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let (info, mut reader) = decoder.read_info().map_err(invalid)?;
+        let mut data = vec![0; info.buffer_size()];
+        reader.next_frame(&mut data).map_err(invalid)?;
+
+        if info.bit_depth != png::BitDepth::Eight {
+            return Err(RendererError::InvalidImage(format!("png: {:?} bit images aren't supported", info.bit_depth)));
+        }
+        let pixels = match info.color_type {
+            png::ColorType::RGBA => data,
+            png::ColorType::RGB => data.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+            png::ColorType::GrayscaleAlpha => data.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+            png::ColorType::Grayscale => data.iter().flat_map(|v| [*v, *v, *v, 255]).collect(),
+            png::ColorType::Indexed => return Err(RendererError::InvalidImage("png: palette wasn't expanded".to_string()))
+        };
+
+        Ok(Image {
+            width: info.width,
+            height: info.height,
+            pixels
+        })
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> Result<(), RendererError> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.width, self.height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(std::io::Error::from)?;
+        writer.write_image_data(&self.pixels).map_err(std::io::Error::from)?;
+        Ok(())
+    }
+}
+
+// Copies the texture into a mapped buffer and repacks it as RGBA. Buffer rows have to be
+// padded out to COPY_BYTES_PER_ROW_ALIGNMENT, the padding is dropped here.
+pub(crate) fn read_back(renderer_state: &WGPUState, texture: &wgpu::Texture) -> Result<Image, RendererError> {
+    let format = renderer_state.swapchain_format;
+    let bgra = match format {
+        wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+        wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+        _ => return Err(RendererError::UnsupportedCaptureFormat(format))
+    };
+
+    let width = renderer_state.sc_desc.width.max(1);
+    let height = renderer_state.sc_desc.height.max(1);
+    let unpadded_bytes_per_row = width * 4;
+    let padded_bytes_per_row = (unpadded_bytes_per_row + wgpu::COPY_BYTES_PER_ROW_ALIGNMENT - 1) / wgpu::COPY_BYTES_PER_ROW_ALIGNMENT * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+    let buffer = renderer_state.device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Capture Buffer"),
+        size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
+        mapped_at_creation: false
+    });
+
+    let mut encoder = renderer_state.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Capture Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(padded_bytes_per_row),
+                rows_per_image: NonZeroU32::new(height),
+            },
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1
+        },
+    );
+    renderer_state.queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    let mapping = slice.map_async(wgpu::MapMode::Read);
+    renderer_state.device.poll(wgpu::Maintain::Wait);
+    block_on(mapping).map_err(|_| RendererError::BufferMap)?;
+
+    let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+    {
+        let data = slice.get_mapped_range();
+        for row in data.chunks_exact(padded_bytes_per_row as usize) {
+            pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+        }
+    }
+    buffer.unmap();
+
+    if bgra {
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+
+    Ok(Image {
+        width,
+        height,
+        pixels
+    })
+}
@@ -0,0 +1,37 @@
+// How the renderer picks and sets up its GPU
+#[derive(Debug, Clone)]
+pub struct RendererConfig {
+    pub backends: wgpu::BackendBit,
+    pub power_preference: wgpu::PowerPreference,
+    // Only consider software adapters, fails with NoAdapter if there aren't any
+    pub force_fallback_adapter: bool,
+    // Creating the renderer fails if the adapter is missing any of these
+    pub required_features: wgpu::Features,
+    // Turned on when the adapter has them, left off otherwise
+    pub optional_features: wgpu::Features,
+    pub limits: wgpu::Limits,
+    // Fifo is vsync on, Immediate is off and Mailbox is off without tearing.
+    // Immediate and Mailbox fall back to Fifo where they aren't available.
+    pub present_mode: wgpu::PresentMode,
+    // None uses whatever the surface prefers
+    pub swapchain_format: Option<wgpu::TextureFormat>
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            // Vulkan + Metal + DX12 + Browser WebGPU
+            backends: wgpu::BackendBit::PRIMARY,
+            power_preference: wgpu::PowerPreference::default(),
+            force_fallback_adapter: false,
+            required_features: wgpu::Features::empty(),
+            // Everything a RendererFeature needs, so Capabilities reports what the adapter can do
+            optional_features: wgpu::Features::NON_FILL_POLYGON_MODE
+                | wgpu::Features::MULTI_DRAW_INDIRECT
+                | wgpu::Features::TIMESTAMP_QUERY,
+            limits: wgpu::Limits::default(),
+            present_mode: wgpu::PresentMode::Fifo,
+            swapchain_format: None
+        }
+    }
+}
@@ -0,0 +1,541 @@
+use std::path::Path;
+use wgpu::util::DeviceExt;
+use crate::WGPUState;
+use crate::background::{self, EnvironmentImage};
+use crate::error::RendererError;
+use crate::shaders::{ShaderStore, ShaderType};
+use crate::texture::Texture;
+
+// Image based lighting for a scene. The panorama is turned into a cubemap and filtered on the
+// GPU the first time it's drawn, lit materials then take their ambient light from it.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    image: EnvironmentImage,
+    pub intensity: f32
+}
+
+impl Environment {
+    pub fn new(image: EnvironmentImage) -> Self {
+        Self {
+            image,
+            intensity: 1.0
+        }
+    }
+
+    // An equirectangular Radiance .hdr file
+    pub fn load_hdr<P: AsRef<Path>>(path: P) -> Result<Self, RendererError> {
+        EnvironmentImage::load_hdr(path).map(Environment::new)
+    }
+
+    pub fn image(&self) -> &EnvironmentImage {
+        &self.image
+    }
+
+    pub(crate) fn image_mut(&mut self) -> &mut EnvironmentImage {
+        &mut self.image
+    }
+}
+
+const FILTER_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const BRDF_LUT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Float;
+const BRDF_LUT_SIZE: u32 = 256;
+const CUBEMAP_SIZE: u32 = 512;
+const IRRADIANCE_SIZE: u32 = 32;
+const PREFILTERED_SIZE: u32 = 128;
+// Roughness 0, 0.25, 0.5, 0.75 and 1, down to 8x8
+const PREFILTERED_MIPS: u32 = 5;
+// What lit materials get without an environment, a dim flat gray from every direction
+const DEFAULT_AMBIENT: [f32; 4] = [0.4, 0.4, 0.4, 1.0];
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct EnvironmentUniforms {
+    intensity: f32,
+    max_lod: f32,
+    _padding: [f32; 2]
+}
+
+// Matches Params in the env_*.frag shaders
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FilterUniforms {
+    face: u32,
+    roughness: f32,
+    source_size: f32,
+    _padding: f32
+}
+
+struct EnvironmentMaps {
+    // The image the maps were made from
+    id: u64,
+    cubemap: Texture,
+    bind_group: wgpu::BindGroup,
+    // Only kept alive for the bind group
+    _irradiance: Texture,
+    _prefiltered: Texture
+}
+
+struct FilterPipelines {
+    equirect_layout: wgpu::BindGroupLayout,
+    cube_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    equirect_to_cube: wgpu::RenderPipeline,
+    downsample: wgpu::RenderPipeline,
+    irradiance: wgpu::RenderPipeline,
+    prefilter: wgpu::RenderPipeline
+}
+
+// Owns set 3 of the lit pipelines: the environment uniforms, irradiance and prefiltered
+// cubemaps and the BRDF lookup table.
+pub(crate) struct EnvironmentStore {
+    bind_group_layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    brdf_lut: Texture,
+    filters: FilterPipelines,
+    default_bind_group: wgpu::BindGroup,
+    _default_cube: Texture,
+    maps: Option<EnvironmentMaps>
+}
+
+impl EnvironmentStore {
+    pub fn new(renderer_state: &WGPUState, shader_store: &ShaderStore) -> Self {
+        let device = &renderer_state.device;
+
+        let bind_group_layout = create_bind_group_layout(device);
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Environment Uniform Buffer"),
+            size: std::mem::size_of::<EnvironmentUniforms>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Environment Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        // The lookup table doesn't depend on the environment, so it's made once up front
+        let brdf_lut = create_brdf_lut(renderer_state, shader_store);
+        let filters = FilterPipelines::new(renderer_state, shader_store);
+
+        let default_cube = background::create_background_texture(renderer_state, 1, 1, 6, &[DEFAULT_AMBIENT; 6]);
+        let default_bind_group = create_bind_group(device, &bind_group_layout, &uniform_buffer, &default_cube.view, &default_cube.view, &brdf_lut.view, &sampler);
+
+        Self {
+            bind_group_layout,
+            uniform_buffer,
+            sampler,
+            brdf_lut,
+            filters,
+            default_bind_group,
+            _default_cube: default_cube,
+            maps: None
+        }
+    }
+
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    // Filters a new environment's maps before the frame is recorded. They're dropped once
+    // the scene has no environment.
+    pub fn prepare(&mut self, renderer_state: &WGPUState, environment: Option<&Environment>) {
+        let environment = match environment {
+            Some(environment) => environment,
+            None => {
+                self.maps = None;
+                self.write_uniforms(renderer_state, 1.0, 0.0);
+                return;
+            }
+        };
+
+        let image = environment.image();
+        let up_to_date = matches!(&self.maps, Some(maps) if maps.id == image.id());
+        if !up_to_date {
+            self.maps = Some(self.create_maps(renderer_state, image));
+        }
+        self.write_uniforms(renderer_state, environment.intensity, (PREFILTERED_MIPS - 1) as f32);
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        match &self.maps {
+            Some(maps) => &maps.bind_group,
+            None => &self.default_bind_group
+        }
+    }
+
+    // The unfiltered cubemap and the id of the image it came from, for the skybox background
+    pub fn skybox(&self) -> Option<(u64, &wgpu::TextureView)> {
+        self.maps.as_ref().map(|maps| (maps.id, &maps.cubemap.view))
+    }
+
+    fn write_uniforms(&self, renderer_state: &WGPUState, intensity: f32, max_lod: f32) {
+        let uniforms = EnvironmentUniforms {
+            intensity,
+            max_lod,
+            _padding: [0.0; 2]
+        };
+        renderer_state.queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniforms]));
+    }
+
+    fn create_maps(&self, renderer_state: &WGPUState, image: &EnvironmentImage) -> EnvironmentMaps {
+        let device = &renderer_state.device;
+        let filters = &self.filters;
+
+        let cubemap_mips = 32 - CUBEMAP_SIZE.leading_zeros();
+        let cubemap = create_cube_texture(device, CUBEMAP_SIZE, cubemap_mips, "Environment Cubemap");
+        let irradiance = create_cube_texture(device, IRRADIANCE_SIZE, 1, "Environment Irradiance");
+        let prefiltered = create_cube_texture(device, PREFILTERED_SIZE, PREFILTERED_MIPS, "Environment Prefiltered");
+
+        let (width, height, pixels) = background::fit_to_limit(image.width(), image.height(), 1, image.pixels(), renderer_state.limits.max_texture_dimension_2d);
+        let source = background::create_background_texture(renderer_state, width, height, 1, &pixels);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Environment Filter Encoder"),
+        });
+
+        // Panorama into the top mip, then a box filtered chain below it so the filters
+        // can read bright spots from a mip where they're already averaged out
+        filters.draw_faces(device, &mut encoder, &filters.equirect_to_cube, &filters.equirect_layout, &source.view, &cubemap, 0, 0.0, width as f32);
+        for mip in 1..cubemap_mips {
+            let previous_mip = cube_view(&cubemap.texture, mip - 1, 1);
+            filters.draw_faces(device, &mut encoder, &filters.downsample, &filters.cube_layout, &previous_mip, &cubemap, mip, 0.0, (CUBEMAP_SIZE >> (mip - 1)) as f32);
+        }
+
+        let full_chain = cube_view(&cubemap.texture, 0, cubemap_mips);
+        filters.draw_faces(device, &mut encoder, &filters.irradiance, &filters.cube_layout, &full_chain, &irradiance, 0, 0.0, CUBEMAP_SIZE as f32);
+        for mip in 0..PREFILTERED_MIPS {
+            let roughness = mip as f32 / (PREFILTERED_MIPS - 1) as f32;
+            filters.draw_faces(device, &mut encoder, &filters.prefilter, &filters.cube_layout, &full_chain, &prefiltered, mip, roughness, CUBEMAP_SIZE as f32);
+        }
+
+        renderer_state.queue.submit(std::iter::once(encoder.finish()));
+
+        let bind_group = create_bind_group(device, &self.bind_group_layout, &self.uniform_buffer, &irradiance.view, &prefiltered.view, &self.brdf_lut.view, &self.sampler);
+
+        EnvironmentMaps {
+            id: image.id(),
+            cubemap,
+            bind_group,
+            _irradiance: irradiance,
+            _prefiltered: prefiltered
+        }
+    }
+}
+
+impl FilterPipelines {
+    fn new(renderer_state: &WGPUState, shader_store: &ShaderStore) -> Self {
+        let device = &renderer_state.device;
+
+        let equirect_layout = create_filter_layout(device, wgpu::TextureViewDimension::D2, "environment_equirect_bind_group_layout");
+        let cube_layout = create_filter_layout(device, wgpu::TextureViewDimension::Cube, "environment_cube_bind_group_layout");
+
+        // Panoramas wrap around in u
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Environment Filter Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            equirect_to_cube: create_filter_pipeline(renderer_state, shader_store, Some(&equirect_layout), ShaderType::EnvEquirectToCubeFrag, FILTER_FORMAT),
+            downsample: create_filter_pipeline(renderer_state, shader_store, Some(&cube_layout), ShaderType::EnvDownsampleFrag, FILTER_FORMAT),
+            irradiance: create_filter_pipeline(renderer_state, shader_store, Some(&cube_layout), ShaderType::EnvIrradianceFrag, FILTER_FORMAT),
+            prefilter: create_filter_pipeline(renderer_state, shader_store, Some(&cube_layout), ShaderType::EnvPrefilterFrag, FILTER_FORMAT),
+            equirect_layout,
+            cube_layout,
+            sampler
+        }
+    }
+
+    // Draws all six faces of one mip of target. Every draw gets its own small uniform
+    // buffer, this only happens when the environment changes.
+    #[allow(clippy::too_many_arguments)]
+    fn draw_faces(&self,
+                  device: &wgpu::Device,
+                  encoder: &mut wgpu::CommandEncoder,
+                  pipeline: &wgpu::RenderPipeline,
+                  layout: &wgpu::BindGroupLayout,
+                  source: &wgpu::TextureView,
+                  target: &Texture,
+                  mip: u32,
+                  roughness: f32,
+                  source_size: f32)
+    {
+        for face in 0..6 {
+            let uniforms = FilterUniforms {
+                face,
+                roughness,
+                source_size,
+                _padding: 0.0
+            };
+            let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Environment Filter Uniform Buffer"),
+                contents: bytemuck::cast_slice(&[uniforms]),
+                usage: wgpu::BufferUsage::UNIFORM,
+            });
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(source),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: uniform_buffer.as_entire_binding(),
+                    }
+                ],
+                label: Some("environment_filter_bind_group"),
+            });
+
+            let face_view = target.texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_mip_level: mip,
+                mip_level_count: std::num::NonZeroU32::new(1),
+                base_array_layer: face,
+                array_layer_count: std::num::NonZeroU32::new(1),
+                ..Default::default()
+            });
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Environment Filter Pass"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: &face_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    let texture_entry = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStage::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            view_dimension,
+            multisampled: false,
+        },
+        count: None,
+    };
+
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            texture_entry(1, wgpu::TextureViewDimension::Cube),
+            texture_entry(2, wgpu::TextureViewDimension::Cube),
+            texture_entry(3, wgpu::TextureViewDimension::D2),
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::Sampler {
+                    filtering: true,
+                    comparison: false,
+                },
+                count: None,
+            }
+        ],
+        label: Some("environment_bind_group_layout"),
+    })
+}
+
+fn create_bind_group(device: &wgpu::Device,
+                     layout: &wgpu::BindGroupLayout,
+                     uniform_buffer: &wgpu::Buffer,
+                     irradiance: &wgpu::TextureView,
+                     prefiltered: &wgpu::TextureView,
+                     brdf_lut: &wgpu::TextureView,
+                     sampler: &wgpu::Sampler) -> wgpu::BindGroup
+{
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(irradiance),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(prefiltered),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(brdf_lut),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            }
+        ],
+        label: Some("environment_bind_group"),
+    })
+}
+
+fn create_filter_layout(device: &wgpu::Device, view_dimension: wgpu::TextureViewDimension, label: &str) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::Sampler {
+                    filtering: true,
+                    comparison: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }
+        ],
+        label: Some(label),
+    })
+}
+
+// Fullscreen triangle pipelines for the filters and the BRDF table
+fn create_filter_pipeline(renderer_state: &WGPUState,
+                          shader_store: &ShaderStore,
+                          layout: Option<&wgpu::BindGroupLayout>,
+                          frag_shader: ShaderType,
+                          format: wgpu::TextureFormat) -> wgpu::RenderPipeline
+{
+    let device = &renderer_state.device;
+
+    let layouts: Vec<&wgpu::BindGroupLayout> = layout.into_iter().collect();
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Environment Filter Pipeline Layout"),
+        bind_group_layouts: &layouts,
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Environment Filter Pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: shader_store.get(ShaderType::FullscreenVert),
+            entry_point: "main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader_store.get(frag_shader),
+            entry_point: "main",
+            targets: &[format.into()],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+    })
+}
+
+fn create_cube_texture(device: &wgpu::Device, size: u32, mip_level_count: u32, label: &str) -> Texture {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 6
+        },
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FILTER_FORMAT,
+        usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED
+    });
+    let view = cube_view(&texture, 0, mip_level_count);
+
+    Texture {
+        texture,
+        view
+    }
+}
+
+fn cube_view(texture: &wgpu::Texture, base_mip_level: u32, mip_level_count: u32) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::Cube),
+        base_mip_level,
+        mip_level_count: std::num::NonZeroU32::new(mip_level_count),
+        ..Default::default()
+    })
+}
+
+// Scale and bias to F0 against n.v and roughness, for the split sum specular
+fn create_brdf_lut(renderer_state: &WGPUState, shader_store: &ShaderStore) -> Texture {
+    let device = &renderer_state.device;
+    let lut = Texture::create_sized_render_target(device, BRDF_LUT_SIZE, BRDF_LUT_SIZE, BRDF_LUT_FORMAT, "BRDF Lookup Table");
+    let pipeline = create_filter_pipeline(renderer_state, shader_store, None, ShaderType::EnvBrdfFrag, BRDF_LUT_FORMAT);
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("BRDF Lookup Table Encoder"),
+    });
+    {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("BRDF Lookup Table Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: &lut.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&pipeline);
+        render_pass.draw(0..3, 0..1);
+    }
+    renderer_state.queue.submit(std::iter::once(encoder.finish()));
+
+    lut
+}
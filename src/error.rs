@@ -0,0 +1,101 @@
+use std::fmt;
+use crate::capabilities::RendererFeature;
+
+#[derive(Debug)]
+pub enum RendererError {
+    SwapChain(wgpu::SwapChainError),
+    // The handle's slot has been freed (and possibly reused) since it was handed out
+    StaleHandle,
+    // The handle was created by a different renderer or scene
+    ForeignHandle,
+    // Something was drawn with a pipeline key that wasn't prepared before the pass started
+    MissingPipeline,
+    UnsupportedSampleCount(u32),
+    // 2 and 8, which the adapter may well handle but wgpu can't be asked about
+    UncheckedSampleCount(u32),
+    UnsupportedFeature(RendererFeature),
+    // Frames can only be captured from 8 bit RGBA and BGRA swap chains
+    UnsupportedCaptureFormat(wgpu::TextureFormat),
+    // Reading a buffer back from the GPU failed
+    BufferMap,
+    // Passes in the render graph read each other's outputs in a loop
+    RenderGraphCycle,
+    Io(std::io::Error),
+    // No adapter matched the config's backends and could present to the window
+    NoAdapter,
+    // The adapter has no preferred format for the window's surface, set one in the config
+    NoSwapChainFormat,
+    // The adapter is missing these required features
+    MissingFeatures(wgpu::Features),
+    // The device couldn't be created, usually because the requested limits are too high
+    RequestDevice(wgpu::RequestDeviceError),
+    // An image file that couldn't be decoded, with the reason
+    InvalidImage(String),
+    Gltf(gltf::Error),
+    // A custom shader that isn't valid SPIR-V or can't be used, with the reason
+    InvalidShader(String),
+    // A model file that parsed but can't be used, with the reason
+    InvalidAsset(String)
+}
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RendererError::SwapChain(err) => write!(f, "swap chain error: {}", err),
+            RendererError::StaleHandle => write!(f, "handle refers to a resource that has been freed"),
+            RendererError::ForeignHandle => write!(f, "handle belongs to a different renderer or scene"),
+            RendererError::MissingPipeline => write!(f, "no pipeline was prepared for this draw"),
+            RendererError::UnsupportedSampleCount(count) => write!(f, "sample count {} isn't supported by this adapter", count),
+            RendererError::UncheckedSampleCount(count) => write!(f, "sample count {} can't be checked against the adapter, only 1 and 4 are allowed", count),
+            RendererError::UnsupportedFeature(feature) => write!(f, "{:?} isn't supported by this adapter", feature),
+            RendererError::UnsupportedCaptureFormat(format) => write!(f, "can't capture frames in {:?}", format),
+            RendererError::BufferMap => write!(f, "couldn't map a buffer for reading"),
+            RendererError::RenderGraphCycle => write!(f, "render graph passes depend on each other in a cycle"),
+            RendererError::Io(err) => write!(f, "io error: {}", err),
+            RendererError::NoAdapter => write!(f, "no suitable graphics adapter was found"),
+            RendererError::NoSwapChainFormat => write!(f, "the adapter has no preferred swap chain format for this surface, set RendererConfig::swapchain_format"),
+            RendererError::MissingFeatures(features) => write!(f, "the adapter doesn't support the required features {:?}", features),
+            RendererError::RequestDevice(err) => write!(f, "couldn't create a device: {}", err),
+            RendererError::InvalidImage(reason) => write!(f, "invalid image: {}", reason),
+            RendererError::Gltf(err) => write!(f, "gltf error: {}", err),
+            RendererError::InvalidShader(reason) => write!(f, "invalid shader: {}", reason),
+            RendererError::InvalidAsset(reason) => write!(f, "invalid asset: {}", reason)
+        }
+    }
+}
+
+impl std::error::Error for RendererError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RendererError::SwapChain(err) => Some(err),
+            RendererError::Io(err) => Some(err),
+            RendererError::RequestDevice(err) => Some(err),
+            RendererError::Gltf(err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<wgpu::SwapChainError> for RendererError {
+    fn from(err: wgpu::SwapChainError) -> Self {
+        RendererError::SwapChain(err)
+    }
+}
+
+impl From<wgpu::RequestDeviceError> for RendererError {
+    fn from(err: wgpu::RequestDeviceError) -> Self {
+        RendererError::RequestDevice(err)
+    }
+}
+
+impl From<gltf::Error> for RendererError {
+    fn from(err: gltf::Error) -> Self {
+        RendererError::Gltf(err)
+    }
+}
+
+impl From<std::io::Error> for RendererError {
+    fn from(err: std::io::Error) -> Self {
+        RendererError::Io(err)
+    }
+}
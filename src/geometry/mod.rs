@@ -0,0 +1,354 @@
+use std::{mem::size_of};
+use std::marker::PhantomData;
+use crate::handles::{Handle, Pool};
+use crate::RendererError;
+
+pub mod primitives;
+mod processing;
+
+// In bytes. The buffers double whenever something doesn't fit.
+const STARTING_VERTICES: usize = 1 << 16;
+const STARTING_INDICES: usize = 1 << 16;
+
+#[derive(Debug, Copy, Clone)]
+pub struct BufferRange<T: bytemuck::Pod> {
+    pub start: wgpu::BufferAddress,
+    pub size: usize,
+    pub buffer_item_size: usize, // need a better name or is it even needed?
+    phantom: PhantomData<T>
+}
+
+pub struct Buffer<T: bytemuck::Pod> {
+    wgpu_buffer: wgpu::Buffer,
+    // Kept to make the bigger buffer when it grows
+    label: &'static str,
+    usage: wgpu::BufferUsage,
+    // In bytes
+    capacity: u64,
+    buffer_offset: u64,
+    idx_offset: u64,
+    // Freed (start, length) runs in items, sorted by start and never touching each other
+    free_ranges: Vec<(u64, u64)>,
+    phantom: PhantomData<T>
+}
+
+impl<T: bytemuck::Pod> Buffer<T> {
+    pub fn new(device: &wgpu::Device, label: &'static str, usage: wgpu::BufferUsage, capacity: u64) -> Self {
+        // Copied out of when it grows
+        let usage = usage | wgpu::BufferUsage::COPY_SRC | wgpu::BufferUsage::COPY_DST;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: capacity,
+            usage,
+            mapped_at_creation: false
+        });
+        Self {
+            wgpu_buffer: buffer,
+            label,
+            usage,
+            capacity,
+            buffer_offset: 0,
+            idx_offset: 0,
+            free_ranges: Vec::new(),
+            phantom: PhantomData
+        }
+    }
+
+    // Goes into the first freed run it fits in, otherwise onto the end
+    pub fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, data: &Vec<T>) -> BufferRange<T> {
+        let length = data.len() as u64;
+        let start = match self.free_ranges.iter().position(|&(_, free_length)| free_length >= length) {
+            Some(index) => {
+                let (start, free_length) = self.free_ranges[index];
+                if free_length == length {
+                    self.free_ranges.remove(index);
+                }
+                else {
+                    self.free_ranges[index] = (start + length, free_length - length);
+                }
+                start
+            },
+            None => {
+                let start = self.idx_offset;
+                let data_size = (data.len() * size_of::<T>()) as wgpu::BufferAddress;
+                self.buffer_offset = self.buffer_offset + data_size;
+                self.idx_offset = self.idx_offset + length;
+                start
+            }
+        };
+
+        let buffer_item_size = size_of::<T>();
+        let range = BufferRange {
+            start,
+            size: data.len(),
+            buffer_item_size,
+            phantom: PhantomData
+        };
+        self.reserve(device, queue, start + length);
+        self.write_at(queue, &range, data);
+        range
+    }
+
+    // Makes room for items items from the start, moving everything written so far into a
+    // bigger buffer if there isn't. Handles keep working, they only hold item offsets.
+    pub fn reserve(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, items: u64) {
+        let needed = items * size_of::<T>() as u64;
+        if needed <= self.capacity {
+            return;
+        }
+
+        // Rounded up to 4 bytes, copies have to be
+        let capacity = (needed.max(self.capacity * 2) + 3) & !3;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(self.label),
+            size: capacity,
+            usage: self.usage,
+            mapped_at_creation: false
+        });
+        // Writes already queued for the old buffer land before this copy runs
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("geometry_buffer_grow") });
+        encoder.copy_buffer_to_buffer(&self.wgpu_buffer, 0, &buffer, 0, self.capacity);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        self.wgpu_buffer = buffer;
+        self.capacity = capacity;
+    }
+
+    // Into a range handed out by another buffer of the same item size, for data kept in step with it
+    pub fn write_at(&self, queue: &wgpu::Queue, range: &BufferRange<T>, data: &[T]) {
+        queue.write_buffer(
+            &self.wgpu_buffer,
+            range.start * size_of::<T>() as wgpu::BufferAddress,
+            bytemuck::cast_slice(data),
+        );
+    }
+
+    // The range can be handed out again by a later write
+    pub fn free(&mut self, range: &BufferRange<T>) {
+        let (start, length) = (range.start, range.size as u64);
+        if length == 0 {
+            return;
+        }
+
+        let index = self.free_ranges.partition_point(|&(free_start, _)| free_start < start);
+        self.free_ranges.insert(index, (start, length));
+        // Merge with the neighbours it touches
+        if index + 1 < self.free_ranges.len() && start + length == self.free_ranges[index + 1].0 {
+            self.free_ranges[index].1 += self.free_ranges[index + 1].1;
+            self.free_ranges.remove(index + 1);
+        }
+        if index > 0 && self.free_ranges[index - 1].0 + self.free_ranges[index - 1].1 == start {
+            self.free_ranges[index - 1].1 += self.free_ranges[index].1;
+            self.free_ranges.remove(index);
+        }
+        // A free run at the end just moves the end back
+        if let Some(&(last_start, last_length)) = self.free_ranges.last() {
+            if last_start + last_length == self.idx_offset {
+                self.free_ranges.pop();
+                self.idx_offset = last_start;
+                self.buffer_offset = last_start * size_of::<T>() as u64;
+            }
+        }
+    }
+}
+
+// How indices are put together into primitives
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq, Default)]
+pub enum Topology {
+    #[default]
+    Triangles,
+    // Every index is its own point, for point clouds
+    Points
+}
+
+impl Topology {
+    pub(crate) fn primitive_topology(&self) -> wgpu::PrimitiveTopology {
+        match self {
+            Topology::Triangles => wgpu::PrimitiveTopology::TriangleList,
+            Topology::Points => wgpu::PrimitiveTopology::PointList
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Geometry {
+    pub vertex_positions: Vec<glam::Vec3>,
+    // One per position. Left empty they're worked out from the triangles when loaded.
+    pub vertex_normals: Vec<glam::Vec3>,
+    // One per position with (0, 0) at the top left, or empty. Empty ones upload as (0, 0).
+    pub vertex_uvs: Vec<glam::Vec2>,
+    // Linear RGBA, one per position or empty. CPU side only for now, like the uvs.
+    pub vertex_colors: Vec<glam::Vec4>,
+    // xyz along +u, w is the handedness of the bitangent, cross(normal, tangent) * w. One per
+    // position or empty, see compute_tangents.
+    pub vertex_tangents: Vec<glam::Vec4>,
+    pub indices: Vec<u32>,
+    pub topology: Topology
+}
+
+impl Geometry {
+    // Smooth normals, each face weighted by its area. Points have no faces to go by, they all
+    // face +Y like vertices no triangle touches.
+    pub fn compute_normals(&mut self) {
+        if self.topology == Topology::Points {
+            self.vertex_normals = vec![glam::Vec3::Y; self.vertex_positions.len()];
+            return;
+        }
+
+        let mut normals = vec![glam::Vec3::ZERO; self.vertex_positions.len()];
+        for triangle in self.indices.chunks_exact(3) {
+            let (a, b, c) = (triangle[0] as usize, triangle[1] as usize, triangle[2] as usize);
+            if a >= normals.len() || b >= normals.len() || c >= normals.len() {
+                continue;
+            }
+            let face_normal = (self.vertex_positions[b] - self.vertex_positions[a])
+                .cross(self.vertex_positions[c] - self.vertex_positions[a]);
+            normals[a] += face_normal;
+            normals[b] += face_normal;
+            normals[c] += face_normal;
+        }
+
+        self.vertex_normals = normals.into_iter()
+            .map(|normal| if normal.length_squared() > 0.0 { normal.normalize() } else { glam::Vec3::Y })
+            .collect();
+    }
+
+    // Centered on the bounding box, not the tightest sphere but close enough for culling and LODs
+    pub fn bounding_sphere(&self) -> (glam::Vec3, f32) {
+        if self.vertex_positions.is_empty() {
+            return (glam::Vec3::ZERO, 0.0);
+        }
+
+        let (min, max) = self.vertex_positions.iter().fold(
+            (glam::Vec3::splat(f32::MAX), glam::Vec3::splat(f32::MIN)),
+            |(min, max), position| (min.min(*position), max.max(*position))
+        );
+        let center = (min + max) / 2.0;
+        let radius = self.vertex_positions.iter().map(|position| position.distance(center)).fold(0.0, f32::max);
+        (center, radius)
+    }
+}
+
+pub struct GeometryEntry {
+    pub geometry: Geometry,
+    pub vertex_position_range: BufferRange<glam::Vec3>,
+    pub indices_range: BufferRange<u32>,
+    // The ranges belong to another entry, see GeometryStore::share
+    shared: bool,
+    // Center and radius in the geometry's own space, for picking LODs
+    pub bounding_sphere: (glam::Vec3, f32)
+}
+
+pub type GeometryHandle = Handle<GeometryEntry>;
+
+// Normals and uvs are written at the positions' range, so a position range's start is also
+// where its normals and uvs begin
+pub struct GeometryStore {
+    pub vertex_positions: Buffer<glam::Vec3>,
+    pub vertex_normals: Buffer<glam::Vec3>,
+    pub vertex_uvs: Buffer<glam::Vec2>,
+    pub indices: Buffer<u32>,
+    pub(crate) geometries: Pool<GeometryEntry>
+}
+
+impl GeometryStore {
+    pub fn new(device: &wgpu::Device, owner: u32) -> Self {
+        Self {
+            vertex_positions: Buffer::new(device, "Vertex Positions", wgpu::BufferUsage::VERTEX, STARTING_VERTICES as u64),
+            vertex_normals: Buffer::new(device, "Vertex Normals", wgpu::BufferUsage::VERTEX, STARTING_VERTICES as u64),
+            vertex_uvs: Buffer::new(device, "Vertex UVs", wgpu::BufferUsage::VERTEX, STARTING_VERTICES as u64),
+            indices: Buffer::new(device, "Indices", wgpu::BufferUsage::INDEX, STARTING_INDICES as u64),
+            geometries: Pool::new(owner)
+        }
+    }
+
+    pub fn load_mesh(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, geometry: Geometry) -> GeometryHandle {
+        let entry = self.upload(device, queue, geometry);
+        self.geometries.insert(entry)
+    }
+
+    // Swaps new geometry in behind a handle that's already out there
+    pub fn replace(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, handle: GeometryHandle, geometry: Geometry) -> Result<(), RendererError> {
+        // Checked first so nothing's uploaded for a stale handle
+        self.geometries.get(handle)?;
+        let entry = self.upload(device, queue, geometry);
+        let old = std::mem::replace(self.geometries.get_mut(handle)?, entry);
+        self.free(&old);
+        Ok(())
+    }
+
+    // A new handle drawing the same buffer ranges, which can be replaced on its own later
+    pub fn share(&mut self, handle: GeometryHandle) -> Result<GeometryHandle, RendererError> {
+        let entry = self.geometries.get(handle)?;
+        let shared = GeometryEntry {
+            geometry: entry.geometry.clone(),
+            shared: true,
+            ..*entry
+        };
+        Ok(self.geometries.insert(shared))
+    }
+
+    fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, mut geometry: Geometry) -> GeometryEntry {
+        if geometry.vertex_normals.len() != geometry.vertex_positions.len() {
+            geometry.compute_normals();
+        }
+
+        let vertex_position_range = self.vertex_positions.write(device, queue, &geometry.vertex_positions);
+        self.vertex_normals.reserve(device, queue, vertex_position_range.start + vertex_position_range.size as u64);
+        self.vertex_normals.write_at(queue, &vertex_position_range, &geometry.vertex_normals);
+        let uv_range = BufferRange {
+            start: vertex_position_range.start,
+            size: vertex_position_range.size,
+            buffer_item_size: size_of::<glam::Vec2>(),
+            phantom: PhantomData
+        };
+        self.vertex_uvs.reserve(device, queue, uv_range.start + uv_range.size as u64);
+        if geometry.vertex_uvs.len() == geometry.vertex_positions.len() {
+            self.vertex_uvs.write_at(queue, &uv_range, &geometry.vertex_uvs);
+        }
+        else {
+            self.vertex_uvs.write_at(queue, &uv_range, &vec![glam::Vec2::ZERO; uv_range.size]);
+        }
+        let indices_range = self.indices.write(device, queue, &geometry.indices);
+
+        GeometryEntry {
+            bounding_sphere: geometry.bounding_sphere(),
+            geometry,
+            vertex_position_range,
+            indices_range,
+            shared: false
+        }
+    }
+
+    // Normals and uvs live at the positions' range, so there's nothing to free for them separately
+    fn free(&mut self, entry: &GeometryEntry) {
+        if !entry.shared {
+            self.vertex_positions.free(&entry.vertex_position_range);
+            self.indices.free(&entry.indices_range);
+        }
+    }
+
+    // The handle stops resolving right away and its buffer space goes back to be reused
+    pub fn unload_mesh(&mut self, handle: GeometryHandle) -> Result<Geometry, RendererError> {
+        let entry = self.geometries.remove(handle)?;
+        self.free(&entry);
+        Ok(entry.geometry)
+    }
+
+    pub fn get(&self, handle: GeometryHandle) -> Result<&GeometryEntry, RendererError> {
+        self.geometries.get(handle)
+    }
+
+    pub fn get_geometry_data(&self, handle: GeometryHandle) -> Result<&Geometry, RendererError> {
+        self.get(handle).map(|entry| &entry.geometry)
+    }
+
+    pub fn set_geometry_buffers<'a, 'b>(&'a self, render_pass: &mut wgpu::RenderPass<'b>) 
+        where 'a: 'b
+    {
+        render_pass.set_vertex_buffer(0, self.vertex_positions.wgpu_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.vertex_normals.wgpu_buffer.slice(..));
+        render_pass.set_vertex_buffer(2, self.vertex_uvs.wgpu_buffer.slice(..));
+        render_pass.set_index_buffer(self.indices.wgpu_buffer.slice(..), wgpu::IndexFormat::Uint32);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,354 @@
+// Generated shapes for debugging and placeholders. Everything is centered on the origin with
+// +Y up, has outward normals and uvs, and is wound counter clockwise like everything else.
+// Segment counts below the smallest that still makes the shape are raised to it.
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use glam::{Vec2, Vec3};
+use super::Geometry;
+
+// A box with sides of length size, each face split into segments x segments quads and
+// given the whole texture
+pub fn cube(size: f32, segments: u32) -> Geometry {
+    let segments = segments.max(1);
+    let half = size / 2.0;
+    let mut geometry = Geometry::default();
+    // Top left corner of each face seen from outside, then its right and down edges
+    let faces = [
+        (Vec3::new(-half, half, half), Vec3::X, -Vec3::Y),
+        (Vec3::new(half, half, -half), -Vec3::X, -Vec3::Y),
+        (Vec3::new(half, half, half), -Vec3::Z, -Vec3::Y),
+        (Vec3::new(-half, half, -half), Vec3::Z, -Vec3::Y),
+        (Vec3::new(-half, half, -half), Vec3::X, Vec3::Z),
+        (Vec3::new(-half, -half, half), Vec3::X, -Vec3::Z)
+    ];
+    for (origin, right, down) in faces.iter() {
+        let normal = down.cross(*right);
+        push_grid(&mut geometry, segments, segments, |u, v| {
+            (*origin + *right * (u * size) + *down * (v * size), normal)
+        });
+    }
+
+    geometry
+}
+
+// A flat width x depth square facing +Y
+pub fn plane(width: f32, depth: f32) -> Geometry {
+    grid(width, depth, 1, 1)
+}
+
+// A plane split into columns x rows quads, columns along X and rows along Z
+pub fn grid(width: f32, depth: f32, columns: u32, rows: u32) -> Geometry {
+    let mut geometry = Geometry::default();
+    push_grid(&mut geometry, columns.max(1), rows.max(1), |u, v| {
+        (Vec3::new((u - 0.5) * width, 0.0, (v - 0.5) * depth), Vec3::Y)
+    });
+
+    geometry
+}
+
+// Latitude/longitude sphere. segments go around the equator, rings from pole to pole.
+pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> Geometry {
+    let mut geometry = Geometry::default();
+    push_grid(&mut geometry, segments.max(3), rings.max(2), |u, v| {
+        let normal = sphere_normal(u * 2.0 * PI, v * PI);
+        (normal * radius, normal)
+    });
+
+    geometry
+}
+
+// An icosahedron with every triangle split in four, subdivisions times, and pushed out onto
+// the sphere. Evenly spread triangles but the uvs are only approximate, the triangles along
+// the seam and around the poles stretch across the texture.
+pub fn icosphere(radius: f32, subdivisions: u32) -> Geometry {
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let mut directions: Vec<Vec3> = [
+        (-1.0, t, 0.0), (1.0, t, 0.0), (-1.0, -t, 0.0), (1.0, -t, 0.0),
+        (0.0, -1.0, t), (0.0, 1.0, t), (0.0, -1.0, -t), (0.0, 1.0, -t),
+        (t, 0.0, -1.0), (t, 0.0, 1.0), (-t, 0.0, -1.0), (-t, 0.0, 1.0)
+    ].iter().map(|&(x, y, z)| Vec3::new(x, y, z).normalize()).collect();
+    let mut indices = vec![
+        0, 11, 5, 0, 5, 1, 0, 1, 7, 0, 7, 10, 0, 10, 11,
+        1, 5, 9, 5, 11, 4, 11, 10, 2, 10, 7, 6, 7, 1, 8,
+        3, 9, 4, 3, 4, 2, 3, 2, 6, 3, 6, 8, 3, 8, 9,
+        4, 9, 5, 2, 4, 11, 6, 2, 10, 8, 6, 7, 9, 8, 1
+    ];
+
+    for _ in 0..subdivisions {
+        // Neighbouring triangles share their edge midpoints
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut midpoint = |a: u32, b: u32| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                directions.push((directions[a as usize] + directions[b as usize]).normalize());
+                (directions.len() - 1) as u32
+            })
+        };
+
+        let mut subdivided = Vec::with_capacity(indices.len() * 4);
+        for triangle in indices.chunks_exact(3) {
+            let (a, b, c) = (triangle[0], triangle[1], triangle[2]);
+            let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+            subdivided.extend_from_slice(&[a, ab, ca, b, bc, ab, c, ca, bc, ab, bc, ca]);
+        }
+        indices = subdivided;
+    }
+
+    Geometry {
+        vertex_positions: directions.iter().map(|direction| *direction * radius).collect(),
+        vertex_uvs: directions.iter().map(|direction| Vec2::new(
+            (-direction.z).atan2(direction.x).rem_euclid(2.0 * PI) / (2.0 * PI),
+            direction.y.clamp(-1.0, 1.0).acos() / PI
+        )).collect(),
+        vertex_normals: directions,
+        indices,
+        ..Geometry::default()
+    }
+}
+
+// Capped at both ends, height is the full length along Y
+pub fn cylinder(radius: f32, height: f32, segments: u32, height_segments: u32) -> Geometry {
+    let segments = segments.max(3);
+    let mut geometry = Geometry::default();
+    push_grid(&mut geometry, segments, height_segments.max(1), |u, v| {
+        let normal = ring_direction(u * 2.0 * PI);
+        (normal * radius + Vec3::Y * (height * (0.5 - v)), normal)
+    });
+    push_cap(&mut geometry, radius, height / 2.0, segments, true);
+    push_cap(&mut geometry, radius, -height / 2.0, segments, false);
+
+    geometry
+}
+
+// Point up, with a cap on the base
+pub fn cone(radius: f32, height: f32, segments: u32) -> Geometry {
+    let segments = segments.max(3);
+    let mut geometry = Geometry::default();
+    push_grid(&mut geometry, segments, 1, |u, v| {
+        let direction = ring_direction(u * 2.0 * PI);
+        // Tilted up by the slope of the side
+        let normal = (direction * height + Vec3::Y * radius).normalize();
+        (direction * (radius * v) + Vec3::Y * (height * (0.5 - v)), normal)
+    });
+    push_cap(&mut geometry, radius, -height / 2.0, segments, false);
+
+    geometry
+}
+
+// A cylinder with hemispheres on the ends. height is the length of the straight part,
+// rings is per hemisphere.
+pub fn capsule(radius: f32, height: f32, segments: u32, rings: u32) -> Geometry {
+    let rings = rings.max(1);
+    let mut geometry = Geometry::default();
+    // The uvs are stretched along the whole outline so the texture doesn't jump at the seams
+    let arc = PI * radius / 2.0;
+    let length = 2.0 * arc + height;
+    push_rows(&mut geometry, segments.max(3), 2 * rings + 1, |u, row| {
+        // The first half of the rows are the top hemisphere, the rest the bottom one
+        let (polar, offset, distance) = if row <= rings {
+            let t = row as f32 / rings as f32;
+            (t * PI / 2.0, height / 2.0, t * arc)
+        }
+        else {
+            let t = (row - rings - 1) as f32 / rings as f32;
+            (PI / 2.0 + t * PI / 2.0, -height / 2.0, arc + height + t * arc)
+        };
+        let normal = sphere_normal(u * 2.0 * PI, polar);
+        (normal * radius + Vec3::Y * offset, normal, Vec2::new(u, distance / length))
+    });
+
+    geometry
+}
+
+// Lying flat around Y. major_radius is out to the middle of the tube, minor_radius the tube's own.
+pub fn torus(major_radius: f32, minor_radius: f32, major_segments: u32, minor_segments: u32) -> Geometry {
+    let mut geometry = Geometry::default();
+    push_grid(&mut geometry, major_segments.max(3), minor_segments.max(3), |u, v| {
+        let direction = ring_direction(u * 2.0 * PI);
+        // Around the tube starting from its outer edge, heading down first
+        let angle = -v * 2.0 * PI;
+        let normal = direction * angle.cos() + Vec3::Y * angle.sin();
+        (direction * major_radius + normal * minor_radius, normal)
+    });
+
+    geometry
+}
+
+// Outward direction around Y, counter clockwise seen from above
+fn ring_direction(angle: f32) -> Vec3 {
+    Vec3::new(angle.cos(), 0.0, -angle.sin())
+}
+
+// polar is 0 at the top pole and PI at the bottom one
+fn sphere_normal(azimuth: f32, polar: f32) -> Vec3 {
+    ring_direction(azimuth) * polar.sin() + Vec3::Y * polar.cos()
+}
+
+// A grid of (columns + 1) x (rows + 1) vertices, uvs straight from the grid position. The
+// surface gives the position and normal at each (u, v), with u heading right and v heading
+// down as seen from the front.
+fn push_grid<F: Fn(f32, f32) -> (Vec3, Vec3)>(geometry: &mut Geometry, columns: u32, rows: u32, surface: F) {
+    push_rows(geometry, columns, rows, |u, row| {
+        let v = row as f32 / rows as f32;
+        let (position, normal) = surface(u, v);
+        (position, normal, Vec2::new(u, v))
+    });
+}
+
+// Like push_grid but the surface also picks the uvs, and gets the row index to work from
+fn push_rows<F: Fn(f32, u32) -> (Vec3, Vec3, Vec2)>(geometry: &mut Geometry, columns: u32, rows: u32, surface: F) {
+    let start = geometry.vertex_positions.len() as u32;
+    for row in 0..=rows {
+        for column in 0..=columns {
+            let (position, normal, uv) = surface(column as f32 / columns as f32, row);
+            geometry.vertex_positions.push(position);
+            geometry.vertex_normals.push(normal);
+            geometry.vertex_uvs.push(uv);
+        }
+    }
+
+    let stride = columns + 1;
+    for row in 0..rows {
+        for column in 0..columns {
+            let top_left = start + row * stride + column;
+            let (top_right, bottom_left, bottom_right) = (top_left + 1, top_left + stride, top_left + stride + 1);
+            geometry.indices.extend_from_slice(&[top_left, bottom_left, top_right, top_right, bottom_left, bottom_right]);
+        }
+    }
+}
+
+// A flat disc closing off one end of a cylinder or cone at height y
+fn push_cap(geometry: &mut Geometry, radius: f32, y: f32, segments: u32, top: bool) {
+    let normal = if top { Vec3::Y } else { -Vec3::Y };
+    let center = geometry.vertex_positions.len() as u32;
+    geometry.vertex_positions.push(Vec3::new(0.0, y, 0.0));
+    geometry.vertex_normals.push(normal);
+    geometry.vertex_uvs.push(Vec2::new(0.5, 0.5));
+
+    for segment in 0..=segments {
+        let direction = ring_direction(segment as f32 / segments as f32 * 2.0 * PI);
+        geometry.vertex_positions.push(direction * radius + Vec3::Y * y);
+        geometry.vertex_normals.push(normal);
+        // Mirrored underneath, so the texture reads the right way round from either side
+        let z = if top { direction.z } else { -direction.z };
+        geometry.vertex_uvs.push(Vec2::new(0.5 + direction.x / 2.0, 0.5 + z / 2.0));
+    }
+
+    for segment in 0..segments {
+        let (a, b) = (center + 1 + segment, center + 2 + segment);
+        if top {
+            geometry.indices.extend_from_slice(&[center, a, b]);
+        }
+        else {
+            geometry.indices.extend_from_slice(&[center, b, a]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Indices in range, unit normals, and every triangle wound to face the way its vertex
+    // normals do, which for these shapes is outward. Triangles that collapse at poles and
+    // tips have no direction and are skipped.
+    fn check_surface(geometry: &Geometry) {
+        let vertex_count = geometry.vertex_positions.len();
+        assert_eq!(geometry.vertex_normals.len(), vertex_count);
+        assert_eq!(geometry.vertex_uvs.len(), vertex_count);
+        assert_eq!(geometry.indices.len() % 3, 0);
+        assert!(geometry.indices.iter().all(|&index| (index as usize) < vertex_count));
+        assert!(geometry.vertex_normals.iter().all(|normal| (normal.length() - 1.0).abs() < 1e-4));
+
+        for triangle in geometry.indices.chunks_exact(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|index| index as usize);
+            let [pa, pb, pc] = [a, b, c].map(|index| geometry.vertex_positions[index]);
+            let face = (pb - pa).cross(pc - pa);
+            if face.length() < 1e-6 {
+                continue;
+            }
+            let normal = geometry.vertex_normals[a] + geometry.vertex_normals[b] + geometry.vertex_normals[c];
+            assert!(face.dot(normal) > 0.0, "triangle {:?} is wound against its normals", triangle);
+        }
+    }
+
+    // Closed shapes around the origin also face away from it
+    fn check_outward(geometry: &Geometry) {
+        for triangle in geometry.indices.chunks_exact(3) {
+            let [pa, pb, pc] = [triangle[0], triangle[1], triangle[2]].map(|index| geometry.vertex_positions[index as usize]);
+            let face = (pb - pa).cross(pc - pa);
+            if face.length() >= 1e-6 {
+                assert!(face.dot(pa + pb + pc) > 0.0, "triangle {:?} faces inward", triangle);
+            }
+        }
+    }
+
+    fn check_counts(geometry: &Geometry, vertices: usize, triangles: usize) {
+        assert_eq!(geometry.vertex_positions.len(), vertices);
+        assert_eq!(geometry.indices.len(), triangles * 3);
+    }
+
+    #[test]
+    fn cube_faces() {
+        let geometry = cube(2.0, 3);
+        check_counts(&geometry, 6 * 4 * 4, 6 * 3 * 3 * 2);
+        check_surface(&geometry);
+        check_outward(&geometry);
+        assert!(geometry.vertex_positions.iter().all(|position| position.abs().max_element() == 1.0));
+    }
+
+    #[test]
+    fn grid_and_plane_face_up() {
+        let geometry = grid(4.0, 2.0, 4, 2);
+        check_counts(&geometry, 5 * 3, 4 * 2 * 2);
+        check_surface(&geometry);
+        assert!(geometry.vertex_normals.iter().all(|normal| *normal == Vec3::Y));
+        check_counts(&plane(1.0, 1.0), 4, 2);
+    }
+
+    #[test]
+    fn sphere_faces() {
+        let geometry = uv_sphere(2.0, 16, 8);
+        check_counts(&geometry, 17 * 9, 16 * 8 * 2);
+        check_surface(&geometry);
+        check_outward(&geometry);
+        assert!(geometry.vertex_positions.iter().all(|position| (position.length() - 2.0).abs() < 1e-4));
+
+        // Big enough to go past the geometry store's starting buffer sizes
+        let geometry = icosphere(1.0, 5);
+        check_counts(&geometry, 10 * 4usize.pow(5) + 2, 20 * 4usize.pow(5));
+        check_surface(&geometry);
+        check_outward(&geometry);
+    }
+
+    #[test]
+    fn cylinder_and_cone_faces() {
+        let geometry = cylinder(1.0, 2.0, 12, 3);
+        check_counts(&geometry, 13 * 4 + 2 * 14, 12 * 3 * 2 + 2 * 12);
+        check_surface(&geometry);
+        check_outward(&geometry);
+
+        let geometry = cone(1.0, 2.0, 12);
+        check_counts(&geometry, 13 * 2 + 14, 12 * 2 + 12);
+        check_surface(&geometry);
+        check_outward(&geometry);
+    }
+
+    #[test]
+    fn capsule_and_torus_faces() {
+        let geometry = capsule(0.5, 1.0, 12, 4);
+        check_counts(&geometry, 13 * 10, 12 * 9 * 2);
+        check_surface(&geometry);
+        check_outward(&geometry);
+
+        // Not convex, the normals point out of the tube rather than away from the origin
+        let geometry = torus(2.0, 0.5, 16, 8);
+        check_counts(&geometry, 17 * 9, 16 * 8 * 2);
+        check_surface(&geometry);
+    }
+
+    #[test]
+    fn segment_counts_are_raised_to_the_minimum() {
+        check_counts(&uv_sphere(1.0, 0, 0), 4 * 3, 3 * 2 * 2);
+        check_counts(&cube(1.0, 0), 6 * 4, 6 * 2);
+    }
+}
@@ -0,0 +1,821 @@
+// Clean up and optimization passes, all run on the CPU copy before it's handed to
+// GeometryStore::load_mesh. The triangle passes leave point clouds alone.
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use glam::Vec3;
+use super::{Geometry, Topology};
+
+// Sizes of the caches simulated when reordering triangles. Forsyth's scoring assumes a
+// 32 entry LRU, overdraw clusters are split where a 16 entry one misses completely.
+const VERTEX_CACHE_SIZE: usize = 32;
+const OVERDRAW_CACHE_SIZE: usize = 16;
+
+impl Geometry {
+    // Every triangle gets its own three vertices with the face normal, the other attributes
+    // are copied along. Tangents are dropped, they need working out again for the new normals.
+    pub fn compute_flat_normals(&mut self) {
+        if self.topology != Topology::Triangles {
+            return;
+        }
+
+        let mut order = Vec::with_capacity(self.indices.len());
+        let mut normals = Vec::with_capacity(self.indices.len());
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|index| self.vertex_positions[index as usize]);
+            let normal = (b - a).cross(c - a);
+            let normal = if normal.length_squared() > 0.0 { normal.normalize() } else { Vec3::Y };
+            order.extend_from_slice(triangle);
+            normals.extend_from_slice(&[normal; 3]);
+        }
+
+        self.vertex_tangents.clear();
+        self.gather_vertices(&order);
+        self.vertex_normals = normals;
+        self.indices = (0..order.len() as u32).collect();
+    }
+
+    // Tangents from the reference MikkTSpace implementation, the convention glTF uses, so
+    // normal maps baked against it line up. Vertices whose corners end up with different
+    // tangents, like along mirrored seams, are split. Needs uvs, computes smooth normals first
+    // if there aren't any.
+    pub fn compute_tangents(&mut self) {
+        let vertex_count = self.vertex_positions.len();
+        if self.topology != Topology::Triangles || self.vertex_uvs.len() != vertex_count || self.indices.len() < 3 {
+            return;
+        }
+        if self.vertex_normals.len() != vertex_count {
+            self.compute_normals();
+        }
+
+        let mut faces = MikkTSpaceFaces {
+            geometry: self,
+            corner_tangents: vec![glam::Vec4::ZERO; self.indices.len() / 3 * 3]
+        };
+        if !bevy_mikktspace::generate_tangents(&mut faces) {
+            return;
+        }
+        let corner_tangents = faces.corner_tangents;
+
+        // A vertex per distinct vertex and tangent pair, in the order they're first used
+        let mut vertices: HashMap<(u32, [u32; 4]), u32> = HashMap::new();
+        let mut order = Vec::with_capacity(vertex_count);
+        let mut tangents = Vec::with_capacity(vertex_count);
+        let mut indices = Vec::with_capacity(corner_tangents.len());
+        for (&index, tangent) in self.indices.iter().zip(&corner_tangents) {
+            let new_index = *vertices.entry((index, tangent.to_array().map(f32::to_bits))).or_insert_with(|| {
+                order.push(index);
+                tangents.push(*tangent);
+                (order.len() - 1) as u32
+            });
+            indices.push(new_index);
+        }
+
+        self.vertex_tangents.clear();
+        self.gather_vertices(&order);
+        self.vertex_tangents = tangents;
+        self.indices = indices;
+    }
+
+    // Merges vertices whose attributes are all exactly the same
+    pub fn deduplicate_vertices(&mut self) {
+        self.weld_vertices(0.0);
+    }
+
+    // Merges vertices where every attribute is within tolerance of another vertex's, keeping
+    // the first one's. Triangles that collapse as a result are removed.
+    pub fn weld_vertices(&mut self, tolerance: f32) {
+        let vertex_count = self.vertex_positions.len();
+        // Vertices are bucketed by position, a match can only be in the same or a neighbouring cell.
+        // With no tolerance the cell is the exact position.
+        let cell = |position: Vec3| -> [i64; 3] {
+            if tolerance > 0.0 {
+                (position / tolerance).floor().to_array().map(|value| value as i64)
+            }
+            else {
+                // -0.0 and 0.0 are the same point
+                (position + Vec3::ZERO).to_array().map(|value| value.to_bits() as i64)
+            }
+        };
+        let reach = if tolerance > 0.0 { 1 } else { 0 };
+
+        let mut cells: HashMap<[i64; 3], Vec<u32>> = HashMap::new();
+        let mut remap = vec![0u32; vertex_count];
+        let mut kept = Vec::new();
+        for vertex in 0..vertex_count {
+            let [x, y, z] = cell(self.vertex_positions[vertex]);
+            let mut found = None;
+            'search: for dx in -reach..=reach {
+                for dy in -reach..=reach {
+                    for dz in -reach..=reach {
+                        let candidates = match cells.get(&[x + dx, y + dy, z + dz]) {
+                            Some(candidates) => candidates,
+                            None => continue
+                        };
+                        if let Some(&other) = candidates.iter().find(|&&other| self.vertices_match(other as usize, vertex, tolerance)) {
+                            found = Some(other);
+                            break 'search;
+                        }
+                    }
+                }
+            }
+
+            remap[vertex] = match found {
+                Some(other) => remap[other as usize],
+                None => {
+                    cells.entry([x, y, z]).or_default().push(vertex as u32);
+                    kept.push(vertex as u32);
+                    (kept.len() - 1) as u32
+                }
+            };
+        }
+
+        self.gather_vertices(&kept);
+        let indices: Vec<u32> = self.indices.iter().map(|&index| remap[index as usize]).collect();
+        self.indices = match self.topology {
+            Topology::Triangles => indices.chunks_exact(3)
+                .filter(|triangle| triangle[0] != triangle[1] && triangle[1] != triangle[2] && triangle[2] != triangle[0])
+                .flatten()
+                .copied()
+                .collect(),
+            // Merged points would be drawn twice
+            Topology::Points => {
+                let mut seen = vec![false; kept.len()];
+                indices.into_iter().filter(|&index| !std::mem::replace(&mut seen[index as usize], true)).collect()
+            }
+        };
+    }
+
+    // Reorders triangles so vertices are reused while they're still in the post transform
+    // cache, Tom Forsyth's linear speed algorithm
+    pub fn optimize_vertex_cache(&mut self) {
+        if self.topology != Topology::Triangles {
+            return;
+        }
+
+        let vertex_count = self.vertex_positions.len();
+        let triangle_count = self.indices.len() / 3;
+        let mut vertex_triangles: Vec<Vec<u32>> = vec![Vec::new(); vertex_count];
+        for (triangle, corners) in self.indices.chunks_exact(3).enumerate() {
+            for &vertex in corners {
+                vertex_triangles[vertex as usize].push(triangle as u32);
+            }
+        }
+
+        let mut cache_positions: Vec<Option<usize>> = vec![None; vertex_count];
+        let mut vertex_scores: Vec<f32> = vertex_triangles.iter().map(|triangles| vertex_score(None, triangles.len())).collect();
+        let triangle_score = |triangle: usize, vertex_scores: &[f32]| -> f32 {
+            self.indices[triangle * 3..triangle * 3 + 3].iter().map(|&vertex| vertex_scores[vertex as usize]).sum()
+        };
+        let mut triangle_scores: Vec<f32> = (0..triangle_count).map(|triangle| triangle_score(triangle, &vertex_scores)).collect();
+        let mut added = vec![false; triangle_count];
+
+        let mut best = (0..triangle_count).max_by(|&a, &b| triangle_scores[a].partial_cmp(&triangle_scores[b]).unwrap_or(Ordering::Equal));
+        let mut cache: Vec<u32> = Vec::with_capacity(VERTEX_CACHE_SIZE + 3);
+        let mut output = Vec::with_capacity(self.indices.len());
+        let mut next_unadded = 0;
+        while output.len() < triangle_count * 3 {
+            // Nothing in the cache has triangles left, carry on with any that hasn't been added
+            let triangle = match best {
+                Some(triangle) => triangle,
+                None => {
+                    while added[next_unadded] {
+                        next_unadded += 1;
+                    }
+                    next_unadded
+                }
+            };
+            added[triangle] = true;
+
+            let corners = [self.indices[triangle * 3], self.indices[triangle * 3 + 1], self.indices[triangle * 3 + 2]];
+            output.extend_from_slice(&corners);
+            for &vertex in &corners {
+                let triangles = &mut vertex_triangles[vertex as usize];
+                if let Some(position) = triangles.iter().position(|&other| other as usize == triangle) {
+                    triangles.swap_remove(position);
+                }
+            }
+
+            // The triangle's vertices move to the front, whatever falls off the end is evicted
+            let mut new_cache = Vec::with_capacity(VERTEX_CACHE_SIZE + 3);
+            for &vertex in &corners {
+                if !new_cache.contains(&vertex) {
+                    new_cache.push(vertex);
+                }
+            }
+            new_cache.extend(cache.iter().filter(|vertex| !corners.contains(vertex)));
+            for (position, &vertex) in new_cache.iter().enumerate() {
+                cache_positions[vertex as usize] = if position < VERTEX_CACHE_SIZE { Some(position) } else { None };
+                vertex_scores[vertex as usize] = vertex_score(cache_positions[vertex as usize], vertex_triangles[vertex as usize].len());
+            }
+
+            best = None;
+            let mut best_score = f32::MIN;
+            for &vertex in &new_cache {
+                for &other in &vertex_triangles[vertex as usize] {
+                    let score = triangle_score(other as usize, &vertex_scores);
+                    triangle_scores[other as usize] = score;
+                    if score > best_score {
+                        best_score = score;
+                        best = Some(other as usize);
+                    }
+                }
+            }
+            new_cache.truncate(VERTEX_CACHE_SIZE);
+            cache = new_cache;
+        }
+
+        self.indices = output;
+    }
+
+    // Splits the triangle order into clusters where the vertex cache would start over anyway,
+    // then draws the clusters facing out from the middle of the mesh first, so what's behind
+    // them fails the depth test. Run it after optimize_vertex_cache, it keeps the order
+    // inside each cluster.
+    pub fn optimize_overdraw(&mut self) {
+        if self.topology != Topology::Triangles || self.indices.len() < 6 {
+            return;
+        }
+
+        let mut clusters = Vec::new();
+        let mut cache: Vec<u32> = Vec::with_capacity(OVERDRAW_CACHE_SIZE + 3);
+        let mut start = 0;
+        for (triangle, corners) in self.indices.chunks_exact(3).enumerate() {
+            if triangle > start && corners.iter().all(|vertex| !cache.contains(vertex)) {
+                clusters.push(start..triangle);
+                start = triangle;
+            }
+            for &vertex in corners {
+                cache.retain(|&other| other != vertex);
+                cache.insert(0, vertex);
+            }
+            cache.truncate(OVERDRAW_CACHE_SIZE);
+        }
+        clusters.push(start..self.indices.len() / 3);
+
+        // Area weighted centroid and normal of a run of triangles
+        let surface = |triangles: std::ops::Range<usize>| -> (Vec3, Vec3) {
+            let (mut centroid, mut normal, mut area) = (Vec3::ZERO, Vec3::ZERO, 0.0);
+            for corners in self.indices[triangles.start * 3..triangles.end * 3].chunks_exact(3) {
+                let [a, b, c] = [corners[0], corners[1], corners[2]].map(|index| self.vertex_positions[index as usize]);
+                let face = (b - a).cross(c - a);
+                let face_area = face.length();
+                centroid += (a + b + c) / 3.0 * face_area;
+                normal += face;
+                area += face_area;
+            }
+            (if area > 0.0 { centroid / area } else { Vec3::ZERO }, normal.normalize_or_zero())
+        };
+
+        let (mesh_centroid, _) = surface(0..self.indices.len() / 3);
+        let mut sorted: Vec<(f32, std::ops::Range<usize>)> = clusters.into_iter().map(|cluster| {
+            let (centroid, normal) = surface(cluster.clone());
+            ((centroid - mesh_centroid).dot(normal), cluster)
+        }).collect();
+        sorted.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+
+        self.indices = sorted.iter()
+            .flat_map(|(_, cluster)| self.indices[cluster.start * 3..cluster.end * 3].iter().copied())
+            .collect();
+    }
+
+    // Renumbers the vertices in the order the indices first use them, so vertex fetches walk
+    // the buffers forwards. Vertices nothing uses are dropped.
+    pub fn optimize_vertex_fetch(&mut self) {
+        let mut remap = vec![u32::MAX; self.vertex_positions.len()];
+        let mut order = Vec::new();
+        for index in self.indices.iter_mut() {
+            if remap[*index as usize] == u32::MAX {
+                remap[*index as usize] = order.len() as u32;
+                order.push(*index);
+            }
+            *index = remap[*index as usize];
+        }
+
+        self.gather_vertices(&order);
+    }
+
+    // Collapses edges, cheapest first by quadric error, until there are at most target_triangles
+    // left or nothing more can go without flipping a triangle. Vertices on open edges and uv or
+    // normal seams are kept where they are so the outline and seams don't crack. The result only
+    // keeps the vertices still in use.
+    pub fn simplify(&self, target_triangles: usize) -> Geometry {
+        let mut simplified = self.clone();
+        if self.topology != Topology::Triangles || self.indices.len() / 3 <= target_triangles {
+            return simplified;
+        }
+
+        let positions = &self.vertex_positions;
+        let vertex_count = positions.len();
+        let mut triangles: Vec<[u32; 3]> = self.indices.chunks_exact(3).map(|corners| [corners[0], corners[1], corners[2]]).collect();
+        let mut triangle_alive = vec![true; triangles.len()];
+        let mut alive_count = triangles.len();
+        let mut vertex_triangles: Vec<Vec<usize>> = vec![Vec::new(); vertex_count];
+        for (triangle, corners) in triangles.iter().enumerate() {
+            for &vertex in corners {
+                vertex_triangles[vertex as usize].push(triangle);
+            }
+        }
+
+        // Open edges are only used by one triangle. Seams show up as open edges too, the two
+        // sides use different vertices, but a vertex sharing its position is locked either way.
+        let mut locked = vec![false; vertex_count];
+        let mut edge_uses: HashMap<(u32, u32), u32> = HashMap::new();
+        for corners in &triangles {
+            for edge in 0..3 {
+                let (a, b) = (corners[edge], corners[(edge + 1) % 3]);
+                *edge_uses.entry((a.min(b), a.max(b))).or_default() += 1;
+            }
+        }
+        for (&(a, b), &uses) in &edge_uses {
+            if uses == 1 {
+                locked[a as usize] = true;
+                locked[b as usize] = true;
+            }
+        }
+        let mut at_position: HashMap<[u32; 3], u32> = HashMap::new();
+        for position in positions {
+            *at_position.entry((*position + Vec3::ZERO).to_array().map(f32::to_bits)).or_default() += 1;
+        }
+        for (vertex, position) in positions.iter().enumerate() {
+            if at_position[&(*position + Vec3::ZERO).to_array().map(f32::to_bits)] > 1 {
+                locked[vertex] = true;
+            }
+        }
+
+        // Flips are checked against where each triangle started too, small turns add up otherwise
+        let original_normals: Vec<Vec3> = triangles.iter().map(|corners| {
+            let [a, b, c] = corners.map(|index| positions[index as usize]);
+            (b - a).cross(c - a)
+        }).collect();
+
+        let mut quadrics = vec![Quadric::default(); vertex_count];
+        for corners in &triangles {
+            let [a, b, c] = corners.map(|index| positions[index as usize]);
+            let face = (b - a).cross(c - a);
+            let area = face.length();
+            if area > 0.0 {
+                let quadric = Quadric::from_plane(face / area, a, area / 2.0);
+                for &vertex in corners {
+                    quadrics[vertex as usize].add(&quadric);
+                }
+            }
+        }
+
+        // Candidates go stale when either end changes, versions tell which ones are still current
+        let mut versions = vec![0u32; vertex_count];
+        let mut removed = vec![false; vertex_count];
+        let mut candidates = BinaryHeap::new();
+        let push_candidates = |vertex: u32,
+                               candidates: &mut BinaryHeap<Reverse<Collapse>>,
+                               quadrics: &[Quadric],
+                               versions: &[u32],
+                               vertex_triangles: &[Vec<usize>],
+                               triangles: &[[u32; 3]]| {
+            for &triangle in &vertex_triangles[vertex as usize] {
+                for &other in &triangles[triangle] {
+                    if other == vertex {
+                        continue;
+                    }
+                    for (from, to) in [(vertex, other), (other, vertex)] {
+                        if locked[from as usize] {
+                            continue;
+                        }
+                        let mut quadric = quadrics[from as usize];
+                        quadric.add(&quadrics[to as usize]);
+                        // Errors are never negative, so the bits sort the same as the floats
+                        let cost = quadric.error(positions[to as usize]).max(0.0);
+                        candidates.push(Reverse((cost.to_bits(), from, to, versions[from as usize], versions[to as usize])));
+                    }
+                }
+            }
+        };
+        for vertex in 0..vertex_count as u32 {
+            push_candidates(vertex, &mut candidates, &quadrics, &versions, &vertex_triangles, &triangles);
+        }
+
+        while alive_count > target_triangles {
+            let Reverse((_, from, to, from_version, to_version)) = match candidates.pop() {
+                Some(candidate) => candidate,
+                None => break
+            };
+            let (from_index, to_index) = (from as usize, to as usize);
+            if removed[from_index] || removed[to_index] || versions[from_index] != from_version || versions[to_index] != to_version {
+                continue;
+            }
+
+            // Moving from onto to mustn't turn any of the triangles that stay around by more than
+            // about 75 degrees, or squash them flat
+            let flips = vertex_triangles[from_index].iter()
+                .filter(|&&triangle| !triangles[triangle].contains(&to))
+                .any(|&triangle| {
+                    let corners = triangles[triangle];
+                    let before = corners.map(|index| positions[index as usize]);
+                    let after = corners.map(|index| positions[if index == from { to_index } else { index as usize }]);
+                    let normal_before = (before[1] - before[0]).cross(before[2] - before[0]);
+                    let normal_after = (after[1] - after[0]).cross(after[2] - after[0]);
+                    normal_after.dot(normal_before) <= 0.25 * normal_after.length() * normal_before.length()
+                        || normal_after.dot(original_normals[triangle]) <= 0.0
+                });
+            if flips {
+                continue;
+            }
+
+            for triangle in std::mem::take(&mut vertex_triangles[from_index]) {
+                if !triangle_alive[triangle] {
+                    continue;
+                }
+                if triangles[triangle].contains(&to) {
+                    triangle_alive[triangle] = false;
+                    alive_count -= 1;
+                }
+                else {
+                    for corner in triangles[triangle].iter_mut() {
+                        if *corner == from {
+                            *corner = to;
+                        }
+                    }
+                    vertex_triangles[to_index].push(triangle);
+                }
+            }
+            vertex_triangles[to_index].retain(|&triangle| triangle_alive[triangle]);
+            removed[from_index] = true;
+            let from_quadric = quadrics[from_index];
+            quadrics[to_index].add(&from_quadric);
+            versions[to_index] += 1;
+            push_candidates(to, &mut candidates, &quadrics, &versions, &vertex_triangles, &triangles);
+        }
+
+        simplified.indices = triangles.iter()
+            .zip(&triangle_alive)
+            .filter(|(_, &alive)| alive)
+            .flat_map(|(corners, _)| corners.iter().copied())
+            .collect();
+        simplified.optimize_vertex_fetch();
+        simplified
+    }
+
+    // Rebuilds every attribute from the given vertices, in that order
+    fn gather_vertices(&mut self, order: &[u32]) {
+        let vertex_count = self.vertex_positions.len();
+        fn gather<T: Copy>(attribute: &mut Vec<T>, order: &[u32], vertex_count: usize) {
+            // Attributes that don't cover every vertex can't be matched up, they're dropped
+            *attribute = if attribute.len() == vertex_count {
+                order.iter().map(|&index| attribute[index as usize]).collect()
+            }
+            else {
+                Vec::new()
+            };
+        }
+
+        gather(&mut self.vertex_positions, order, vertex_count);
+        gather(&mut self.vertex_normals, order, vertex_count);
+        gather(&mut self.vertex_uvs, order, vertex_count);
+        gather(&mut self.vertex_colors, order, vertex_count);
+        gather(&mut self.vertex_tangents, order, vertex_count);
+    }
+
+    fn vertices_match(&self, a: usize, b: usize, tolerance: f32) -> bool {
+        // Missing attributes always match
+        fn close<T: Copy>(attribute: &[T], a: usize, b: usize, difference: impl Fn(T, T) -> f32, tolerance: f32) -> bool {
+            match (attribute.get(a), attribute.get(b)) {
+                (Some(&x), Some(&y)) => difference(x, y) <= tolerance,
+                _ => true
+            }
+        }
+
+        close(&self.vertex_positions, a, b, |x, y| (x - y).abs().max_element(), tolerance)
+            && close(&self.vertex_normals, a, b, |x, y| (x - y).abs().max_element(), tolerance)
+            && close(&self.vertex_uvs, a, b, |x, y| (x - y).abs().max_element(), tolerance)
+            && close(&self.vertex_colors, a, b, |x, y| (x - y).abs().max_element(), tolerance)
+            && close(&self.vertex_tangents, a, b, |x, y| (x - y).abs().max_element(), tolerance)
+    }
+}
+
+// The triangles as MikkTSpace reads them, collecting the tangent it works out for each corner
+struct MikkTSpaceFaces<'a> {
+    geometry: &'a Geometry,
+    corner_tangents: Vec<glam::Vec4>
+}
+
+impl MikkTSpaceFaces<'_> {
+    fn vertex(&self, face: usize, vert: usize) -> usize {
+        self.geometry.indices[face * 3 + vert] as usize
+    }
+}
+
+impl bevy_mikktspace::Geometry for MikkTSpaceFaces<'_> {
+    fn num_faces(&self) -> usize {
+        self.geometry.indices.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.geometry.vertex_positions[self.vertex(face, vert)].into()
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.geometry.vertex_normals[self.vertex(face, vert)].into()
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        self.geometry.vertex_uvs[self.vertex(face, vert)].into()
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        self.corner_tangents[face * 3 + vert] = glam::Vec4::from(tangent);
+    }
+}
+
+// Cost as bits, the vertex that moves, the one it moves onto, and both their versions when it was worked out
+type Collapse = (u64, u32, u32, u32, u32);
+
+// Forsyth's vertex score, higher for vertices recently used and ones with few triangles left
+fn vertex_score(cache_position: Option<usize>, remaining_triangles: usize) -> f32 {
+    if remaining_triangles == 0 {
+        return -1.0;
+    }
+
+    let cache_score = match cache_position {
+        None => 0.0,
+        // The last triangle's vertices score the same, whichever order they went in
+        Some(position) if position < 3 => 0.75,
+        Some(position) => (1.0 - (position - 3) as f32 / (VERTEX_CACHE_SIZE - 3) as f32).powf(1.5)
+    };
+    // Finishing off vertices with few triangles left gets them out of the way
+    let valence_score = 2.0 * (remaining_triangles as f32).powf(-0.5);
+
+    cache_score + valence_score
+}
+
+// Sum of squared distances to a set of planes, as the upper triangle of a symmetric 4x4 matrix
+#[derive(Debug, Copy, Clone, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(normal: Vec3, point: Vec3, weight: f32) -> Self {
+        let (a, b, c) = (normal.x as f64, normal.y as f64, normal.z as f64);
+        let d = -normal.dot(point) as f64;
+        let weight = weight as f64;
+        Quadric([a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d].map(|value| value * weight))
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (value, other) in self.0.iter_mut().zip(other.0.iter()) {
+            *value += other;
+        }
+    }
+
+    fn error(&self, point: Vec3) -> f64 {
+        let (x, y, z) = (point.x as f64, point.y as f64, point.z as f64);
+        let q = &self.0;
+        q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x
+            + q[4] * y * y + 2.0 * q[5] * y * z + 2.0 * q[6] * y
+            + q[7] * z * z + 2.0 * q[8] * z
+            + q[9]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec2;
+    use crate::geometry::primitives::{cube, grid, icosphere};
+
+    fn compare(a: &[Vec3], b: &[Vec3]) -> std::cmp::Ordering {
+        a.iter().flat_map(|v| v.to_array()).zip(b.iter().flat_map(|v| v.to_array()))
+            .map(|(x, y)| x.total_cmp(&y))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    }
+
+    // Triangles by position with their corners rotated so the smallest comes first, sorted, to
+    // compare meshes whose triangle order or vertex numbering changed but whose winding didn't
+    fn triangle_set(geometry: &Geometry) -> Vec<[Vec3; 3]> {
+        let mut triangles: Vec<[Vec3; 3]> = geometry.indices.chunks_exact(3).map(|corners| {
+            let positions = [corners[0], corners[1], corners[2]].map(|index| geometry.vertex_positions[index as usize]);
+            (0..3).map(|first| [0, 1, 2].map(|offset| positions[(first + offset) % 3]))
+                .min_by(|a, b| compare(a, b))
+                .unwrap()
+        }).collect();
+        triangles.sort_by(|a, b| compare(a, b));
+        triangles
+    }
+
+    // Vertices transformed per triangle with a FIFO cache, what GPUs roughly do
+    fn cache_misses(indices: &[u32], cache_size: usize) -> usize {
+        let mut cache = std::collections::VecDeque::new();
+        let mut misses = 0;
+        for &index in indices {
+            if !cache.contains(&index) {
+                misses += 1;
+                cache.push_back(index);
+                if cache.len() > cache_size {
+                    cache.pop_front();
+                }
+            }
+        }
+        misses
+    }
+
+    fn shuffled_sphere() -> Geometry {
+        let mut geometry = icosphere(1.0, 3);
+        let mut triangles: Vec<[u32; 3]> = geometry.indices.chunks_exact(3).map(|corners| [corners[0], corners[1], corners[2]]).collect();
+        // Fixed seed linear congruential shuffle, the generator's order is already cache friendly
+        let mut state = 12345u64;
+        for i in (1..triangles.len()).rev() {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            triangles.swap(i, (state >> 33) as usize % (i + 1));
+        }
+        geometry.indices = triangles.into_iter().flatten().collect();
+        geometry
+    }
+
+    fn check_indices(geometry: &Geometry) {
+        assert_eq!(geometry.indices.len() % 3, 0);
+        assert!(geometry.indices.iter().all(|&index| (index as usize) < geometry.vertex_positions.len()));
+        assert!(geometry.indices.chunks_exact(3).all(|t| t[0] != t[1] && t[1] != t[2] && t[2] != t[0]));
+    }
+
+    #[test]
+    fn welds_shared_positions() {
+        // The cube's faces only share positions, without normals and uvs they're one vertex
+        let mut geometry = cube(1.0, 1);
+        geometry.vertex_normals.clear();
+        geometry.vertex_uvs.clear();
+        let triangles = triangle_set(&geometry);
+        geometry.deduplicate_vertices();
+        assert_eq!(geometry.vertex_positions.len(), 8);
+        assert_eq!(triangle_set(&geometry), triangles);
+
+        // With normals the faces stay split
+        let mut geometry = cube(1.0, 1);
+        geometry.deduplicate_vertices();
+        assert_eq!(geometry.vertex_positions.len(), 24);
+    }
+
+    #[test]
+    fn welds_within_tolerance_and_drops_collapsed_triangles() {
+        let mut geometry = Geometry {
+            vertex_positions: vec![Vec3::ZERO, Vec3::X, Vec3::Y, Vec3::new(1.0005, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0004)],
+            indices: vec![0, 1, 2, 2, 3, 0, 0, 4, 1],
+            ..Geometry::default()
+        };
+        geometry.weld_vertices(1e-3);
+        assert_eq!(geometry.vertex_positions, vec![Vec3::ZERO, Vec3::X, Vec3::Y]);
+        // The last triangle had two corners at the origin
+        assert_eq!(geometry.indices, vec![0, 1, 2, 2, 1, 0]);
+
+        geometry.weld_vertices(1e-5);
+        assert_eq!(geometry.vertex_positions.len(), 3);
+    }
+
+    #[test]
+    fn vertex_cache_order_misses_less() {
+        let mut geometry = shuffled_sphere();
+        let triangles = triangle_set(&geometry);
+        let before = cache_misses(&geometry.indices, 16);
+        geometry.optimize_vertex_cache();
+        let after = cache_misses(&geometry.indices, 16);
+
+        assert_eq!(triangle_set(&geometry), triangles);
+        assert!(after * 2 < before, "{} misses after, {} before", after, before);
+        // Close to one miss per vertex, each is transformed about once
+        assert!(after < geometry.vertex_positions.len() * 3 / 2, "{} misses for {} vertices", after, geometry.vertex_positions.len());
+    }
+
+    #[test]
+    fn overdraw_order_keeps_triangles_and_cache() {
+        let mut geometry = shuffled_sphere();
+        geometry.optimize_vertex_cache();
+        let triangles = triangle_set(&geometry);
+        let before = cache_misses(&geometry.indices, 16);
+        geometry.optimize_overdraw();
+
+        assert_eq!(triangle_set(&geometry), triangles);
+        // Clusters are only split where the cache started over, so it barely costs anything
+        assert!(cache_misses(&geometry.indices, 16) <= before + before / 10);
+    }
+
+    #[test]
+    fn fetch_order_follows_first_use() {
+        let mut geometry = shuffled_sphere();
+        // An unused vertex, which gets dropped
+        geometry.vertex_positions.push(Vec3::splat(5.0));
+        geometry.vertex_normals.push(Vec3::Y);
+        geometry.vertex_uvs.push(Vec2::ZERO);
+        let triangles = triangle_set(&geometry);
+        geometry.optimize_vertex_fetch();
+
+        assert_eq!(triangle_set(&geometry), triangles);
+        assert_eq!(geometry.vertex_positions.len(), geometry.vertex_normals.len());
+        assert_eq!(geometry.vertex_positions.len(), geometry.vertex_uvs.len());
+        assert!(!geometry.vertex_positions.contains(&Vec3::splat(5.0)));
+        let mut next = 0;
+        for &index in &geometry.indices {
+            assert!(index <= next);
+            if index == next {
+                next += 1;
+            }
+        }
+        assert_eq!(next as usize, geometry.vertex_positions.len());
+    }
+
+    #[test]
+    fn simplifies_a_closed_mesh_without_flipping() {
+        let mut sphere = icosphere(1.0, 3);
+        sphere.vertex_uvs.clear();
+        sphere.vertex_normals.clear();
+        sphere.deduplicate_vertices();
+
+        for target in [600, 200, 50] {
+            let simplified = sphere.simplify(target);
+            let triangles = simplified.indices.len() / 3;
+            assert!(triangles <= target, "{} triangles for a target of {}", triangles, target);
+            assert!(triangles > 0);
+            check_indices(&simplified);
+            // Nothing faces the middle. Big triangles can end up edge on to it, give or take rounding.
+            for corners in simplified.indices.chunks_exact(3) {
+                let [a, b, c] = [corners[0], corners[1], corners[2]].map(|index| simplified.vertex_positions[index as usize]);
+                let facing = (b - a).cross(c - a).normalize().dot((a + b + c).normalize());
+                assert!(facing > -1e-5, "flipped triangle {:?} at a target of {}", corners, target);
+            }
+        }
+    }
+
+    #[test]
+    fn simplifying_keeps_open_edges_and_facing() {
+        let mut plane = grid(2.0, 2.0, 8, 8);
+        plane.vertex_normals = vec![Vec3::Y; plane.vertex_positions.len()];
+        let simplified = plane.simplify(16);
+        check_indices(&simplified);
+        assert!(simplified.indices.len() / 3 < plane.indices.len() / 3);
+        for corners in simplified.indices.chunks_exact(3) {
+            let [a, b, c] = [corners[0], corners[1], corners[2]].map(|index| simplified.vertex_positions[index as usize]);
+            assert!((b - a).cross(c - a).y > 0.0, "flipped triangle {:?}", corners);
+        }
+        // The outline is locked, its corners are all still there
+        for corner in [Vec3::new(-1.0, 0.0, -1.0), Vec3::new(1.0, 0.0, -1.0), Vec3::new(-1.0, 0.0, 1.0), Vec3::new(1.0, 0.0, 1.0)] {
+            assert!(simplified.vertex_positions.iter().any(|position| position.distance(corner) < 1e-5));
+        }
+        // Already under the target, nothing happens
+        assert_eq!(plane.simplify(1000).indices, plane.indices);
+    }
+
+    // Two quads in the XY plane sharing the edge at x = 1, the second one's texture mirrored
+    fn mirrored_quads() -> Geometry {
+        Geometry {
+            vertex_positions: vec![
+                Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(2.0, 1.0, 0.0)
+            ],
+            vertex_normals: vec![Vec3::Z; 6],
+            vertex_uvs: vec![
+                Vec2::new(0.0, 1.0), Vec2::new(1.0, 1.0), Vec2::new(0.0, 1.0),
+                Vec2::new(0.0, 0.0), Vec2::new(1.0, 0.0), Vec2::new(0.0, 0.0)
+            ],
+            indices: vec![0, 1, 4, 0, 4, 3, 1, 2, 5, 1, 5, 4],
+            ..Geometry::default()
+        }
+    }
+
+    #[test]
+    fn tangents_follow_u_and_split_mirrored_seams() {
+        let mut geometry = mirrored_quads();
+        let triangles = triangle_set(&geometry);
+        geometry.compute_tangents();
+
+        // The two vertices on the seam each become two
+        assert_eq!(geometry.vertex_positions.len(), 8);
+        assert_eq!(geometry.vertex_tangents.len(), 8);
+        assert_eq!(triangle_set(&geometry), triangles);
+        check_indices(&geometry);
+
+        let tangent_of = |triangle: usize| geometry.vertex_tangents[geometry.indices[triangle * 3] as usize];
+        for triangle in 0..4 {
+            for &index in &geometry.indices[triangle * 3..triangle * 3 + 3] {
+                let tangent = geometry.vertex_tangents[index as usize];
+                assert!((tangent.truncate().length() - 1.0).abs() < 1e-4);
+                assert!(tangent.truncate().dot(geometry.vertex_normals[index as usize]).abs() < 1e-4);
+                assert_eq!(tangent.w.abs(), 1.0);
+                assert_eq!(tangent, tangent_of(triangle));
+            }
+        }
+        // Along +u, which runs the other way across the seam, and so does the handedness
+        let (left, right) = (tangent_of(0), tangent_of(2));
+        assert!(left.truncate().distance(Vec3::X) < 1e-4);
+        assert!(right.truncate().distance(-Vec3::X) < 1e-4);
+        assert_eq!(left.w, -right.w);
+    }
+
+    #[test]
+    fn tangents_need_uvs() {
+        let mut geometry = mirrored_quads();
+        geometry.vertex_uvs.clear();
+        geometry.compute_tangents();
+        assert!(geometry.vertex_tangents.is_empty());
+        assert_eq!(geometry.vertex_positions.len(), 6);
+    }
+}
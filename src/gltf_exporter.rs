@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+use gltf::json;
+use gltf::json::accessor::{ComponentType, Type};
+use gltf::json::mesh::Semantic;
+use gltf::json::validation::Checked;
+use crate::error::RendererError;
+use crate::geometry::{Geometry, Topology};
+use crate::materials::{AlphaMode, Material, MaterialType};
+
+const UNLIT: &str = "KHR_materials_unlit";
+
+// What one scene mesh is written from, its CPU side geometry plus the mesh's material and transform
+pub(crate) struct ExportMesh<'a> {
+    pub geometry: &'a Geometry,
+    pub material: &'a Material,
+    pub transform: glam::Mat4
+}
+
+// The JSON document and the one buffer it refers to
+pub(crate) struct GltfDocument {
+    pub json: String,
+    pub bin: Vec<u8>
+}
+
+// Every mesh with vertices gets its own node, mesh and material, empty ones are left out
+// since glTF has no way to say nothing. bin_uri is where the buffer lives, None for a .glb
+// where it's the binary chunk. Fails on NaN or infinite values and indices past the
+// vertices, which would make a file nothing can read.
+pub(crate) fn write(meshes: &[ExportMesh], bin_uri: Option<&str>) -> Result<GltfDocument, RendererError> {
+    let mut root = json::Root {
+        asset: json::Asset {
+            generator: Some("trips".to_string()),
+            ..json::Asset::default()
+        },
+        ..json::Root::default()
+    };
+    let mut bin = Vec::new();
+
+    for (index, mesh) in meshes.iter().enumerate() {
+        let geometry = mesh.geometry;
+        let vertex_count = geometry.vertex_positions.len();
+        if vertex_count == 0 {
+            log::warn!("mesh {} has no vertices, it's left out of the export", index);
+            continue;
+        }
+        check_mesh(index, mesh)?;
+
+        // POSITION needs its bounds
+        let (min, max) = geometry.vertex_positions.iter().fold(
+            (glam::Vec3::splat(f32::MAX), glam::Vec3::splat(f32::MIN)),
+            |(min, max), position| (min.min(*position), max.max(*position))
+        );
+        let mut attributes = HashMap::new();
+        let positions = push_accessor(&mut root, &mut bin, bytemuck::cast_slice(&geometry.vertex_positions),
+                                      json::buffer::Target::ArrayBuffer, ComponentType::F32, vertex_count, Type::Vec3);
+        root.accessors[positions.value()].min = Some(json::Value::from(min.to_array().to_vec()));
+        root.accessors[positions.value()].max = Some(json::Value::from(max.to_array().to_vec()));
+        attributes.insert(Checked::Valid(Semantic::Positions), positions);
+
+        // Attributes that don't cover every vertex would make an invalid file, leave them out
+        let optional: [(Semantic, &[u8], usize, Type); 4] = [
+            (Semantic::Normals, bytemuck::cast_slice(&geometry.vertex_normals), geometry.vertex_normals.len(), Type::Vec3),
+            (Semantic::TexCoords(0), bytemuck::cast_slice(&geometry.vertex_uvs), geometry.vertex_uvs.len(), Type::Vec2),
+            (Semantic::Colors(0), bytemuck::cast_slice(&geometry.vertex_colors), geometry.vertex_colors.len(), Type::Vec4),
+            (Semantic::Tangents, bytemuck::cast_slice(&geometry.vertex_tangents), geometry.vertex_tangents.len(), Type::Vec4)
+        ];
+        for (semantic, bytes, count, kind) in optional.iter() {
+            if *count == vertex_count {
+                let accessor = push_accessor(&mut root, &mut bin, bytes, json::buffer::Target::ArrayBuffer, ComponentType::F32, *count, *kind);
+                attributes.insert(Checked::Valid(semantic.clone()), accessor);
+            }
+        }
+        // Without indices the vertices are drawn in order
+        let indices = if geometry.indices.is_empty() {
+            None
+        }
+        else {
+            Some(push_accessor(&mut root, &mut bin, bytemuck::cast_slice(&geometry.indices),
+                               json::buffer::Target::ElementArrayBuffer, ComponentType::U32, geometry.indices.len(), Type::Scalar))
+        };
+
+        let material = json::Index::new(root.materials.len() as u32);
+        root.materials.push(write_material(mesh.material));
+        if is_unlit(mesh.material) && !root.extensions_used.iter().any(|used| used == UNLIT) {
+            root.extensions_used.push(UNLIT.to_string());
+        }
+
+        let mode = match geometry.topology {
+            Topology::Points => json::mesh::Mode::Points,
+            Topology::Triangles => json::mesh::Mode::Triangles
+        };
+        root.meshes.push(json::Mesh {
+            extensions: None,
+            extras: Default::default(),
+            name: None,
+            primitives: vec![json::mesh::Primitive {
+                attributes,
+                extensions: None,
+                extras: Default::default(),
+                indices,
+                material: Some(material),
+                mode: Checked::Valid(mode),
+                targets: None
+            }],
+            weights: None
+        });
+        root.nodes.push(json::Node {
+            camera: None,
+            children: None,
+            extensions: None,
+            extras: Default::default(),
+            // glTF leaves the matrix out when it's the identity
+            matrix: if mesh.transform == glam::Mat4::IDENTITY { None } else { Some(mesh.transform.to_cols_array()) },
+            mesh: Some(json::Index::new(root.meshes.len() as u32 - 1)),
+            name: None,
+            rotation: None,
+            scale: None,
+            translation: None,
+            skin: None,
+            weights: None
+        });
+    }
+
+    // A scene needs at least one node, with nothing in it there's no scene at all
+    if !root.nodes.is_empty() {
+        root.scenes.push(json::Scene {
+            extensions: None,
+            extras: Default::default(),
+            name: None,
+            nodes: (0..root.nodes.len() as u32).map(json::Index::new).collect()
+        });
+        root.scene = Some(json::Index::new(0));
+    }
+    if !bin.is_empty() {
+        root.buffers.push(json::Buffer {
+            byte_length: bin.len() as u32,
+            name: None,
+            uri: bin_uri.map(str::to_string),
+            extensions: None,
+            extras: Default::default()
+        });
+    }
+
+    let json = json::serialize::to_string(&root)
+        .map_err(|err| RendererError::InvalidAsset(format!("gltf export: {}", err)))?;
+    Ok(GltfDocument { json, bin })
+}
+
+// The document packed into a .glb, the JSON chunk padded with spaces and the binary one with zeros
+pub(crate) fn to_glb(document: &GltfDocument) -> Vec<u8> {
+    let mut json = document.json.clone().into_bytes();
+    json.resize(align4(json.len()), b' ');
+    let mut bin = document.bin.clone();
+    bin.resize(align4(bin.len()), 0);
+
+    let bin_chunk_length = if bin.is_empty() { 0 } else { 8 + bin.len() };
+    let length = 12 + 8 + json.len() + bin_chunk_length;
+    let mut glb = Vec::with_capacity(length);
+    glb.extend_from_slice(b"glTF");
+    glb.extend_from_slice(&2u32.to_le_bytes());
+    glb.extend_from_slice(&(length as u32).to_le_bytes());
+
+    glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    glb.extend_from_slice(b"JSON");
+    glb.extend_from_slice(&json);
+
+    if !bin.is_empty() {
+        glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"BIN\0");
+        glb.extend_from_slice(&bin);
+    }
+
+    glb
+}
+
+// Textures aren't exported, textured materials go out as their albedo
+fn is_unlit(material: &Material) -> bool {
+    matches!(material.material_type, MaterialType::SolidColorMaterial | MaterialType::TexturedMaterial)
+}
+
+// glTF materials are metallic-roughness, unlit ones are marked so viewers don't shade them
+fn write_material(material: &Material) -> json::Material {
+    let properties = &material.render_properties;
+    // glTF only knows opaque, mask and blend, the other blend modes are closest to blend
+    let (alpha_mode, alpha_cutoff) = match material.alpha_mode {
+        AlphaMode::Opaque => (json::material::AlphaMode::Opaque, None),
+        AlphaMode::Mask { cutoff } => (json::material::AlphaMode::Mask, Some(json::material::AlphaCutoff(cutoff))),
+        AlphaMode::Blend | AlphaMode::Additive | AlphaMode::Premultiplied => (json::material::AlphaMode::Blend, None)
+    };
+    let unlit = is_unlit(material);
+    let mut extensions = json::extensions::material::Material::default();
+    extensions.unlit = Some(json::extensions::material::Unlit {});
+
+    json::Material {
+        alpha_cutoff,
+        alpha_mode: Checked::Valid(alpha_mode),
+        double_sided: material.rasterizer.cull_mode.is_none(),
+        pbr_metallic_roughness: json::material::PbrMetallicRoughness {
+            base_color_factor: json::material::PbrBaseColorFactor(properties.albedo.into()),
+            metallic_factor: json::material::StrengthFactor(properties.metallic),
+            roughness_factor: json::material::StrengthFactor(properties.roughness),
+            ..Default::default()
+        },
+        extensions: if unlit { Some(extensions) } else { None },
+        ..Default::default()
+    }
+}
+
+// Appends the data as a buffer view plus an accessor over it
+fn push_accessor(root: &mut json::Root, bin: &mut Vec<u8>, bytes: &[u8], target: json::buffer::Target,
+                 component_type: ComponentType, count: usize, kind: Type) -> json::Index<json::Accessor> {
+    // Accessors of 4 byte components have to start 4 byte aligned
+    bin.resize(align4(bin.len()), 0);
+    root.buffer_views.push(json::buffer::View {
+        buffer: json::Index::new(0),
+        byte_length: bytes.len() as u32,
+        byte_offset: Some(bin.len() as u32),
+        byte_stride: None,
+        name: None,
+        target: Some(Checked::Valid(target)),
+        extensions: None,
+        extras: Default::default()
+    });
+    bin.extend_from_slice(bytes);
+
+    root.accessors.push(json::Accessor {
+        buffer_view: Some(json::Index::new(root.buffer_views.len() as u32 - 1)),
+        byte_offset: 0,
+        count: count as u32,
+        component_type: Checked::Valid(json::accessor::GenericComponentType(component_type)),
+        extensions: None,
+        extras: Default::default(),
+        type_: Checked::Valid(kind),
+        min: None,
+        max: None,
+        name: None,
+        normalized: false,
+        sparse: None
+    });
+    json::Index::new(root.accessors.len() as u32 - 1)
+}
+
+fn check_mesh(index: usize, mesh: &ExportMesh) -> Result<(), RendererError> {
+    let geometry = mesh.geometry;
+    let invalid = |what: &str| Err(RendererError::InvalidAsset(format!("gltf export: mesh {} {}", index, what)));
+    let finite = |values: &[f32]| values.iter().all(|value| value.is_finite());
+
+    if !finite(bytemuck::cast_slice(&geometry.vertex_positions)) || !finite(bytemuck::cast_slice(&geometry.vertex_normals))
+        || !finite(bytemuck::cast_slice(&geometry.vertex_uvs)) || !finite(bytemuck::cast_slice(&geometry.vertex_colors))
+        || !finite(bytemuck::cast_slice(&geometry.vertex_tangents)) {
+        return invalid("has NaN or infinite vertex data");
+    }
+    if !finite(&mesh.transform.to_cols_array()) {
+        return invalid("has a NaN or infinite transform");
+    }
+    let properties = &mesh.material.render_properties;
+    if !finite(&properties.albedo.to_array()) || !properties.metallic.is_finite() || !properties.roughness.is_finite() {
+        return invalid("has a NaN or infinite material");
+    }
+    if geometry.indices.iter().any(|&vertex| vertex as usize >= geometry.vertex_positions.len()) {
+        return invalid("has indices past its vertices");
+    }
+
+    Ok(())
+}
+
+fn align4(length: usize) -> usize {
+    (length + 3) & !3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gltf_loader::{self, NoResolver};
+    use crate::handles::Pool;
+    use crate::materials::{MaterialBuffers, RasterizerState, RenderProperties};
+    use glam::{Mat4, Vec2, Vec3, Vec4};
+
+    fn material(material_type: MaterialType, alpha_mode: AlphaMode) -> Material {
+        let mut handles: Pool<(), MaterialBuffers> = Pool::new(0);
+        Material {
+            material_handle: handles.insert(()),
+            material_type,
+            render_properties: RenderProperties {
+                albedo: Vec4::new(0.25, 0.5, 0.75, 0.5),
+                metallic: 0.25,
+                roughness: 0.75
+            },
+            alpha_mode,
+            rasterizer: RasterizerState::double_sided()
+        }
+    }
+
+    fn triangle() -> Geometry {
+        Geometry {
+            vertex_positions: vec![Vec3::ZERO, Vec3::X, Vec3::Y],
+            vertex_normals: vec![Vec3::Z; 3],
+            vertex_uvs: vec![Vec2::ZERO, Vec2::X, Vec2::Y],
+            vertex_colors: vec![Vec4::ONE, Vec4::new(1.0, 0.0, 0.0, 1.0), Vec4::new(0.0, 1.0, 0.0, 1.0)],
+            vertex_tangents: vec![Vec4::new(1.0, 0.0, 0.0, 1.0); 3],
+            indices: vec![0, 1, 2],
+            topology: Topology::Triangles
+        }
+    }
+
+    #[test]
+    fn round_trips_every_mesh_with_its_transform() {
+        let points = Geometry {
+            vertex_positions: vec![Vec3::ONE, Vec3::new(-1.0, 2.0, 3.0)],
+            indices: vec![0, 1],
+            topology: Topology::Points,
+            ..Geometry::default()
+        };
+        let (unlit, lit) = (material(MaterialType::SolidColorMaterial, AlphaMode::Blend),
+                            material(MaterialType::PbrMaterial, AlphaMode::Mask { cutoff: 0.25 }));
+        let moved = Mat4::from_scale_rotation_translation(Vec3::splat(2.0), glam::Quat::from_rotation_y(0.5), Vec3::new(1.0, 2.0, 3.0));
+        let triangle = triangle();
+        let meshes = [
+            ExportMesh { geometry: &triangle, material: &unlit, transform: Mat4::IDENTITY },
+            ExportMesh { geometry: &points, material: &lit, transform: moved }
+        ];
+
+        let document = write(&meshes, None).unwrap();
+        let primitives = gltf_loader::read_scene(&to_glb(&document), &NoResolver).unwrap();
+        assert_eq!(primitives.len(), 2);
+        for (primitive, mesh) in primitives.iter().zip(meshes.iter()) {
+            let (read, written) = (&primitive.geometry, mesh.geometry);
+            assert_eq!(read.vertex_positions, written.vertex_positions);
+            assert_eq!(read.vertex_normals, written.vertex_normals);
+            assert_eq!(read.vertex_uvs, written.vertex_uvs);
+            assert_eq!(read.vertex_colors, written.vertex_colors);
+            assert_eq!(read.vertex_tangents, written.vertex_tangents);
+            assert_eq!(read.indices, written.indices);
+            assert_eq!(read.topology, written.topology);
+            assert!(primitive.transform.abs_diff_eq(mesh.transform, 1e-6));
+
+            let properties = primitive.render_properties.unwrap();
+            assert_eq!(properties.albedo, mesh.material.render_properties.albedo);
+            assert_eq!(properties.metallic, 0.25);
+            assert_eq!(properties.roughness, 0.75);
+            assert_eq!(primitive.rasterizer, RasterizerState::double_sided());
+        }
+        assert_eq!(primitives[0].alpha_mode, AlphaMode::Blend);
+        assert_eq!(primitives[1].alpha_mode, AlphaMode::Mask { cutoff: 0.25 });
+        assert!(document.json.contains("KHR_materials_unlit"));
+    }
+
+    #[test]
+    fn external_buffer_is_resolved_by_uri() {
+        let triangle = triangle();
+        let unlit = material(MaterialType::SolidColorMaterial, AlphaMode::Opaque);
+        let document = write(&[ExportMesh { geometry: &triangle, material: &unlit, transform: Mat4::IDENTITY }], Some("scene.bin")).unwrap();
+
+        let bin = document.bin.clone();
+        let resolver = move |uri: &str| if uri == "scene.bin" { Ok(bin.clone()) } else { Err(RendererError::InvalidAsset(uri.to_string())) };
+        let primitives = gltf_loader::read_scene(document.json.as_bytes(), &resolver).unwrap();
+        assert_eq!(primitives[0].geometry.vertex_positions, triangle.vertex_positions);
+    }
+
+    #[test]
+    fn rejects_non_finite_data_and_skips_empty_meshes() {
+        let unlit = material(MaterialType::SolidColorMaterial, AlphaMode::Opaque);
+        let mut broken = triangle();
+        broken.vertex_positions[1].x = f32::NAN;
+        assert!(write(&[ExportMesh { geometry: &broken, material: &unlit, transform: Mat4::IDENTITY }], None).is_err());
+
+        let triangle = triangle();
+        let infinite = Mat4::from_translation(Vec3::new(f32::INFINITY, 0.0, 0.0));
+        assert!(write(&[ExportMesh { geometry: &triangle, material: &unlit, transform: infinite }], None).is_err());
+
+        let mut out_of_range = triangle.clone();
+        out_of_range.indices[2] = 3;
+        assert!(write(&[ExportMesh { geometry: &out_of_range, material: &unlit, transform: Mat4::IDENTITY }], None).is_err());
+
+        // Nothing to write at all still makes a valid, empty scene
+        let empty = Geometry::default();
+        let document = write(&[ExportMesh { geometry: &empty, material: &unlit, transform: Mat4::IDENTITY }], None).unwrap();
+        assert!(document.bin.is_empty());
+        let gltf = gltf::Gltf::from_slice(document.json.as_bytes()).unwrap();
+        assert_eq!(gltf.meshes().count(), 0);
+        assert_eq!(gltf.views().count(), 0);
+    }
+}
@@ -0,0 +1,363 @@
+use std::path::{Path, PathBuf};
+use crate::error::RendererError;
+use crate::geometry::{Geometry, Topology};
+use crate::materials::{AlphaMode, RasterizerState, RenderProperties};
+
+// Fetches what a glTF file points at by URI, its external .bin buffers and images.
+// Data URIs never get here, they're decoded by the loader.
+pub trait UriResolver {
+    fn resolve(&self, uri: &str) -> Result<Vec<u8>, RendererError>;
+}
+
+impl<F: Fn(&str) -> Result<Vec<u8>, RendererError>> UriResolver for F {
+    fn resolve(&self, uri: &str) -> Result<Vec<u8>, RendererError> {
+        self(uri)
+    }
+}
+
+// Resolves relative URIs against a directory, usually the one the .gltf came from
+#[derive(Debug, Clone)]
+pub struct FileResolver {
+    base: PathBuf
+}
+
+impl FileResolver {
+    pub fn new<P: AsRef<Path>>(base: P) -> Self {
+        Self {
+            base: base.as_ref().to_path_buf()
+        }
+    }
+
+    // The file a URI points at
+    pub(crate) fn path(&self, uri: &str) -> PathBuf {
+        self.base.join(uri.strip_prefix("file://").unwrap_or(uri))
+    }
+}
+
+impl UriResolver for FileResolver {
+    fn resolve(&self, uri: &str) -> Result<Vec<u8>, RendererError> {
+        Ok(std::fs::read(self.path(uri))?)
+    }
+}
+
+// For self contained files, any external reference is an error
+pub struct NoResolver;
+
+impl UriResolver for NoResolver {
+    fn resolve(&self, uri: &str) -> Result<Vec<u8>, RendererError> {
+        Err(RendererError::InvalidAsset(format!("gltf: external uri {} with no resolver", uri)))
+    }
+}
+
+// What a glTF primitive turns into, before anything is on the GPU
+pub(crate) struct GltfPrimitive {
+    pub geometry: Geometry,
+    // None when the primitive uses glTF's default material
+    pub render_properties: Option<RenderProperties>,
+    pub alpha_mode: AlphaMode,
+    pub rasterizer: RasterizerState,
+    // Where the node holding it puts it in the scene, identity for read_first_primitive
+    pub transform: glam::Mat4
+}
+
+// First primitive of the first mesh, from .gltf JSON or .glb bytes. Nodes aren't looked at.
+pub(crate) fn read_first_primitive(bytes: &[u8], resolver: &dyn UriResolver) -> Result<GltfPrimitive, RendererError> {
+    let gltf = gltf::Gltf::from_slice(bytes)?;
+    let buffers = read_buffers(&gltf, resolver)?;
+
+    let mesh = gltf.meshes().next().ok_or_else(|| RendererError::InvalidAsset("gltf: no meshes".to_string()))?;
+    let primitive = mesh.primitives().next().ok_or_else(|| RendererError::InvalidAsset("gltf: mesh has no primitives".to_string()))?;
+    read_primitive(&primitive, &buffers, glam::Mat4::IDENTITY)
+}
+
+// Every primitive of every node in the file's scene, each with its node's transform combined
+// with its parents'. Meshes used by several nodes come back once per node. Files without a
+// scene get each mesh once, where it is.
+pub(crate) fn read_scene(bytes: &[u8], resolver: &dyn UriResolver) -> Result<Vec<GltfPrimitive>, RendererError> {
+    let gltf = gltf::Gltf::from_slice(bytes)?;
+    let buffers = read_buffers(&gltf, resolver)?;
+
+    let mut primitives = Vec::new();
+    match gltf.default_scene().or_else(|| gltf.scenes().next()) {
+        Some(scene) => {
+            // Nodes with their parent's transform, depth first so the file's order is kept
+            let mut nodes: Vec<(gltf::Node, glam::Mat4)> = scene.nodes().map(|node| (node, glam::Mat4::IDENTITY)).collect();
+            nodes.reverse();
+            let mut visited = 0;
+            while let Some((node, parent)) = nodes.pop() {
+                // A node can only be visited more often than there are nodes if children loop back
+                visited += 1;
+                if visited > gltf.nodes().len() {
+                    return Err(RendererError::InvalidAsset("gltf: node hierarchy has a cycle".to_string()));
+                }
+                let transform = parent * glam::Mat4::from_cols_array_2d(&node.transform().matrix());
+                if let Some(mesh) = node.mesh() {
+                    for primitive in mesh.primitives() {
+                        primitives.push(read_primitive(&primitive, &buffers, transform)?);
+                    }
+                }
+                let first_child = nodes.len();
+                nodes.extend(node.children().map(|child| (child, transform)));
+                nodes[first_child..].reverse();
+            }
+        },
+        None => {
+            for mesh in gltf.meshes() {
+                for primitive in mesh.primitives() {
+                    primitives.push(read_primitive(&primitive, &buffers, glam::Mat4::IDENTITY)?);
+                }
+            }
+        }
+    }
+
+    if primitives.is_empty() {
+        return Err(RendererError::InvalidAsset("gltf: no meshes".to_string()));
+    }
+    Ok(primitives)
+}
+
+fn read_primitive(primitive: &gltf::Primitive, buffers: &[Vec<u8>], transform: glam::Mat4) -> Result<GltfPrimitive, RendererError> {
+    // The reader slices the buffers without checking, anything out of range has to be caught first
+    for (_, accessor) in primitive.attributes() {
+        check_accessor(&accessor, buffers)?;
+    }
+    if let Some(accessor) = primitive.indices() {
+        check_accessor(&accessor, buffers)?;
+    }
+
+    let reader = primitive.reader(|b| Some(&buffers.get(b.index())?[..b.length()]));
+
+    let vertex_positions: Vec<_> = reader.read_positions()
+        .ok_or_else(|| RendererError::InvalidAsset("gltf: primitive has no positions".to_string()))?
+        .map(glam::Vec3::from)
+        .collect();
+    let vertex_normals: Vec<_> = reader.read_normals()
+        .map(|normals| normals.map(glam::Vec3::from).collect())
+        .unwrap_or_default();
+    let vertex_uvs: Vec<_> = reader.read_tex_coords(0)
+        .map(|uvs| uvs.into_f32().map(glam::Vec2::from).collect())
+        .unwrap_or_default();
+    let vertex_colors: Vec<_> = reader.read_colors(0)
+        .map(|colors| colors.into_rgba_f32().map(glam::Vec4::from).collect())
+        .unwrap_or_default();
+    let vertex_tangents: Vec<_> = reader.read_tangents()
+        .map(|tangents| tangents.map(glam::Vec4::from).collect())
+        .unwrap_or_default();
+    let topology = match primitive.mode() {
+        gltf::mesh::Mode::Triangles => Topology::Triangles,
+        gltf::mesh::Mode::Points => Topology::Points,
+        mode => return Err(RendererError::InvalidAsset(format!("gltf: {:?} primitives aren't supported", mode)))
+    };
+    // Unindexed primitives draw their vertices in order
+    let indices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..vertex_positions.len() as u32).collect()
+    };
+
+    let vertex_count = vertex_positions.len();
+    for (attribute, count) in [("normals", vertex_normals.len()), ("uvs", vertex_uvs.len()), ("colors", vertex_colors.len()), ("tangents", vertex_tangents.len())] {
+        if count != 0 && count != vertex_count {
+            return Err(RendererError::InvalidAsset(format!("gltf: {} {} for {} positions", count, attribute, vertex_count)));
+        }
+    }
+    if let Some(&index) = indices.iter().find(|&&index| index as usize >= vertex_count) {
+        return Err(RendererError::InvalidAsset(format!("gltf: index {} is past the {} vertices", index, vertex_count)));
+    }
+    if topology == Topology::Triangles && indices.len() % 3 != 0 {
+        return Err(RendererError::InvalidAsset(format!("gltf: {} indices isn't whole triangles", indices.len())));
+    }
+
+    Ok(GltfPrimitive {
+        geometry: Geometry {
+            vertex_positions,
+            vertex_normals,
+            vertex_uvs,
+            vertex_colors,
+            vertex_tangents,
+            indices,
+            topology
+        },
+        render_properties: primitive.material().index().map(|_| {
+            let pbr = primitive.material().pbr_metallic_roughness();
+            RenderProperties {
+                albedo: glam::Vec4::from(pbr.base_color_factor()),
+                metallic: pbr.metallic_factor(),
+                roughness: pbr.roughness_factor()
+            }
+        }),
+        alpha_mode: AlphaMode::from_gltf(&primitive.material()),
+        rasterizer: RasterizerState::from_gltf(&primitive.material()),
+        transform
+    })
+}
+
+// Whether every element the accessor covers is inside its buffer view, and the view inside its buffer
+fn check_accessor(accessor: &gltf::Accessor, buffers: &[Vec<u8>]) -> Result<(), RendererError> {
+    let invalid = |reason: &str| RendererError::InvalidAsset(format!("gltf: accessor {} {}", accessor.index(), reason));
+    if accessor.sparse().is_some() {
+        return Err(invalid("is sparse, which isn't supported"));
+    }
+    let view = accessor.view().ok_or_else(|| invalid("has no buffer view"))?;
+    if accessor.count() == 0 {
+        return Err(invalid("is empty"));
+    }
+
+    let buffer_length = buffers.get(view.buffer().index()).map(|buffer| buffer.len()).unwrap_or(0);
+    if view.offset().checked_add(view.length()).map_or(true, |end| end > buffer_length) {
+        return Err(invalid("has a buffer view past the end of its buffer"));
+    }
+    let size = accessor.size();
+    let stride = view.stride().unwrap_or(size);
+    if stride < size {
+        return Err(invalid("has a stride smaller than its elements"));
+    }
+    let end = stride.checked_mul(accessor.count() - 1)
+        .and_then(|last| last.checked_add(accessor.offset()))
+        .and_then(|last| last.checked_add(size));
+    if end.map_or(true, |end| end > view.length()) {
+        return Err(invalid("runs past the end of its buffer view"));
+    }
+
+    Ok(())
+}
+
+fn read_buffers(gltf: &gltf::Gltf, resolver: &dyn UriResolver) -> Result<Vec<Vec<u8>>, RendererError> {
+    let mut buffers = Vec::new();
+    for buffer in gltf.buffers() {
+        let mut data = match buffer.source() {
+            gltf::buffer::Source::Bin => gltf.blob.clone()
+                .ok_or_else(|| RendererError::InvalidAsset("gltf: missing binary chunk".to_string()))?,
+            gltf::buffer::Source::Uri(uri) => match decode_data_uri(uri)? {
+                Some(data) => data,
+                None => resolver.resolve(uri)?
+            }
+        };
+        if data.len() < buffer.length() {
+            return Err(RendererError::InvalidAsset(format!("gltf: buffer {} is shorter than its declared length", buffer.index())));
+        }
+        // The binary chunk is padded to 4 bytes
+        data.truncate(buffer.length());
+        buffers.push(data);
+    }
+
+    Ok(buffers)
+}
+
+// None when it isn't a data URI
+fn decode_data_uri(uri: &str) -> Result<Option<Vec<u8>>, RendererError> {
+    let data = match uri.strip_prefix("data:") {
+        Some(data) => data,
+        None => return Ok(None)
+    };
+
+    match data.split_once(";base64,") {
+        Some((_, encoded)) => base64::decode(encoded)
+            .map(Some)
+            .map_err(|err| RendererError::InvalidAsset(format!("gltf: bad base64 data uri: {}", err))),
+        None => Err(RendererError::InvalidAsset("gltf: only base64 data uris are supported".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOX: &str = include_str!("../res/Box.gltf");
+    const BOX_BIN: &[u8] = include_bytes!("../res/Box0.bin");
+
+    // Box.gltf with its buffer entry swapped for the given one
+    fn with_buffer(buffer: &str) -> String {
+        let start = BOX.find("\"buffers\"").unwrap();
+        let end = start + BOX[start..].find(']').unwrap() + 1;
+        format!("{}\"buffers\": [{}]{}", &BOX[..start], buffer, &BOX[end..])
+    }
+
+    fn glb(json: &str, bin: &[u8]) -> Vec<u8> {
+        let mut json = json.as_bytes().to_vec();
+        json.resize(json.len().div_ceil(4) * 4, b' ');
+        let mut bin = bin.to_vec();
+        bin.resize(bin.len().div_ceil(4) * 4, 0);
+
+        let mut bytes = b"glTF".to_vec();
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        bytes.extend_from_slice(&(12 + 8 + json.len() as u32 + 8 + bin.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(b"JSON");
+        bytes.extend_from_slice(&json);
+        bytes.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(b"BIN\0");
+        bytes.extend_from_slice(&bin);
+        bytes
+    }
+
+    fn check_box(primitive: &GltfPrimitive) {
+        let geometry = &primitive.geometry;
+        assert_eq!(geometry.vertex_positions.len(), 24);
+        assert_eq!(geometry.vertex_normals.len(), 24);
+        assert_eq!(geometry.indices.len(), 36);
+        assert!(geometry.vertex_positions.iter().all(|position| position.abs().max_element() == 0.5));
+        let properties = primitive.render_properties.unwrap();
+        assert_eq!(properties.albedo, glam::Vec4::new(0.8, 0.0, 0.0, 1.0));
+        assert_eq!(properties.metallic, 0.0);
+    }
+
+    #[test]
+    fn reads_external_buffers_through_the_resolver() {
+        let resolved = std::cell::RefCell::new(Vec::new());
+        let resolver = |uri: &str| {
+            resolved.borrow_mut().push(uri.to_string());
+            Ok(BOX_BIN.to_vec())
+        };
+        check_box(&read_first_primitive(BOX.as_bytes(), &resolver).unwrap());
+        assert_eq!(*resolved.borrow(), vec!["Box0.bin".to_string()]);
+
+        // And from disk, next to the file
+        let resolver = FileResolver::new(concat!(env!("CARGO_MANIFEST_DIR"), "/res"));
+        check_box(&read_first_primitive(BOX.as_bytes(), &resolver).unwrap());
+        assert!(read_first_primitive(BOX.as_bytes(), &NoResolver).is_err());
+    }
+
+    #[test]
+    fn reads_base64_data_uris() {
+        let json = with_buffer(&format!("{{\"byteLength\": 648, \"uri\": \"data:application/octet-stream;base64,{}\"}}", base64::encode(BOX_BIN)));
+        check_box(&read_first_primitive(json.as_bytes(), &NoResolver).unwrap());
+
+        let broken = with_buffer("{\"byteLength\": 648, \"uri\": \"data:application/octet-stream;base64,not base64!\"}");
+        assert!(read_first_primitive(broken.as_bytes(), &NoResolver).is_err());
+    }
+
+    #[test]
+    fn reads_glb() {
+        let bytes = glb(&with_buffer("{\"byteLength\": 648}"), BOX_BIN);
+        check_box(&read_first_primitive(&bytes, &NoResolver).unwrap());
+
+        // The scene's root node turns Y up into Z up
+        let primitives = read_scene(&bytes, &NoResolver).unwrap();
+        assert_eq!(primitives.len(), 1);
+        let up = primitives[0].transform.transform_vector3(glam::Vec3::Y);
+        assert!(up.distance(glam::Vec3::Z) < 1e-6);
+    }
+
+    #[test]
+    fn rejects_accessors_outside_their_data() {
+        let resolver = |_: &str| Ok(BOX_BIN.to_vec());
+
+        // An index past the 24 vertices, the first index is the first two bytes of the index view
+        let mut bin = BOX_BIN.to_vec();
+        bin[576..578].copy_from_slice(&99u16.to_le_bytes());
+        let out_of_range = move |_: &str| Ok(bin.clone());
+        assert!(read_first_primitive(BOX.as_bytes(), &out_of_range).is_err());
+
+        // More normals than the buffer view holds
+        let too_many = BOX.replacen("\"count\": 24", "\"count\": 2400", 1);
+        assert!(read_first_primitive(too_many.as_bytes(), &resolver).is_err());
+
+        // Normals and positions that don't match up
+        let fewer_normals = BOX.replacen("\"count\": 24", "\"count\": 23", 1);
+        assert!(read_first_primitive(fewer_normals.as_bytes(), &resolver).is_err());
+
+        // A buffer shorter than it says it is
+        let short = |_: &str| Ok(BOX_BIN[..600].to_vec());
+        assert!(read_first_primitive(BOX.as_bytes(), &short).is_err());
+    }
+}
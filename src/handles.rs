@@ -0,0 +1,185 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use crate::arena::{Arena, Index};
+use crate::RendererError;
+
+static NEXT_OWNER_ID: AtomicU32 = AtomicU32::new(1);
+
+// Every renderer (and scene) stamps its handles with its own id so a handle
+// can't silently index into somebody else's storage.
+pub(crate) fn next_owner_id() -> u32 {
+    NEXT_OWNER_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+static NEXT_RESOURCE_ID: AtomicU64 = AtomicU64::new(1);
+
+// CPU side images, LUTs and shaders get a fresh id whenever one is built, that's how
+// whatever uploads them notices a replaced one and uploads it again.
+pub(crate) fn next_resource_id() -> u64 {
+    NEXT_RESOURCE_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+// Typed generational handle: arena index + generation + id of the owner that made it.
+pub struct Handle<T> {
+    index: Index,
+    owner: u32,
+    phantom: PhantomData<fn() -> T>
+}
+
+impl<T> Handle<T> {
+    pub(crate) fn slot(&self) -> usize {
+        self.index.slot()
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.owner == other.owner
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.owner.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Handle")
+            .field("index", &self.index)
+            .field("owner", &self.owner)
+            .finish()
+    }
+}
+
+// Arena that hands out Handle<M> and refuses handles it didn't create.
+// M is the public face of the handle, T what's actually stored.
+pub(crate) struct Pool<T, M = T> {
+    owner: u32,
+    arena: Arena<T>,
+    phantom: PhantomData<fn() -> M>
+}
+
+impl<T, M> Pool<T, M> {
+    pub fn new(owner: u32) -> Self {
+        Self {
+            owner,
+            arena: Arena::new(),
+            phantom: PhantomData
+        }
+    }
+
+    pub fn insert(&mut self, value: T) -> Handle<M> {
+        Handle {
+            index: self.arena.insert(value),
+            owner: self.owner,
+            phantom: PhantomData
+        }
+    }
+
+    pub fn remove(&mut self, handle: Handle<M>) -> Result<T, RendererError> {
+        self.check_owner(&handle)?;
+        self.arena.remove(handle.index).ok_or(RendererError::StaleHandle)
+    }
+
+    pub fn get(&self, handle: Handle<M>) -> Result<&T, RendererError> {
+        self.check_owner(&handle)?;
+        self.arena.get(handle.index).ok_or(RendererError::StaleHandle)
+    }
+
+    pub fn get_mut(&mut self, handle: Handle<M>) -> Result<&mut T, RendererError> {
+        self.check_owner(&handle)?;
+        self.arena.get_mut(handle.index).ok_or(RendererError::StaleHandle)
+    }
+
+    pub fn len(&self) -> usize {
+        self.arena.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.arena.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.arena.capacity()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Handle<M>, &T)> {
+        let owner = self.owner;
+        self.arena.iter().map(move |(index, value)| (Handle { index, owner, phantom: PhantomData }, value))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Handle<M>, &mut T)> {
+        let owner = self.owner;
+        self.arena.iter_mut().map(move |(index, value)| (Handle { index, owner, phantom: PhantomData }, value))
+    }
+
+    fn check_owner(&self, handle: &Handle<M>) -> Result<(), RendererError> {
+        if handle.owner == self.owner {
+            Ok(())
+        }
+        else {
+            Err(RendererError::ForeignHandle)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removed_handles_are_stale_even_once_their_slot_is_reused() {
+        let mut pool: Pool<&str> = Pool::new(next_owner_id());
+        let first = pool.insert("first");
+        assert_eq!(pool.remove(first).unwrap(), "first");
+        assert!(matches!(pool.get(first), Err(RendererError::StaleHandle)));
+        assert!(matches!(pool.remove(first), Err(RendererError::StaleHandle)));
+
+        let second = pool.insert("second");
+        assert_eq!(second.slot(), first.slot());
+        assert_ne!(second, first);
+        assert!(matches!(pool.get(first), Err(RendererError::StaleHandle)));
+        assert!(matches!(pool.get_mut(first), Err(RendererError::StaleHandle)));
+        assert_eq!(*pool.get(second).unwrap(), "second");
+    }
+
+    #[test]
+    fn handles_only_work_with_the_pool_that_made_them() {
+        let mut renderer: Pool<u32> = Pool::new(next_owner_id());
+        let mut other: Pool<u32> = Pool::new(next_owner_id());
+        let ours = renderer.insert(1);
+        let theirs = other.insert(2);
+
+        // Same slot and generation, different owner
+        assert_eq!(ours.slot(), theirs.slot());
+        assert_ne!(ours, theirs);
+        assert!(matches!(renderer.get(theirs), Err(RendererError::ForeignHandle)));
+        assert!(matches!(renderer.get_mut(theirs), Err(RendererError::ForeignHandle)));
+        assert!(matches!(renderer.remove(theirs), Err(RendererError::ForeignHandle)));
+        // Refusing it didn't touch what's actually in the slot
+        assert_eq!(*renderer.get(ours).unwrap(), 1);
+        assert_eq!(*other.get(theirs).unwrap(), 2);
+    }
+
+    #[test]
+    fn owner_ids_are_unique() {
+        let a = next_owner_id();
+        let b = next_owner_id();
+        assert_ne!(a, b);
+    }
+}
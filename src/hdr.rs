@@ -0,0 +1,186 @@
+use crate::error::RendererError;
+
+// 16k x 8k, the biggest panoramas around. Anything claiming more is refused before a
+// single pixel is allocated. Adapters that can't take one that big get it scaled down on upload.
+const MAX_PIXELS: u64 = 1 << 27;
+// Run length encoded runs cover at most 127 pixels of a channel in 2 bytes
+const MAX_RUN: u64 = 127;
+
+// Radiance .hdr (RGBE) reader. Handles the usual -Y H +X W orientation with either flat
+// or run length encoded scanlines, which is what every tool writes these days.
+pub(crate) fn decode(bytes: &[u8]) -> Result<(u32, u32, Vec<[f32; 4]>), RendererError> {
+    let invalid = |reason: &str| RendererError::InvalidImage(format!("hdr: {}", reason));
+
+    let mut position = 0;
+    let next_line = |position: &mut usize| -> Option<String> {
+        let start = *position;
+        let end = bytes[start..].iter().position(|b| *b == b'\n')? + start;
+        *position = end + 1;
+        Some(String::from_utf8_lossy(&bytes[start..end]).trim_end().to_string())
+    };
+
+    let magic = next_line(&mut position).ok_or_else(|| invalid("empty file"))?;
+    if !magic.starts_with("#?") {
+        return Err(invalid("missing #? header"));
+    }
+
+    // Header variables, up to the blank line
+    loop {
+        let line = next_line(&mut position).ok_or_else(|| invalid("header never ends"))?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(invalid("only 32-bit_rle_rgbe is supported"));
+            }
+        }
+    }
+
+    let resolution = next_line(&mut position).ok_or_else(|| invalid("missing resolution"))?;
+    let parts: Vec<&str> = resolution.split_whitespace().collect();
+    let (height, width) = match parts.as_slice() {
+        ["-Y", height, "+X", width] => (
+            height.parse::<u32>().map_err(|_| invalid("bad height"))?,
+            width.parse::<u32>().map_err(|_| invalid("bad width"))?
+        ),
+        _ => return Err(invalid("only -Y H +X W orientation is supported"))
+    };
+    if width == 0 || height == 0 {
+        return Err(invalid("empty image"));
+    }
+    let pixel_count = (width as u64).checked_mul(height as u64).filter(|&count| count <= MAX_PIXELS)
+        .ok_or_else(|| invalid("image is too big"))?;
+
+    // The smallest each scanline could be, so a tiny file can't ask for a huge image
+    let columns = width as u64;
+    let smallest_scanline = if (8..32768).contains(&columns) { 4 + 4 * 2 * ((columns + MAX_RUN - 1) / MAX_RUN) } else { 4 * columns };
+    if ((bytes.len() - position) as u64) < smallest_scanline * height as u64 {
+        return Err(invalid("truncated pixel data"));
+    }
+
+    let mut data = bytes[position..].iter().copied();
+    let mut pixels = Vec::with_capacity(pixel_count as usize);
+    let mut scanline = vec![[0u8; 4]; width as usize];
+    for _ in 0..height {
+        read_scanline(&mut data, &mut scanline).ok_or_else(|| invalid("truncated pixel data"))?;
+        pixels.extend(scanline.iter().map(rgbe_to_float));
+    }
+
+    Ok((width, height, pixels))
+}
+
+fn read_scanline(data: &mut impl Iterator<Item = u8>, scanline: &mut [[u8; 4]]) -> Option<()> {
+    let width = scanline.len();
+    let first = [data.next()?, data.next()?, data.next()?, data.next()?];
+
+    // Run length encoded scanlines start with 2 2 and the width. Anything else is flat pixels.
+    let encoded = (8..32768).contains(&width) && first[0] == 2 && first[1] == 2 && first[2] & 0x80 == 0;
+    if !encoded {
+        scanline[0] = first;
+        for pixel in scanline.iter_mut().skip(1) {
+            *pixel = [data.next()?, data.next()?, data.next()?, data.next()?];
+        }
+        return Some(());
+    }
+    if ((first[2] as usize) << 8 | first[3] as usize) != width {
+        return None;
+    }
+
+    // Each channel is stored on its own, as runs and literal spans
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = data.next()? as usize;
+            if count > 128 {
+                let count = count - 128;
+                let value = data.next()?;
+                for pixel in scanline.get_mut(x..x + count)? {
+                    pixel[channel] = value;
+                }
+                x += count;
+            }
+            else {
+                if count == 0 {
+                    return None;
+                }
+                for pixel in scanline.get_mut(x..x + count)? {
+                    pixel[channel] = data.next()?;
+                }
+                x += count;
+            }
+        }
+    }
+
+    Some(())
+}
+
+fn rgbe_to_float(rgbe: &[u8; 4]) -> [f32; 4] {
+    if rgbe[3] == 0 {
+        return [0.0, 0.0, 0.0, 1.0];
+    }
+
+    let scale = 2f32.powi(rgbe[3] as i32 - (128 + 8));
+    [rgbe[0] as f32 * scale, rgbe[1] as f32 * scale, rgbe[2] as f32 * scale, 1.0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(width: u32, height: u32) -> Vec<u8> {
+        format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", height, width).into_bytes()
+    }
+
+    // 1.0 is mantissa 128 with exponent 129, 0.5 is 128 with 128
+    const ONE: [u8; 4] = [128, 128, 128, 129];
+    const HALF: [u8; 4] = [128, 128, 128, 128];
+
+    #[test]
+    fn reads_flat_scanlines() {
+        let mut bytes = header(2, 2);
+        for pixel in [ONE, HALF, [0, 0, 0, 0], ONE] {
+            bytes.extend_from_slice(&pixel);
+        }
+
+        let (width, height, pixels) = decode(&bytes).unwrap();
+        assert_eq!((width, height), (2, 2));
+        assert_eq!(pixels, vec![[1.0, 1.0, 1.0, 1.0], [0.5, 0.5, 0.5, 1.0], [0.0, 0.0, 0.0, 1.0], [1.0, 1.0, 1.0, 1.0]]);
+    }
+
+    #[test]
+    fn reads_run_length_encoded_scanlines() {
+        // 8 pixels, the first half 1.0 and the rest 0.5
+        let mut bytes = header(8, 1);
+        bytes.extend_from_slice(&[2, 2, 0, 8]);
+        // Mantissas as one run of 8 each
+        for _ in 0..3 {
+            bytes.extend_from_slice(&[128 + 8, 128]);
+        }
+        // Exponents as a literal span of 4 and a run of 4
+        bytes.extend_from_slice(&[4, 129, 129, 129, 129, 128 + 4, 128]);
+
+        let (width, height, pixels) = decode(&bytes).unwrap();
+        assert_eq!((width, height), (8, 1));
+        assert_eq!(&pixels[..4], &[[1.0, 1.0, 1.0, 1.0]; 4]);
+        assert_eq!(&pixels[4..], &[[0.5, 0.5, 0.5, 1.0]; 4]);
+    }
+
+    #[test]
+    fn rejects_truncated_and_oversized_images() {
+        let mut truncated = header(2, 2);
+        truncated.extend_from_slice(&ONE);
+        assert!(decode(&truncated).is_err());
+
+        // A run that goes past the end of the scanline
+        let mut overrun = header(8, 1);
+        overrun.extend_from_slice(&[2, 2, 0, 8, 128 + 9, 128, 0, 0, 0, 0, 0, 0]);
+        assert!(decode(&overrun).is_err());
+
+        // Would overflow u32 and ask for terabytes
+        assert!(decode(&header(65536, 65536)).is_err());
+        assert!(decode(&header(u32::MAX, u32::MAX)).is_err());
+        // Small enough to allocate but nowhere near enough data for it
+        assert!(decode(&header(8192, 4096)).is_err());
+    }
+}
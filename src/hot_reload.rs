@@ -0,0 +1,478 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime};
+use crate::asset_loader::AssetLoader;
+use crate::background::{Background, EnvironmentImage};
+use crate::capture::Image;
+use crate::error::RendererError;
+use crate::geometry::{Geometry, GeometryHandle};
+use crate::gltf_loader::{self, FileResolver, UriResolver};
+use crate::materials::MaterialHandle;
+use crate::scene::Scene;
+use crate::{obj_loader, ply_loader, stl_loader};
+
+// Checking every file's timestamp every frame is wasted work, nobody saves that fast
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+// A loaded geometry and, when it was drawn with a TexturedMaterial, the material whose
+// texture gets swapped on a reload
+pub(crate) type WatchedMesh = (GeometryHandle, Option<MaterialHandle>);
+
+// One load of a file. The same file can be loaded by several loaders, each gets its own.
+struct Watcher {
+    // Which loader read it, the same names the asset cache uses
+    loader: &'static str,
+    // In the order the loader returned them
+    meshes: Vec<WatchedMesh>
+}
+
+struct WatchedFile {
+    watchers: Vec<Watcher>,
+    modified: Option<SystemTime>,
+    // Buffers, mtllibs and textures it pulled in, a change to any of them reloads it
+    dependencies: HashMap<PathBuf, Option<SystemTime>>,
+    // Being read on a worker, it isn't checked again until that's back
+    reloading: bool
+}
+
+impl WatchedFile {
+    // Whether it or anything it pulls in changed, remembering the new timestamps
+    fn take_change(&mut self, path: &Path) -> bool {
+        let mut changed = take_change(path, &mut self.modified);
+        for (dependency, modified) in self.dependencies.iter_mut() {
+            changed |= take_change(dependency, modified);
+        }
+        changed
+    }
+
+    fn add_dependencies(&mut self, dependencies: Vec<PathBuf>) {
+        for dependency in dependencies {
+            self.dependencies.entry(dependency).or_insert_with_key(|path| modified(path));
+        }
+    }
+}
+
+// The newest version of a .hdr panorama, swapped into scenes still drawing an older one
+struct WatchedImage {
+    modified: Option<SystemTime>,
+    latest: Option<EnvironmentImage>,
+    reloading: bool
+}
+
+// One mesh of a reloaded file
+pub(crate) struct ReloadedMesh {
+    pub geometry: Geometry,
+    // Only for OBJ materials with a map_Kd that could be read
+    pub texture: Option<Image>
+}
+
+// A watched file that changed, with the new meshes for each of its handles or why it
+// couldn't be read
+pub(crate) struct Reloaded {
+    pub path: PathBuf,
+    pub meshes: Vec<WatchedMesh>,
+    pub result: Result<Vec<ReloadedMesh>, RendererError>
+}
+
+// What the workers send back
+enum Finished {
+    File {
+        path: PathBuf,
+        reloaded: Vec<Reloaded>,
+        dependencies: Vec<PathBuf>
+    },
+    Image {
+        path: PathBuf,
+        result: Result<EnvironmentImage, RendererError>
+    }
+}
+
+// Reads external files next to the model like FileResolver, remembering which ones so
+// they're watched along with it
+pub(crate) struct DependencyResolver {
+    files: FileResolver,
+    dependencies: RefCell<Vec<PathBuf>>
+}
+
+impl DependencyResolver {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            files: FileResolver::new(path.parent().unwrap_or_else(|| Path::new(""))),
+            dependencies: RefCell::new(Vec::new())
+        }
+    }
+
+    pub fn into_dependencies(self) -> Vec<PathBuf> {
+        self.dependencies.into_inner()
+    }
+}
+
+impl UriResolver for DependencyResolver {
+    fn resolve(&self, uri: &str) -> Result<Vec<u8>, RendererError> {
+        // Watched even when it can't be read, it's picked up if it turns up later
+        let path = self.files.path(uri);
+        self.dependencies.borrow_mut().push(std::fs::canonicalize(&path).unwrap_or(path));
+        self.files.resolve(uri)
+    }
+}
+
+// Files loaded from disk, what they pulled in and the geometry they became. Everything
+// loaded is tracked, the timestamps are only looked at while it's enabled. Changed files are
+// read again on the asset loader's workers, poll hands back what they read. Panoramas from
+// EnvironmentImage::load_hdr are picked up the first time a scene using them is drawn with
+// reloading on.
+pub(crate) struct HotReload {
+    pub enabled: bool,
+    files: HashMap<PathBuf, WatchedFile>,
+    images: HashMap<PathBuf, WatchedImage>,
+    last_check: Option<Instant>,
+    finished_sender: mpsc::Sender<Finished>,
+    finished: mpsc::Receiver<Finished>
+}
+
+impl HotReload {
+    pub fn new() -> Self {
+        let (finished_sender, finished) = mpsc::channel();
+        Self {
+            enabled: false,
+            files: HashMap::new(),
+            images: HashMap::new(),
+            last_check: None,
+            finished_sender,
+            finished
+        }
+    }
+
+    pub fn watch(&mut self, path: PathBuf, loader: &'static str, meshes: Vec<WatchedMesh>, dependencies: Vec<PathBuf>) {
+        let file = self.files.entry(path).or_insert_with_key(|path| WatchedFile {
+            watchers: Vec::new(),
+            modified: modified(path),
+            dependencies: HashMap::new(),
+            reloading: false
+        });
+        file.watchers.push(Watcher { loader, meshes });
+        file.add_dependencies(dependencies);
+    }
+
+    // For background loads, which only know what they pulled in once they're done
+    pub fn add_dependencies(&mut self, geometry: GeometryHandle, dependencies: Vec<PathBuf>) {
+        let watching = self.files.values_mut()
+            .find(|file| file.watchers.iter().any(|watcher| watcher.meshes.iter().any(|(watched, _)| *watched == geometry)));
+        if let Some(file) = watching {
+            file.add_dependencies(dependencies);
+        }
+    }
+
+    // Reloads the workers have finished since the last call. Files that changed since they
+    // were loaded or last reloaded are sent off to be read again by every loader that read
+    // them, changed panoramas to be decoded and kept for refresh_images.
+    pub fn poll(&mut self, loader: &mut AssetLoader) -> Vec<Reloaded> {
+        let mut reloaded = Vec::new();
+        while let Ok(finished) = self.finished.try_recv() {
+            match finished {
+                Finished::File { path, reloaded: results, dependencies } => {
+                    // Unloaded while it was being read
+                    let file = match self.files.get_mut(&path) {
+                        Some(file) => file,
+                        None => continue
+                    };
+                    file.reloading = false;
+                    file.dependencies.retain(|dependency, _| dependencies.contains(dependency));
+                    file.add_dependencies(dependencies);
+                    reloaded.extend(results);
+                },
+                Finished::Image { path, result } => {
+                    let image = match self.images.get_mut(&path) {
+                        Some(image) => image,
+                        None => continue
+                    };
+                    image.reloading = false;
+                    match result {
+                        Ok(latest) => {
+                            image.latest = Some(latest);
+                            log::info!("reloaded {}", path.display());
+                        },
+                        Err(err) => log::error!("couldn't reload {}, keeping the old version: {}", path.display(), err)
+                    }
+                }
+            }
+        }
+
+        let now = Instant::now();
+        if !self.enabled || self.last_check.map_or(false, |last_check| now.duration_since(last_check) < CHECK_INTERVAL) {
+            return reloaded;
+        }
+        self.last_check = Some(now);
+
+        for (path, file) in self.files.iter_mut() {
+            if file.reloading || !file.take_change(path) {
+                continue;
+            }
+            file.reloading = true;
+
+            let watchers: Vec<_> = file.watchers.iter().map(|watcher| (watcher.loader, watcher.meshes.clone())).collect();
+            let (path, finished) = (path.clone(), self.finished_sender.clone());
+            loader.run(Box::new(move || {
+                let resolver = DependencyResolver::new(&path);
+                let reloaded = watchers.into_iter().map(|(loader, meshes)| {
+                    let result = read(&path, loader, &resolver).and_then(|read| {
+                        if read.len() == meshes.len() {
+                            Ok(read)
+                        }
+                        else {
+                            Err(RendererError::InvalidAsset(format!("{} has {} meshes now, it had {} when it was loaded",
+                                path.display(), read.len(), meshes.len())))
+                        }
+                    });
+                    Reloaded { path: path.clone(), meshes, result }
+                }).collect();
+                // Only fails once the renderer is gone
+                let _ = finished.send(Finished::File { dependencies: resolver.into_dependencies(), path, reloaded });
+            }));
+        }
+
+        for (path, image) in self.images.iter_mut() {
+            if image.reloading || !take_change(path, &mut image.modified) {
+                continue;
+            }
+            image.reloading = true;
+
+            let (path, finished) = (path.clone(), self.finished_sender.clone());
+            loader.run(Box::new(move || {
+                let result = EnvironmentImage::load_hdr(&path);
+                let _ = finished.send(Finished::Image { path, result });
+            }));
+        }
+
+        reloaded
+    }
+
+    // Swaps the newest version of each watched panorama into the scene's environment and
+    // background. Panoramas seen for the first time start being watched.
+    pub fn refresh_images(&mut self, scene: &mut Scene) {
+        if !self.enabled {
+            return;
+        }
+
+        if let Some(environment) = scene.environment.as_mut() {
+            self.refresh_image(environment.image_mut());
+        }
+        if let Background::Equirectangular(image) = &mut scene.background {
+            self.refresh_image(image);
+        }
+    }
+
+    fn refresh_image(&mut self, image: &mut EnvironmentImage) {
+        let path = match image.source() {
+            Some(path) => path.to_path_buf(),
+            None => return
+        };
+        let watched = self.images.entry(path).or_insert_with_key(|path| WatchedImage {
+            modified: modified(path),
+            latest: None,
+            reloading: false
+        });
+        // Ids only go up, so a panorama loaded after the last reload is left alone
+        if let Some(latest) = &watched.latest {
+            if latest.id() > image.id() {
+                *image = latest.clone();
+            }
+        }
+    }
+
+    // Files stop being watched once all their geometry is unloaded
+    pub fn forget(&mut self, geometry: GeometryHandle) {
+        for file in self.files.values_mut() {
+            for watcher in file.watchers.iter_mut() {
+                watcher.meshes.retain(|(watched, _)| *watched != geometry);
+            }
+            file.watchers.retain(|watcher| !watcher.meshes.is_empty());
+        }
+        self.files.retain(|_, file| !file.watchers.is_empty());
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// Whether the file's timestamp moved since last time, remembering the new one. Taken even if
+// the read that follows fails, so a broken file is only reported once per save. Deleted files
+// keep what was loaded and are picked up again if they come back.
+fn take_change(path: &Path, last: &mut Option<SystemTime>) -> bool {
+    let modified = modified(path);
+    if modified.is_none() || modified == *last {
+        return false;
+    }
+    *last = modified;
+    true
+}
+
+// Runs on a worker. The geometry, and the texture for OBJ materials that have one, the rest
+// of the material is left as the user has it now.
+fn read(path: &Path, loader: &'static str, resolver: &dyn UriResolver) -> Result<Vec<ReloadedMesh>, RendererError> {
+    let bytes = std::fs::read(path)?;
+    let untextured = |geometry| ReloadedMesh { geometry, texture: None };
+    // Background loads went by the extension
+    let loader = match loader {
+        "background" => match path.extension().and_then(|extension| extension.to_str()).unwrap_or("").to_ascii_lowercase().as_str() {
+            "stl" => "stl",
+            "ply" => "ply",
+            // Merged and untextured, like the load was
+            "obj" => return Ok(vec![untextured(obj_loader::read_merged(&bytes, resolver)?.geometry)]),
+            _ => "gltf"
+        },
+        loader => loader
+    };
+
+    match loader {
+        "gltf" => Ok(vec![untextured(gltf_loader::read_first_primitive(&bytes, resolver)?.geometry)]),
+        "gltf_scene" => Ok(gltf_loader::read_scene(&bytes, resolver)?.into_iter().map(|primitive| untextured(primitive.geometry)).collect()),
+        "obj" => Ok(obj_loader::read(&bytes, resolver)?.into_iter().map(|primitive| {
+            // A texture that can't be read keeps the old one
+            let texture = primitive.material.and_then(|material| material.diffuse_texture).and_then(|name| {
+                resolver.resolve(&name).and_then(|bytes| Image::from_png(&bytes))
+                    .map_err(|err| log::warn!("obj diffuse texture {} isn't reloaded: {}", name, err))
+                    .ok()
+            });
+            ReloadedMesh { geometry: primitive.geometry, texture }
+        }).collect()),
+        "stl" => Ok(vec![untextured(stl_loader::read(&bytes)?)]),
+        "ply" => Ok(vec![untextured(ply_loader::read(&bytes)?)]),
+        _ => Err(RendererError::InvalidAsset(format!("{} was loaded by {}, which can't reload it", path.display(), loader)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::GeometryEntry;
+    use crate::handles::Pool;
+
+    const TRIANGLE: &str = "mtllib triangle.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3\n";
+    const MATERIALS: &str = "newmtl red\nKd 1 0 0\n";
+
+    // A fresh directory with a one triangle OBJ and its mtllib
+    fn model(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("trips-hot-reload-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("triangle.obj"), TRIANGLE).unwrap();
+        std::fs::write(directory.join("triangle.mtl"), MATERIALS).unwrap();
+        std::fs::canonicalize(directory.join("triangle.obj")).unwrap()
+    }
+
+    fn handles(count: usize) -> Vec<WatchedMesh> {
+        let mut geometries: Pool<(), GeometryEntry> = Pool::new(0);
+        (0..count).map(|_| (geometries.insert(()), None)).collect()
+    }
+
+    fn enabled() -> HotReload {
+        let mut hot_reload = HotReload::new();
+        hot_reload.enabled = true;
+        hot_reload
+    }
+
+    // Makes the next check see the file as saved
+    fn touch(hot_reload: &mut HotReload, path: &Path) {
+        hot_reload.files.get_mut(path).unwrap().modified = Some(SystemTime::UNIX_EPOCH);
+    }
+
+    // Reloads run on the workers, so this waits for them
+    fn wait_for_reload(hot_reload: &mut HotReload, loader: &mut AssetLoader) -> Vec<Reloaded> {
+        for _ in 0..500 {
+            let reloaded = hot_reload.poll(loader);
+            if !reloaded.is_empty() {
+                return reloaded;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("nothing was reloaded");
+    }
+
+    #[test]
+    fn changes_are_only_taken_once() {
+        let path = model("take_change");
+        let mut last = None;
+        assert!(take_change(&path, &mut last));
+        assert!(last.is_some());
+        assert!(!take_change(&path, &mut last));
+
+        last = Some(SystemTime::UNIX_EPOCH);
+        assert!(take_change(&path, &mut last));
+
+        // A deleted file isn't a change, what was loaded stays
+        let missing = path.with_file_name("missing.obj");
+        let mut last = Some(SystemTime::UNIX_EPOCH);
+        assert!(!take_change(&missing, &mut last));
+        assert_eq!(last, Some(SystemTime::UNIX_EPOCH));
+    }
+
+    #[test]
+    fn files_are_forgotten_with_their_last_geometry() {
+        let path = model("forget");
+        let meshes = handles(3);
+        let mut hot_reload = HotReload::new();
+        hot_reload.watch(path.clone(), "obj", meshes[..2].to_vec(), Vec::new());
+        hot_reload.watch(path.clone(), "gltf", meshes[2..].to_vec(), Vec::new());
+
+        hot_reload.forget(meshes[2].0);
+        assert_eq!(hot_reload.files[&path].watchers.len(), 1);
+        hot_reload.forget(meshes[0].0);
+        assert_eq!(hot_reload.files[&path].watchers[0].meshes, vec![meshes[1]]);
+        hot_reload.forget(meshes[1].0);
+        assert!(hot_reload.files.is_empty());
+    }
+
+    #[test]
+    fn a_different_mesh_count_is_an_error() {
+        let path = model("mesh_count");
+        let mut hot_reload = enabled();
+        let mut loader = AssetLoader::new();
+        hot_reload.watch(path.clone(), "obj", handles(2), Vec::new());
+        touch(&mut hot_reload, &path);
+
+        let reloaded = wait_for_reload(&mut hot_reload, &mut loader);
+        assert_eq!(reloaded.len(), 1);
+        match &reloaded[0].result {
+            Err(RendererError::InvalidAsset(reason)) => assert!(reason.contains("has 1 meshes now, it had 2"), "{}", reason),
+            _ => panic!("a reload with a different mesh count went through")
+        }
+    }
+
+    #[test]
+    fn a_changed_dependency_reloads_the_model() {
+        let path = model("dependency");
+        let resolver = DependencyResolver::new(&path);
+        obj_loader::read(TRIANGLE.as_bytes(), &resolver).unwrap();
+        let dependencies = resolver.into_dependencies();
+        let mtllib = std::fs::canonicalize(path.with_file_name("triangle.mtl")).unwrap();
+        assert_eq!(dependencies, vec![mtllib.clone()]);
+
+        let mut hot_reload = enabled();
+        let mut loader = AssetLoader::new();
+        hot_reload.watch(path.clone(), "obj", handles(1), dependencies);
+        hot_reload.files.get_mut(&path).unwrap().dependencies.insert(mtllib.clone(), Some(SystemTime::UNIX_EPOCH));
+
+        let reloaded = wait_for_reload(&mut hot_reload, &mut loader);
+        let meshes = reloaded[0].result.as_ref().unwrap();
+        assert_eq!(meshes.len(), 1);
+        assert_eq!(meshes[0].geometry.indices.len(), 3);
+        assert!(!hot_reload.files[&path].reloading);
+        assert_eq!(hot_reload.files[&path].dependencies.get(&mtllib).copied().flatten(), modified(&mtllib));
+    }
+
+    #[test]
+    fn nothing_is_checked_while_disabled() {
+        let path = model("disabled");
+        let mut hot_reload = HotReload::new();
+        let mut loader = AssetLoader::new();
+        hot_reload.watch(path.clone(), "obj", handles(1), Vec::new());
+        touch(&mut hot_reload, &path);
+
+        assert!(hot_reload.poll(&mut loader).is_empty());
+        assert!(!hot_reload.files[&path].reloading);
+        assert_eq!(hot_reload.files[&path].modified, Some(SystemTime::UNIX_EPOCH));
+    }
+}
@@ -3,53 +3,228 @@ use winit::window::Window;
 use futures::executor::block_on;
 use wgpu::util::DeviceExt;
 use std::iter;
+use std::path::Path;
 
+mod arena;
+mod handles;
+mod error;
+mod config;
+mod capabilities;
 mod wgpu_state;
 mod shaders;
 mod pipelines;
 mod scene;
 mod materials;
 mod geometry;
+mod lod;
 mod mesh;
+mod texture;
+mod camera;
+mod tonemapping;
+mod post_processing;
+mod render_graph;
+mod background;
+mod capture;
+mod asset_loader;
+mod asset_cache;
+mod hot_reload;
+mod gltf_loader;
+mod gltf_exporter;
+mod obj_loader;
+mod stl_loader;
+mod ply_loader;
+mod environment;
+mod hdr;
 
 // Exports
-pub use scene::Scene;
+pub use scene::{ Scene, MeshHandle };
+pub use error::RendererError;
+pub use config::RendererConfig;
+pub use capabilities::{ Capabilities, RendererFeature };
+pub use handles::Handle;
 pub use materials::{
+    AlphaMode,
+    Material,
     MaterialType,
-    SolidColorMaterial
+    MaterialHandle,
+    PbrMaterial,
+    RasterizerState,
+    RenderProperties,
+    SolidColorMaterial,
+    TexturedMaterial
+};
+pub use mesh::Mesh;
+pub use lod::{ Lod, LodLevel, LodMetric };
+pub use camera::Camera;
+pub use background::{ Background, CubemapImage, EnvironmentImage };
+pub use environment::Environment;
+pub use capture::Image;
+pub use gltf_loader::{ FileResolver, NoResolver, UriResolver };
+pub use asset_loader::{ LoadProgress, LoadState };
+pub use tonemapping::{ HdrSettings, Tonemapper };
+pub use post_processing::{
+    BloomSettings,
+    ChromaticAberrationSettings,
+    ColorGradingLut,
+    ColorGradingSettings,
+    CustomEffect,
+    EffectId,
+    FxaaSettings,
+    PostEffect,
+    PostProcessStack,
+    VignetteSettings
 };
-use mesh::Mesh;
-use geometry::{ Geometry, GeometryStore };
+pub use render_graph::{
+    BufferDesc,
+    CustomPass,
+    FrameResources,
+    PassBuilder,
+    PassContext,
+    PassId,
+    ResourceId,
+    TextureDesc
+};
+pub use geometry::{ Geometry, GeometryHandle, Topology, primitives };
+use geometry::GeometryStore;
+use asset_loader::AssetLoader;
+use asset_cache::{ AssetCache, AssetKey, RecordingResolver };
+use hot_reload::{DependencyResolver, HotReload};
+use handles::Pool;
 use wgpu_state::WGPUState;
 use shaders::ShaderStore;
-use pipelines::PipelineStore;
-use materials::{
-    Material,
-    MaterialBuffers,
-    RenderProperties
-};
+use pipelines::{PipelineStore, TargetState};
+use texture::Texture;
+use materials::MaterialBuffers;
+use camera::CameraBuffers;
+use tonemapping::TonemapPass;
+use render_graph::{RenderGraph, TransientPool};
+use background::BackgroundPass;
+use environment::EnvironmentStore;
+use std::collections::HashMap;
 
 pub struct Renderer {
     state: WGPUState,
     shader_store: ShaderStore,
     pipeline_store: PipelineStore,
-    material_buffers: Vec<MaterialBuffers>,
-    geometry_store: GeometryStore
+    material_buffers: Pool<MaterialBuffers>,
+    geometry_store: GeometryStore,
+    object_bind_group_layout: wgpu::BindGroupLayout,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    camera_buffers: CameraBuffers,
+    hdr: Option<HdrSettings>,
+    tonemap_pass: TonemapPass,
+    background_pass: BackgroundPass,
+    environment_store: EnvironmentStore,
+    post_processing: PostProcessStack,
+    custom_passes: Pool<Box<dyn CustomPass>>,
+    custom_pass_order: Vec<CustomPassHandle>,
+    transient_pool: TransientPool,
+    asset_loader: AssetLoader,
+    asset_cache: AssetCache,
+    hot_reload: HotReload,
+    // Levels generate_lod made from each geometry and their fraction of its triangles, made
+    // again whenever it's replaced
+    lod_sources: HashMap<GeometryHandle, Vec<(GeometryHandle, f32)>>
+}
+
+pub type CustomPassHandle = Handle<Box<dyn CustomPass>>;
+
+// The pieces of the renderer that scene passes draw with, borrowed on their own so the
+// graph's passes don't hold onto the whole renderer
+#[derive(Clone, Copy)]
+struct SceneDraw<'a> {
+    pipeline_store: &'a PipelineStore,
+    material_buffers: &'a Pool<MaterialBuffers>,
+    geometry_store: &'a GeometryStore,
+    camera_buffers: &'a CameraBuffers,
+    environment_bind_group: &'a wgpu::BindGroup
+}
+
+impl<'a> SceneDraw<'a> {
+    fn draw_meshes<'p>(self,
+                       render_pass: &mut wgpu::RenderPass<'p>,
+                       target: &TargetState,
+                       scene: &'p Scene,
+                       meshes: &[(MeshHandle, &Mesh, GeometryHandle)]) -> Result<(), RendererError>
+        where 'a: 'p
+    {
+        render_pass.set_bind_group(2, &self.camera_buffers.bind_group, &[]);
+        render_pass.set_bind_group(3, self.environment_bind_group, &[]);
+        self.geometry_store.set_geometry_buffers(render_pass);
+
+        for (handle, mesh, geometry) in meshes {
+            let material_buffers = self.material_buffers.get(mesh.material.material_handle)?;
+            let topology = self.geometry_store.get(*geometry)?.geometry.topology;
+            let pipeline = self.pipeline_store.get(&mesh.material.get_pipeline_key(target, topology))
+                .ok_or(RendererError::MissingPipeline)?;
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, &material_buffers.uniform_bind_group, &[]); 
+            render_pass.set_bind_group(1, scene.object_bind_group(), &[scene.object_offset(*handle)]);
+
+            mesh.render(*geometry, self.geometry_store, render_pass)?;
+        }
+
+        Ok(())
+    }
+}
+
+// The color attachment and resolve target scene passes draw with
+fn scene_attachments<'r>(ctx: &PassContext<'r>,
+                         msaa: Option<ResourceId>,
+                         scene_color: ResourceId) -> (&'r wgpu::TextureView, Option<&'r wgpu::TextureView>)
+{
+    match msaa {
+        Some(msaa) => (ctx.texture_view(msaa), Some(ctx.texture_view(scene_color))),
+        None => (ctx.texture_view(scene_color), None)
+    }
 }
 
 impl Renderer {
-    pub fn new(window: &Window) -> Self {
-        let state = block_on(WGPUState::new(window));
+    pub fn new(window: &Window, config: RendererConfig) -> Result<Self, RendererError> {
+        let state = block_on(WGPUState::new(window, &config))?;
+        Ok(Renderer::from_state(state))
+    }
+
+    // Renders without a window into an offscreen frame of this size, read it with capture_frame
+    pub fn new_headless(width: u32, height: u32, config: RendererConfig) -> Result<Self, RendererError> {
+        let state = block_on(WGPUState::new_headless(winit::dpi::PhysicalSize::new(width, height), &config))?;
+        Ok(Renderer::from_state(state))
+    }
+
+    fn from_state(state: WGPUState) -> Self {
+        let id = handles::next_owner_id();
         let shader_store = ShaderStore::new(&state);
-        let pipeline_store = PipelineStore::new(&state, &shader_store);
-        let geometry_store = GeometryStore::new(&state.device);
+        let object_bind_group_layout = scene::create_object_bind_group_layout(&state.device);
+        let camera_bind_group_layout = camera::create_camera_bind_group_layout(&state.device);
+        let pipeline_store = PipelineStore::new();
+        let camera_buffers = CameraBuffers::new(&state.device, &camera_bind_group_layout);
+        let tonemap_pass = TonemapPass::new(&state, &shader_store);
+        let geometry_store = GeometryStore::new(&state.device, id);
+        let post_processing = PostProcessStack::new(&state, id);
+        let background_pass = BackgroundPass::new(&state);
+        let environment_store = EnvironmentStore::new(&state, &shader_store);
 
         Self {
             state,
             shader_store,
             pipeline_store,
-            material_buffers: Vec::new(),
-            geometry_store
+            material_buffers: Pool::new(id),
+            geometry_store,
+            object_bind_group_layout,
+            camera_bind_group_layout,
+            camera_buffers,
+            hdr: None,
+            tonemap_pass,
+            background_pass,
+            environment_store,
+            post_processing,
+            custom_passes: Pool::new(id),
+            custom_pass_order: Vec::new(),
+            transient_pool: TransientPool::default(),
+            asset_loader: AssetLoader::new(),
+            asset_cache: AssetCache::new(),
+            hot_reload: HotReload::new(),
+            lod_sources: HashMap::new()
         }
     }
 
@@ -61,13 +236,195 @@ impl Renderer {
         self.state.resize(new_size);
     }
 
+    // Which GPU is in use and what it can do, for reporting and picking fallbacks
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities::new(&self.state)
+    }
+
+    // 1 turns MSAA off and 4 is the only other count WebGPU guarantees. wgpu 0.8 has no way to ask
+    // whether the adapter supports a sample count for a format, so 2 and 8 can't be validated and
+    // fail with UncheckedSampleCount instead of risking a device error. Anything else is
+    // UnsupportedSampleCount.
+    pub fn set_sample_count(&mut self, sample_count: u32) -> Result<(), RendererError> {
+        wgpu_state::check_sample_count(sample_count)?;
+
+        if sample_count != self.state.sample_count {
+            self.state.set_sample_count(sample_count);
+        }
+        Ok(())
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.state.sample_count
+    }
+
+    // None draws straight into the swap chain, Some renders in HDR and tonemaps with these settings
+    pub fn set_hdr(&mut self, settings: Option<HdrSettings>) {
+        self.hdr = settings;
+        self.state.set_hdr(settings.is_some());
+    }
+
+    pub fn hdr_settings(&self) -> Option<HdrSettings> {
+        self.hdr
+    }
+
+    pub fn post_processing(&self) -> &PostProcessStack {
+        &self.post_processing
+    }
+
+    pub fn post_processing_mut(&mut self) -> &mut PostProcessStack {
+        &mut self.post_processing
+    }
+
+    // Uploads whatever finished loading in the background since the last call
     pub fn update(&mut self) {
+        for (geometry, asset) in self.asset_loader.poll() {
+            match asset {
+                // Fails if the geometry was unloaded in the meantime, which is fine
+                Some(asset) => {
+                    self.hot_reload.add_dependencies(geometry, asset.dependencies);
+                    let _ = self.replace_geometry(geometry, asset.geometry);
+                },
+                // The next load_mesh_async of the file tries again instead of getting this one
+                None => self.asset_cache.evict(geometry)
+            }
+        }
+
+        for reloaded in self.hot_reload.poll(&mut self.asset_loader) {
+            match reloaded.result {
+                Ok(meshes) => {
+                    for ((handle, material), mesh) in reloaded.meshes.into_iter().zip(meshes) {
+                        // Still loading in the background, which will swap in what it read
+                        if let LoadState::Loading { .. } = self.asset_loader.state(handle) {
+                            continue;
+                        }
+                        let _ = self.replace_geometry(handle, mesh.geometry);
+                        if let (Some(material), Some(texture)) = (material, mesh.texture) {
+                            if let Err(err) = self.replace_texture(material, &texture) {
+                                log::error!("couldn't reload a texture of {}: {}", reloaded.path.display(), err);
+                            }
+                        }
+                    }
+                    log::info!("reloaded {}", reloaded.path.display());
+                },
+                Err(err) => log::error!("couldn't reload {}, keeping the old version: {}", reloaded.path.display(), err)
+            }
+        }
+    }
+
+    // Swaps in new geometry for a handle, and new levels for any Lod generated from it
+    fn replace_geometry(&mut self, handle: GeometryHandle, geometry: Geometry) -> Result<(), RendererError> {
+        let triangles = geometry.indices.len() / 3;
+        let mut current = geometry.clone();
+        self.geometry_store.replace(&self.state.device, &self.state.queue, handle, geometry)?;
+
+        if let Some(levels) = self.lod_sources.get(&handle) {
+            for &(level, fraction) in levels {
+                current = current.simplify((triangles as f32 * fraction) as usize);
+                // Levels unloaded on their own are skipped
+                let _ = self.geometry_store.replace(&self.state.device, &self.state.queue, level, current.clone());
+            }
+        }
+        Ok(())
+    }
+
+    // New buffers for a TexturedMaterial drawing a different image. The uniforms are written
+    // again the next time a mesh using it is drawn.
+    fn replace_texture(&mut self, material: MaterialHandle, texture: &Image) -> Result<(), RendererError> {
+        check_texture(texture)?;
+        let material_buffers = Material::create_textured_buffers(&self.state, &RenderProperties::default(), texture);
+        *self.material_buffers.get_mut(material)? = material_buffers;
+        Ok(())
+    }
+
+    // Watches files loaded from disk, along with the buffers, mtllibs and map_Kd textures they
+    // pull in. When any of them is saved the file is read again on a worker thread and update
+    // swaps in the new geometry. Meshes and handles already out there pick it up, Lods
+    // generated from them are simplified again. Materials are left alone apart from the
+    // texture of OBJ materials loaded with one. Panoramas loaded with load_hdr are decoded on
+    // a worker too and swapped into the scene the next time it's drawn.
+    pub fn set_hot_reload(&mut self, enabled: bool) {
+        self.hot_reload.enabled = enabled;
+    }
+
+    pub fn draw(&mut self, scene: &mut Scene) -> Result<(), RendererError>
+    {
+        let frame = match &self.state.swap_chain {
+            Some(swap_chain) => Some(swap_chain.get_current_frame()?.output),
+            None => None
+        };
+        match frame {
+            Some(frame) => self.render(scene, &frame.view),
+            None => {
+                // Headless, taken out for the draw so it isn't borrowed from the renderer
+                let target = self.state.offscreen_texture.take().expect("headless renderer without an offscreen target");
+                let result = self.render(scene, &target.view);
+                self.state.offscreen_texture = Some(target);
+                self.state.offscreen_drawn = result.is_ok();
+                result
+            }
+        }
+    }
 
+    // The last frame draw made. Headless renderers copy it straight out of their offscreen
+    // frame, drawing the scene first only if nothing has been drawn since it was made. Swap
+    // chain images can't be read back, so with a window the scene is drawn again into an
+    // offscreen target the same size and format, which also moves LOD selection along a frame.
+    // Blocks until the GPU is done.
+    pub fn capture_frame(&mut self, scene: &mut Scene) -> Result<Image, RendererError> {
+        if self.state.offscreen_texture.is_some() {
+            if !self.state.offscreen_drawn {
+                self.draw(scene)?;
+            }
+            let target = self.state.offscreen_texture.as_ref().expect("headless renderer without an offscreen target");
+            return capture::read_back(&self.state, &target.texture);
+        }
+
+        let target = Texture::create_offscreen_texture(&self.state.device, &self.state.sc_desc, "capture_texture");
+        self.render(scene, &target.view)?;
+        capture::read_back(&self.state, &target.texture)
     }
 
-    pub fn draw(&mut self, scene: &Scene) -> Result<(), wgpu::SwapChainError>
+    // Everything draw does, into whatever view the frame should end up in
+    fn render(&mut self, scene: &mut Scene, output: &wgpu::TextureView) -> Result<(), RendererError>
     {
-        let frame = self.state.swap_chain.get_current_frame()?.output;
+        scene.apply_loaded_materials(&self.asset_loader.loaded_materials, Renderer::loaded_render_properties(None));
+        self.hot_reload.refresh_images(scene);
+        scene.select_lods(&self.geometry_store)?;
+        scene.prepare(&self.state, &self.object_bind_group_layout, &self.material_buffers)?;
+        let scene = &*scene;
+
+        let aspect = self.state.size.width as f32 / self.state.size.height.max(1) as f32;
+        self.camera_buffers.write(&self.state.queue, &scene.camera, aspect);
+
+        // Transparent meshes are drawn after everything opaque, furthest from the camera first
+        let (mut transparent, opaque): (Vec<_>, Vec<_>) = scene.draw_list()
+            .partition(|(_, mesh, _)| mesh.material.alpha_mode.is_transparent());
+        camera::sort_back_to_front(scene.camera.view_matrix(), &mut transparent, |(_, mesh, _)| mesh.transform);
+
+        let target = TargetState {
+            format: self.state.color_format(),
+            depth_format: Some(Texture::DEPTH_FORMAT),
+            sample_count: self.state.sample_count
+        };
+        let scene_layouts = [&self.object_bind_group_layout, &self.camera_bind_group_layout, self.environment_store.bind_group_layout()];
+        for (_, mesh, geometry) in scene.draw_list() {
+            let topology = self.geometry_store.get(geometry)?.geometry.topology;
+            self.pipeline_store.prepare(&self.state, &self.shader_store, &scene_layouts, &mesh.material.get_pipeline_key(&target, topology));
+        }
+
+        self.environment_store.prepare(&self.state, scene.environment.as_ref());
+        self.background_pass.prepare(&self.state, &self.shader_store, &scene.background, self.environment_store.skybox(), &scene.camera, aspect, &target);
+
+        if let Some(settings) = &self.hdr {
+            self.tonemap_pass.prepare(&self.state, settings);
+        }
+
+        let post_processing = self.post_processing.has_enabled_effects();
+        if post_processing {
+            self.post_processing.prepare(&self.state, &self.shader_store);
+        }
+
         let mut encoder = self
             .state
             .device
@@ -75,76 +432,529 @@ impl Renderer {
                 label: Some("Render Encoder"),
             });
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        // The whole frame goes through the graph so custom passes can slot in between the
+        // built-in ones, which draw into the renderer's own targets.
+        let mut graph = RenderGraph::new();
+        let output = graph.import_texture(output);
+        // Tonemapping lands in the first post effect's input if there are any, otherwise the frame
+        let post_input = if post_processing {
+            graph.import_texture(self.post_processing.input_view())
+        }
+        else {
+            output
+        };
+        // The scene goes into the HDR target when there is one, otherwise wherever tonemapping would.
+        // With MSAA on, it's drawn into the multisampled target and resolved into that.
+        let scene_color = match &self.state.hdr_texture {
+            Some(hdr_texture) => graph.import_texture(&hdr_texture.view),
+            None => post_input
+        };
+        let msaa = self.state.msaa_texture.as_ref().map(|msaa_texture| graph.import_texture(&msaa_texture.view));
+        let depth = graph.import_texture(&self.state.depth_texture.view);
+
+        let scene_draw = SceneDraw {
+            pipeline_store: &self.pipeline_store,
+            material_buffers: &self.material_buffers,
+            geometry_store: &self.geometry_store,
+            camera_buffers: &self.camera_buffers,
+            environment_bind_group: self.environment_store.bind_group()
+        };
+
+        let background_pass = &self.background_pass;
+        let clear_color = scene.background.clear_color();
+        let opaque_pass = graph.add_pass("Opaque Pass", |builder| {
+            builder.write(scene_color);
+            builder.write(depth);
+            if let Some(msaa) = msaa {
+                builder.write(msaa);
+            }
+        }, move |ctx| {
+            let (color_view, resolve_target) = scene_attachments(ctx, msaa, scene_color);
+            let depth_view = ctx.texture_view(depth);
+            let mut render_pass = ctx.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[wgpu::RenderPassColorAttachment {
-                    view: &frame.view,
-                    resolve_target: None,
+                    view: color_view,
+                    resolve_target,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
+                        load: wgpu::LoadOp::Clear(clear_color),
                         store: true,
                     },
                 }],
-                depth_stencil_attachment: None,
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
             });
 
-            for mesh in &scene.meshes {
-                let material_buffers = &self.material_buffers[mesh.material.material_handle];
-                let pipeline = &self.pipeline_store.get(mesh.material.get_pipeline_id()); //1
-                render_pass.set_pipeline(pipeline); //1
-                render_pass.set_bind_group(0, &material_buffers.uniform_bind_group, &[]); 
-                self.geometry_store.set_geometry_buffers(&mut render_pass);
+            background_pass.draw(&mut render_pass);
+            scene_draw.draw_meshes(&mut render_pass, &target, scene, &opaque)
+        });
 
-                mesh.render(&mut render_pass);
+        let mut transparent_pass = None;
+        if !transparent.is_empty() {
+            transparent_pass = Some(graph.add_pass("Transparent Pass", |builder| {
+                builder.write(scene_color);
+                builder.write(depth);
+                if let Some(msaa) = msaa {
+                    builder.write(msaa);
+                }
+            }, move |ctx| {
+                let (color_view, resolve_target) = scene_attachments(ctx, msaa, scene_color);
+                let depth_view = ctx.texture_view(depth);
+                let mut render_pass = ctx.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Transparent Pass"),
+                    color_attachments: &[wgpu::RenderPassColorAttachment {
+                        view: color_view,
+                        resolve_target,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    }],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: depth_view,
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        }),
+                        stencil_ops: None,
+                    }),
+                });
+
+                scene_draw.draw_meshes(&mut render_pass, &target, scene, &transparent)
+            }));
+        }
+
+        let mut tonemap_pass = None;
+        if self.hdr.is_some() {
+            let tonemap = &self.tonemap_pass;
+            tonemap_pass = Some(graph.add_pass("Tonemap Pass", |builder| {
+                builder.read(scene_color);
+                builder.write(post_input);
+            }, move |ctx| {
+                let output_view = ctx.texture_view(post_input);
+                tonemap.encode(ctx.encoder, output_view);
+                Ok(())
+            }));
+        }
+
+        let mut post_processing_pass = None;
+        if post_processing {
+            let post_stack = &self.post_processing;
+            post_processing_pass = Some(graph.add_pass("Post Processing", |builder| {
+                builder.read(post_input);
+                builder.write(output);
+            }, move |ctx| {
+                let output_view = ctx.texture_view(output);
+                post_stack.encode(ctx.device, ctx.encoder, output_view);
+                Ok(())
+            }));
+        }
+
+        let frame_resources = FrameResources {
+            output,
+            scene_color,
+            depth,
+            output_format: self.state.swapchain_format,
+            scene_format: self.state.color_format(),
+            sample_count: self.state.sample_count,
+            opaque_pass,
+            transparent_pass,
+            tonemap_pass,
+            post_processing_pass
+        };
+        let mut custom_passes: HashMap<_, _> = self.custom_passes.iter_mut().collect();
+        for handle in &self.custom_pass_order {
+            if let Some(pass) = custom_passes.remove(handle) {
+                graph.add_custom_pass(pass.as_mut(), &frame_resources);
             }
         }
 
+        graph.execute(&self.state, &mut self.transient_pool, &mut encoder)?;
         self.state.queue.submit(iter::once(encoder.finish()));
 
         Ok(())
     }
 
+    // Runs every frame after the built-in passes are added, ordered against them by what it reads and
+    // writes. Passes that have to go ahead of a built-in one can say so with PassBuilder::run_before.
+    pub fn add_custom_pass(&mut self, pass: Box<dyn CustomPass>) -> CustomPassHandle {
+        let handle = self.custom_passes.insert(pass);
+        self.custom_pass_order.push(handle);
+        handle
+    }
+
+    pub fn remove_custom_pass(&mut self, handle: CustomPassHandle) -> Result<Box<dyn CustomPass>, RendererError> {
+        let pass = self.custom_passes.remove(handle)?;
+        self.custom_pass_order.retain(|other| *other != handle);
+        Ok(pass)
+    }
+
     pub fn create_material(&mut self, material_type: MaterialType, render_properties: RenderProperties) -> Material {
         let material_buffers = Material::create_buffers(&self.state, &material_type, &render_properties);
-        self.material_buffers.push(material_buffers);
         return Material::new(
-            self.material_buffers.len()-1,
+            self.material_buffers.insert(material_buffers),
             material_type,
             render_properties
         )
     }
 
+    // A TexturedMaterial drawing the image, sRGB, multiplied by the albedo
+    pub fn create_textured_material(&mut self, render_properties: RenderProperties, texture: &Image) -> Result<Material, RendererError> {
+        check_texture(texture)?;
+        let material_buffers = Material::create_textured_buffers(&self.state, &render_properties, texture);
+        Ok(Material::new(
+            self.material_buffers.insert(material_buffers),
+            MaterialType::TexturedMaterial,
+            render_properties
+        ))
+    }
+
+    // Any Material still pointing at the handle fails to draw with StaleHandle afterwards
+    pub fn destroy_material(&mut self, material_handle: MaterialHandle) -> Result<(), RendererError> {
+        self.material_buffers.remove(material_handle).map(|_| ())
+    }
+
+    // Generated geometry, like the shapes in primitives. Normals are worked out if there aren't any.
+    pub fn load_geometry(&mut self, geometry: Geometry) -> GeometryHandle {
+        self.geometry_store.load_mesh(&self.state.device, &self.state.queue, geometry)
+    }
+
+    // Simplified copies of a loaded geometry for a Lod. Each level is a fraction of the full
+    // triangle count and the threshold to switch to it at, most detailed first.
+    pub fn generate_lod(&mut self, geometry: GeometryHandle, metric: LodMetric, levels: &[(f32, f32)]) -> Result<Lod, RendererError> {
+        let mut current = self.geometry_store.get_geometry_data(geometry)?.clone();
+        let triangles = current.indices.len() / 3;
+        let mut lod = Lod::new(metric);
+        let mut generated = Vec::with_capacity(levels.len());
+        for &(fraction, threshold) in levels {
+            // Each level starts from the one before, it's quicker than going back to the original
+            current = current.simplify((triangles as f32 * fraction) as usize);
+            let level = self.load_geometry(current.clone());
+            generated.push((level, fraction));
+            lod = lod.with_level(level, threshold);
+        }
+        // A second Lod from the same geometry takes over reloading from the first
+        self.lod_sources.insert(geometry, generated);
+
+        Ok(lod)
+    }
+
+    pub fn unload_geometry(&mut self, geometry_handle: GeometryHandle) -> Result<(), RendererError> {
+        self.asset_loader.forget(geometry_handle);
+        self.asset_cache.forget(geometry_handle);
+        self.hot_reload.forget(geometry_handle);
+        self.lod_sources.remove(&geometry_handle);
+        self.geometry_store.unload_mesh(geometry_handle).map(|_| ())
+    }
+
     // I only need 2 buffers. One for material and one for vertex stuff.
+    // Panics if the file can't be loaded, load_gltf reports it instead
     pub fn load_mesh(&mut self, file: &str) -> Mesh {
-        let (doc, buffers, images) = gltf::import(file).unwrap();
-        let mesh_data = doc.meshes().next().expect("no meshes in data.glb");
-
-        let primitive = mesh_data.primitives().next().expect("no primitives in data.glb");
-        let reader = primitive.reader(|b| Some(&buffers.get(b.index())?.0[..b.length()]));
-    
-        let vertex_positions: Vec<_> = reader.read_positions().unwrap().map(glam::Vec3::from).collect();
-        let indices = reader.read_indices().unwrap().into_u32().collect::<Vec<_>>();
-
-        let geometry = Geometry {
-            vertex_positions: vertex_positions,
-            indices: indices
-        };
-        
-        let geometry_handle = self.geometry_store.load_mesh(&self.state.queue, geometry);
+        self.load_gltf(file).unwrap()
+    }
+
+    // External buffers are looked up next to the file, not in the working directory.
+    // Loading the same file again gives a copy that shares the first one's geometry and material,
+    // so setting render_properties on one copy changes all of them. Give a copy its own material
+    // with create_material to draw it differently.
+    pub fn load_gltf<P: AsRef<Path>>(&mut self, path: P) -> Result<Mesh, RendererError> {
+        let path = path.as_ref();
+        let meshes = self.load_cached_file("gltf", path.as_ref(), |renderer, bytes, resolver| {
+            Ok(vec![renderer.read_gltf(bytes, resolver)?])
+        })?;
+        Ok(meshes[0].clone())
+    }
+
+    // .gltf or .glb bytes. Data URIs and the .glb binary chunk are read directly, anything
+    // else the file points at is fetched through the resolver.
+    pub fn load_gltf_from_slice(&mut self, bytes: &[u8], resolver: &dyn UriResolver) -> Result<Mesh, RendererError> {
+        let meshes = self.load_cached_resolved(Some(AssetKey::content("gltf", bytes)), resolver, |renderer, resolver| {
+            Ok(vec![renderer.read_gltf(bytes, resolver)?])
+        })?;
+        Ok(meshes[0].clone())
+    }
+
+    fn read_gltf(&mut self, bytes: &[u8], resolver: &dyn UriResolver) -> Result<Mesh, RendererError> {
+        let primitive = gltf_loader::read_first_primitive(bytes, resolver)?;
+        Ok(self.create_gltf_mesh(primitive))
+    }
+
+    // Every mesh in the file's scene, one per node and primitive, with transform set to where
+    // the node puts it. What export_gltf and export_glb write comes back the same way.
+    pub fn load_gltf_scene<P: AsRef<Path>>(&mut self, path: P) -> Result<Vec<Mesh>, RendererError> {
+        self.load_cached_file("gltf_scene", path.as_ref(), |renderer, bytes, resolver| renderer.read_gltf_scene(bytes, resolver))
+    }
+
+    pub fn load_gltf_scene_from_slice(&mut self, bytes: &[u8], resolver: &dyn UriResolver) -> Result<Vec<Mesh>, RendererError> {
+        self.load_cached_resolved(Some(AssetKey::content("gltf_scene", bytes)), resolver, |renderer, resolver| renderer.read_gltf_scene(bytes, resolver))
+    }
+
+    fn read_gltf_scene(&mut self, bytes: &[u8], resolver: &dyn UriResolver) -> Result<Vec<Mesh>, RendererError> {
+        let primitives = gltf_loader::read_scene(bytes, resolver)?;
+        Ok(primitives.into_iter().map(|primitive| self.create_gltf_mesh(primitive)).collect())
+    }
+
+    fn create_gltf_mesh(&mut self, primitive: gltf_loader::GltfPrimitive) -> Mesh {
+        let geometry_handle = self.geometry_store.load_mesh(&self.state.device, &self.state.queue, primitive.geometry);
 
-        let render_properties = RenderProperties {
-            albedo: glam::Vec4::new(1.0,1.0,0.0,1.0)
+        let render_properties = primitive.render_properties.unwrap_or_else(|| Renderer::loaded_render_properties(None));
+        let mut material = self.create_material(MaterialType::SolidColorMaterial, render_properties);
+        material.alpha_mode = primitive.alpha_mode;
+        material.rasterizer = primitive.rasterizer;
+
+        let mut mesh = mesh::Mesh::new(geometry_handle, material);
+        mesh.transform = primitive.transform;
+        mesh
+    }
+
+    // Returns straight away with a gray placeholder box, the file is parsed on a worker thread
+    // and swapped in by the update after it's done. The mesh picks up the file's material the
+    // first time it's drawn after that. glTF, .glb, OBJ, STL and PLY. OBJ files come back as
+    // one mesh with the first material's color.
+    pub fn load_mesh_async<P: AsRef<Path>>(&mut self, path: P) -> Mesh {
+        // Shared with other background loads of the file, not blocking ones, those never
+        // hand out a placeholder
+        let key = AssetKey::path("background", path.as_ref());
+        if let Some(meshes) = key.as_ref().and_then(|key| self.asset_cache.get(key, &NoResolver)) {
+            return meshes[0].clone();
+        }
+
+        let placeholder = match self.asset_loader.placeholder {
+            Some(placeholder) => placeholder,
+            None => {
+                let placeholder = self.load_geometry(primitives::cube(1.0, 1));
+                self.asset_loader.placeholder = Some(placeholder);
+                placeholder
+            }
         };
+        let geometry = self.geometry_store.share(placeholder).expect("the placeholder geometry is never unloaded");
+        self.asset_loader.request(geometry, path.as_ref().to_path_buf());
 
-        mesh::Mesh::new(
-            geometry_handle,
-            self.create_material(MaterialType::SolidColorMaterial, render_properties)
-        )
+        let material = self.create_material(MaterialType::SolidColorMaterial, RenderProperties {
+            albedo: glam::Vec4::new(0.5, 0.5, 0.5, 1.0),
+            ..RenderProperties::default()
+        });
+        let mesh = mesh::Mesh::new(geometry, material);
+        if let Some(key) = key {
+            if let AssetKey::Path(loader, path) = &key {
+                self.hot_reload.watch(path.clone(), loader, vec![(geometry, None)], Vec::new());
+            }
+            self.asset_cache.insert(key, std::slice::from_ref(&mesh), Vec::new());
+        }
+        mesh
+    }
+
+    // Anything that wasn't loaded with load_mesh_async counts as loaded
+    pub fn load_state(&self, geometry: GeometryHandle) -> LoadState {
+        self.asset_loader.state(geometry)
+    }
+
+    pub fn loading_progress(&self) -> LoadProgress {
+        self.asset_loader.progress()
+    }
+
+    // One mesh per object, group or material in the file. mtllib files and their map_Kd PNGs are
+    // looked up next to it. Like load_gltf, loading the file again shares the materials,
+    // give a mesh one from create_material to change that copy on its own.
+    pub fn load_obj<P: AsRef<Path>>(&mut self, path: P) -> Result<Vec<Mesh>, RendererError> {
+        self.load_cached_file("obj", path.as_ref(), |renderer, bytes, resolver| renderer.read_obj(bytes, resolver))
+    }
+
+    // mtllib files are fetched through the resolver
+    pub fn load_obj_from_slice(&mut self, bytes: &[u8], resolver: &dyn UriResolver) -> Result<Vec<Mesh>, RendererError> {
+        self.load_cached_resolved(Some(AssetKey::content("obj", bytes)), resolver, |renderer, resolver| renderer.read_obj(bytes, resolver))
+    }
+
+    fn read_obj(&mut self, bytes: &[u8], resolver: &dyn UriResolver) -> Result<Vec<Mesh>, RendererError> {
+        let primitives = obj_loader::read(bytes, resolver)?;
+
+        let mut meshes = Vec::with_capacity(primitives.len());
+        for primitive in primitives {
+            let geometry_handle = self.geometry_store.load_mesh(&self.state.device, &self.state.queue, primitive.geometry);
+
+            let albedo = primitive.material.as_ref().map(|material| material.albedo);
+            // A texture that can't be read leaves the diffuse color, rather than failing the model
+            let texture = primitive.material.as_ref().and_then(|material| material.diffuse_texture.as_ref()).and_then(|name| {
+                resolver.resolve(name).and_then(|bytes| Image::from_png(&bytes))
+                    .map_err(|err| log::warn!("obj diffuse texture {} isn't applied: {}", name, err))
+                    .ok()
+            });
+
+            let mut material = match texture {
+                Some(texture) => self.create_textured_material(Renderer::loaded_render_properties(albedo), &texture)?,
+                None => self.create_material(MaterialType::SolidColorMaterial, Renderer::loaded_render_properties(albedo))
+            };
+            if albedo.map_or(false, |albedo| albedo.w < 1.0) {
+                material.alpha_mode = AlphaMode::Blend;
+            }
+            meshes.push(mesh::Mesh::new(geometry_handle, material));
+        }
+
+        Ok(meshes)
+    }
+
+    // Binary or ASCII
+    pub fn load_stl<P: AsRef<Path>>(&mut self, path: P) -> Result<Mesh, RendererError> {
+        let meshes = self.load_cached_file("stl", path.as_ref(), |renderer, bytes, _| {
+            Ok(vec![renderer.create_loaded_mesh(stl_loader::read(bytes)?)])
+        })?;
+        Ok(meshes[0].clone())
+    }
+
+    pub fn load_stl_from_slice(&mut self, bytes: &[u8]) -> Result<Mesh, RendererError> {
+        let meshes = self.load_cached(Some(AssetKey::content("stl", bytes)), |renderer| {
+            Ok(vec![renderer.create_loaded_mesh(stl_loader::read(bytes)?)])
+        })?;
+        Ok(meshes[0].clone())
+    }
+
+    // Files without faces come back as point clouds. Vertex colors are read but not drawn yet,
+    // the mesh gets the yellow loaded material like any other file without one.
+    pub fn load_ply<P: AsRef<Path>>(&mut self, path: P) -> Result<Mesh, RendererError> {
+        let meshes = self.load_cached_file("ply", path.as_ref(), |renderer, bytes, _| {
+            Ok(vec![renderer.create_loaded_mesh(ply_loader::read(bytes)?)])
+        })?;
+        Ok(meshes[0].clone())
+    }
+
+    pub fn load_ply_from_slice(&mut self, bytes: &[u8]) -> Result<Mesh, RendererError> {
+        let meshes = self.load_cached(Some(AssetKey::content("ply", bytes)), |renderer| {
+            Ok(vec![renderer.create_loaded_mesh(ply_loader::read(bytes)?)])
+        })?;
+        Ok(meshes[0].clone())
+    }
+
+    // Every load from a file or bytes goes through here. A file that's been loaded before
+    // gives back the same geometry and material, only the first load uploads anything.
+    // Without a key, when the file can't be found, it's loaded as is and the load reports why.
+    fn load_cached<F>(&mut self, key: Option<AssetKey>, load: F) -> Result<Vec<Mesh>, RendererError>
+        where F: FnOnce(&mut Self) -> Result<Vec<Mesh>, RendererError>
+    {
+        self.load_cached_resolved(key, &NoResolver, |renderer, _| load(renderer))
+    }
+
+    // For loads from memory that fetch external files through a resolver. The load gets a
+    // resolver that passes through to it and records what came back, the cached meshes are
+    // only reused while the resolver still gives the same data.
+    fn load_cached_resolved<F>(&mut self, key: Option<AssetKey>, resolver: &dyn UriResolver, load: F) -> Result<Vec<Mesh>, RendererError>
+        where F: FnOnce(&mut Self, &dyn UriResolver) -> Result<Vec<Mesh>, RendererError>
+    {
+        if let Some(meshes) = key.as_ref().and_then(|key| self.asset_cache.get(key, resolver)) {
+            return Ok(meshes);
+        }
+
+        let recording = RecordingResolver::new(resolver);
+        let meshes = load(self, &recording)?;
+        if let Some(key) = key {
+            self.asset_cache.insert(key, &meshes, recording.into_resolved());
+        }
+        Ok(meshes)
+    }
+
+    // Loads from disk. External files are looked up next to the file and watched along with
+    // it for hot reloading rather than checked on every cache hit.
+    fn load_cached_file<F>(&mut self, loader: &'static str, path: &Path, load: F) -> Result<Vec<Mesh>, RendererError>
+        where F: FnOnce(&mut Self, &[u8], &dyn UriResolver) -> Result<Vec<Mesh>, RendererError>
+    {
+        let key = AssetKey::path(loader, path);
+        if let Some(meshes) = key.as_ref().and_then(|key| self.asset_cache.get(key, &NoResolver)) {
+            return Ok(meshes);
+        }
+
+        let bytes = std::fs::read(path)?;
+        let resolver = DependencyResolver::new(path);
+        let meshes = load(self, &bytes, &resolver)?;
+        if let Some(AssetKey::Path(loader, path)) = &key {
+            let watched = meshes.iter().map(|mesh| {
+                let textured = mesh.material.material_type == MaterialType::TexturedMaterial;
+                (mesh.geometry, if textured { Some(mesh.material.material_handle) } else { None })
+            }).collect();
+            self.hot_reload.watch(path.clone(), loader, watched, resolver.into_dependencies());
+        }
+        if let Some(key) = key {
+            self.asset_cache.insert(key, &meshes, Vec::new());
+        }
+        Ok(meshes)
+    }
+
+    // Done with a mesh from one of the loaders. Its geometry and material are freed once the
+    // last mesh loaded from the same file is released. Meshes that didn't come from a loader
+    // are freed straight away. LOD geometry is left alone, unload it with unload_geometry.
+    pub fn release_mesh(&mut self, mesh: &Mesh) -> Result<(), RendererError> {
+        match self.asset_cache.release(mesh.geometry) {
+            Some(false) => Ok(()),
+            Some(true) | None => {
+                self.unload_geometry(mesh.geometry)?;
+                self.destroy_material(mesh.material.material_handle)
+            }
+        }
+    }
+
+    // How many loaded meshes still use the geometry, 0 if it wasn't loaded from a file
+    pub fn asset_references(&self, geometry: GeometryHandle) -> usize {
+        self.asset_cache.references(geometry)
+    }
+
+    // Writes path plus a .bin with the same stem next to it. Each mesh in the scene becomes a
+    // node with its transform, its geometry and its material's properties, load_gltf_scene
+    // reads them all back. Fails on NaN or infinite values, meshes without vertices are skipped.
+    pub fn export_gltf<P: AsRef<Path>>(&self, scene: &Scene, path: P) -> Result<(), RendererError> {
+        let path = path.as_ref();
+        let bin_name = format!("{}.bin", path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("scene"));
+        let document = gltf_exporter::write(&self.export_meshes(scene)?, Some(&bin_name))?;
+
+        std::fs::write(path, document.json)?;
+        std::fs::write(path.with_file_name(bin_name), document.bin)?;
+        Ok(())
+    }
+
+    pub fn export_glb<P: AsRef<Path>>(&self, scene: &Scene, path: P) -> Result<(), RendererError> {
+        std::fs::write(path, self.export_glb_to_vec(scene)?)?;
+        Ok(())
+    }
+
+    pub fn export_glb_to_vec(&self, scene: &Scene) -> Result<Vec<u8>, RendererError> {
+        let document = gltf_exporter::write(&self.export_meshes(scene)?, None)?;
+        Ok(gltf_exporter::to_glb(&document))
+    }
+
+    fn export_meshes<'a>(&'a self, scene: &'a Scene) -> Result<Vec<gltf_exporter::ExportMesh<'a>>, RendererError> {
+        scene.iter()
+            .map(|(_, mesh)| Ok(gltf_exporter::ExportMesh {
+                geometry: self.geometry_store.get_geometry_data(mesh.geometry)?,
+                material: &mesh.material,
+                transform: mesh.transform
+            }))
+            .collect()
+    }
+
+    fn create_loaded_mesh(&mut self, geometry: Geometry) -> Mesh {
+        let geometry_handle = self.geometry_store.load_mesh(&self.state.device, &self.state.queue, geometry);
+        let material = self.create_material(MaterialType::SolidColorMaterial, Renderer::loaded_render_properties(None));
+        mesh::Mesh::new(geometry_handle, material)
+    }
+
+    // Loaded models without a color of their own are drawn yellow
+    fn loaded_render_properties(albedo: Option<glam::Vec4>) -> RenderProperties {
+        RenderProperties {
+            albedo: albedo.unwrap_or(glam::Vec4::new(1.0,1.0,0.0,1.0)),
+            ..RenderProperties::default()
+        }
+    }
+}
+
+// Textures have to have a pixel and exactly as many bytes as their size says
+fn check_texture(texture: &Image) -> Result<(), RendererError> {
+    let size = texture.width.checked_mul(texture.height).and_then(|pixels| pixels.checked_mul(4));
+    if texture.width == 0 || texture.height == 0 || size.map(|size| size as usize) != Some(texture.pixels.len()) {
+        return Err(RendererError::InvalidImage(format!("{}x{} texture with {} bytes of pixels",
+            texture.width, texture.height, texture.pixels.len())));
     }
+    Ok(())
 }
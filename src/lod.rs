@@ -0,0 +1,136 @@
+use crate::geometry::GeometryHandle;
+
+// What the thresholds of a Lod are measured in
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LodMetric {
+    // World space distance from the camera to the mesh's bounds. A level is used once the
+    // mesh is at least its threshold away.
+    Distance,
+    // Height of the mesh's bounding sphere on screen, as a fraction of the viewport's. A level
+    // is used once the mesh is smaller than its threshold.
+    ScreenSize
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LodLevel {
+    pub geometry: GeometryHandle,
+    pub threshold: f32
+}
+
+// Coarser stand-ins for a mesh's geometry. Mesh::geometry is the full detail level, the
+// levels here follow it from most to least detailed, with thresholds in that order too.
+#[derive(Debug, Clone)]
+pub struct Lod {
+    pub levels: Vec<LodLevel>,
+    pub metric: LodMetric,
+    // How far past a threshold, as a fraction of it, the metric has to go before the level
+    // changes. Stops meshes sitting on a threshold from flicking between levels every frame.
+    pub hysteresis: f32
+}
+
+impl Lod {
+    pub fn new(metric: LodMetric) -> Self {
+        Self {
+            levels: Vec::new(),
+            metric,
+            hysteresis: 0.0
+        }
+    }
+
+    pub fn with_level(mut self, geometry: GeometryHandle, threshold: f32) -> Self {
+        self.levels.push(LodLevel { geometry, threshold });
+        self
+    }
+
+    pub fn with_hysteresis(mut self, hysteresis: f32) -> Self {
+        self.hysteresis = hysteresis;
+        self
+    }
+
+    // 0 is the mesh's own geometry, n is levels[n - 1]. current is the level last drawn.
+    pub(crate) fn select(&self, current: usize, distance: f32, screen_size: f32) -> usize {
+        // Both metrics as something that grows as the mesh should get coarser
+        let (coarseness, thresholds): (f32, Vec<f32>) = match self.metric {
+            LodMetric::Distance => (distance, self.levels.iter().map(|level| level.threshold).collect()),
+            LodMetric::ScreenSize => (1.0 / screen_size.max(f32::MIN_POSITIVE),
+                                      self.levels.iter().map(|level| 1.0 / level.threshold.max(f32::MIN_POSITIVE)).collect())
+        };
+        let level_at = |scale: f32| thresholds.iter().take_while(|&&threshold| coarseness >= threshold * scale).count();
+
+        // Only drop detail once clearly past a threshold, and only take it back once clearly
+        // back before it. In between the current level stays.
+        let coarsest = level_at(1.0 - self.hysteresis);
+        let finest = level_at(1.0 + self.hysteresis).min(coarsest);
+        current.clamp(finest, coarsest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::GeometryEntry;
+    use crate::handles::Pool;
+
+    fn lod(metric: LodMetric, thresholds: &[f32], hysteresis: f32) -> Lod {
+        let mut geometries: Pool<(), GeometryEntry> = Pool::new(0);
+        thresholds.iter()
+            .fold(Lod::new(metric), |lod, threshold| lod.with_level(geometries.insert(()), *threshold))
+            .with_hysteresis(hysteresis)
+    }
+
+    #[test]
+    fn levels_follow_the_distance() {
+        let lod = lod(LodMetric::Distance, &[10.0, 20.0], 0.0);
+        assert_eq!(lod.select(0, 5.0, 1.0), 0);
+        assert_eq!(lod.select(0, 10.0, 1.0), 1);
+        assert_eq!(lod.select(0, 25.0, 1.0), 2);
+        // Any level can be reached straight from any other
+        assert_eq!(lod.select(2, 5.0, 1.0), 0);
+    }
+
+    #[test]
+    fn levels_stay_put_inside_the_hysteresis_band() {
+        // The first threshold's band is 9 to 11
+        let lod = lod(LodMetric::Distance, &[10.0, 20.0], 0.1);
+        for distance in [9.5, 10.0, 10.5] {
+            assert_eq!(lod.select(0, distance, 1.0), 0);
+            assert_eq!(lod.select(1, distance, 1.0), 1);
+        }
+    }
+
+    #[test]
+    fn levels_switch_past_the_hysteresis_band() {
+        let lod = lod(LodMetric::Distance, &[10.0, 20.0], 0.1);
+        assert_eq!(lod.select(0, 11.5, 1.0), 1);
+        assert_eq!(lod.select(1, 8.5, 1.0), 0);
+        // 10% of the second threshold is a wider band, 18 to 22
+        assert_eq!(lod.select(1, 21.5, 1.0), 1);
+        assert_eq!(lod.select(1, 22.5, 1.0), 2);
+        assert_eq!(lod.select(2, 18.5, 1.0), 2);
+        assert_eq!(lod.select(2, 17.5, 1.0), 1);
+    }
+
+    #[test]
+    fn screen_size_gets_coarser_as_it_shrinks() {
+        let lod = lod(LodMetric::ScreenSize, &[0.5, 0.1], 0.0);
+        assert_eq!(lod.select(0, 0.0, 1.0), 0);
+        assert_eq!(lod.select(0, 0.0, 0.5), 1);
+        assert_eq!(lod.select(0, 0.0, 0.3), 1);
+        assert_eq!(lod.select(0, 0.0, 0.05), 2);
+        assert_eq!(lod.select(2, 0.0, 0.8), 0);
+        // Nothing on screen at all is the coarsest level, not a division by zero
+        assert_eq!(lod.select(0, 0.0, 0.0), 2);
+    }
+
+    #[test]
+    fn screen_size_hysteresis_is_applied_to_the_inverse() {
+        // 1 / 0.5 = 2 with a band of 1.8 to 2.2, so sizes from 1 / 2.2 to 1 / 1.8
+        let lod = lod(LodMetric::ScreenSize, &[0.5, 0.1], 0.1);
+        for size in [0.47, 0.5, 0.54] {
+            assert_eq!(lod.select(0, 0.0, size), 0);
+            assert_eq!(lod.select(1, 0.0, size), 1);
+        }
+        assert_eq!(lod.select(0, 0.0, 0.44), 1);
+        assert_eq!(lod.select(1, 0.0, 0.57), 0);
+    }
+}
@@ -1,26 +1,153 @@
 use crate::WGPUState;
-use crate::pipelines::PipelineType;
+use crate::pipelines::{DepthState, PipelineKey, TargetState, VertexLayout};
+use crate::handles::Handle;
+use crate::geometry::Topology;
+use crate::capture::Image;
 
 mod solid_color_material;
+mod pbr_material;
+mod textured_material;
 
 pub use solid_color_material::SolidColorMaterial;
-pub type MaterialHandle = usize;
+pub use pbr_material::PbrMaterial;
+pub use textured_material::TexturedMaterial;
+pub type MaterialHandle = Handle<MaterialBuffers>;
 
 #[derive(Debug, Copy, Clone)]
 pub struct RenderProperties {
-    pub albedo: glam::Vec4
+    pub albedo: glam::Vec4,
+    // Only used by lit materials
+    pub metallic: f32,
+    pub roughness: f32
+}
+
+impl Default for RenderProperties {
+    fn default() -> Self {
+        Self {
+            albedo: glam::Vec4::ONE,
+            metallic: 0.0,
+            roughness: 0.5
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct Material {
     pub(crate) material_handle: MaterialHandle,
     pub material_type: MaterialType,
-    pub render_properties: RenderProperties
+    pub render_properties: RenderProperties,
+    pub alpha_mode: AlphaMode,
+    pub rasterizer: RasterizerState
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+pub struct RasterizerState {
+    pub cull_mode: Option<wgpu::Face>,
+    pub front_face: wgpu::FrontFace,
+    // Line and Point need NON_FILL_POLYGON_MODE, without it they fall back to Fill
+    pub polygon_mode: wgpu::PolygonMode
+}
+
+impl RasterizerState {
+    pub fn double_sided() -> Self {
+        Self {
+            cull_mode: None,
+            ..Self::default()
+        }
+    }
+
+    pub fn wireframe() -> Self {
+        Self {
+            cull_mode: None,
+            polygon_mode: wgpu::PolygonMode::Line,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn from_gltf(material: &gltf::Material) -> Self {
+        if material.double_sided() {
+            RasterizerState::double_sided()
+        }
+        else {
+            RasterizerState::default()
+        }
+    }
+}
+
+impl Default for RasterizerState {
+    fn default() -> Self {
+        Self {
+            cull_mode: Some(wgpu::Face::Back),
+            front_face: wgpu::FrontFace::Ccw,
+            polygon_mode: wgpu::PolygonMode::Fill
+        }
+    }
+}
+
+// How the albedo's alpha is used. Blend, Additive and Premultiplied are drawn in the
+// transparent pass, back to front, without writing depth.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum AlphaMode {
+    #[default]
+    Opaque,
+    // Fragments below the cutoff are discarded, the rest are drawn opaque
+    Mask { cutoff: f32 },
+    Blend,
+    Additive,
+    // The albedo is expected to already be multiplied by its alpha
+    Premultiplied
+}
+
+impl AlphaMode {
+    pub fn is_transparent(&self) -> bool {
+        matches!(self, AlphaMode::Blend | AlphaMode::Additive | AlphaMode::Premultiplied)
+    }
+
+    pub(crate) fn blend_state(&self) -> Option<wgpu::BlendState> {
+        match self {
+            AlphaMode::Opaque | AlphaMode::Mask { .. } => None,
+            AlphaMode::Blend => Some(wgpu::BlendState::ALPHA_BLENDING),
+            AlphaMode::Additive => Some(wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Zero,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+            }),
+            AlphaMode::Premultiplied => Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING)
+        }
+    }
+
+    pub(crate) fn from_gltf(material: &gltf::Material) -> Self {
+        match material.alpha_mode() {
+            gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+            // 0.5 is glTF's default cutoff
+            gltf::material::AlphaMode::Mask => AlphaMode::Mask { cutoff: material.alpha_cutoff().unwrap_or(0.5) },
+            gltf::material::AlphaMode::Blend => AlphaMode::Blend
+        }
+    }
+
+    // Opaque materials get a cutoff of zero, which never discards anything
+    pub(crate) fn alpha_cutoff(&self) -> f32 {
+        match self {
+            AlphaMode::Mask { cutoff } => *cutoff,
+            _ => 0.0
+        }
+    }
+}
+
+
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
 pub enum MaterialType {
-    SolidColorMaterial
+    SolidColorMaterial,
+    PbrMaterial,
+    // Unlit, with the albedo multiplied by a texture. Geometry without uvs samples its corner.
+    TexturedMaterial
 }
 
 pub struct MaterialBuffers {
@@ -34,6 +161,30 @@ impl Material {
         match material_type {
             MaterialType::SolidColorMaterial => {
                 return SolidColorMaterial::create_buffers(renderer_state, &render_properties);
+            },
+            MaterialType::PbrMaterial => {
+                PbrMaterial::create_buffers(renderer_state, render_properties)
+            },
+            MaterialType::TexturedMaterial => {
+                TexturedMaterial::create_buffers(renderer_state, render_properties, None)
+            }
+        }
+    }
+
+    pub(crate) fn create_textured_buffers(renderer_state: &WGPUState, render_properties: &RenderProperties, texture: &Image) -> MaterialBuffers {
+        TexturedMaterial::create_buffers(renderer_state, render_properties, Some(texture))
+    }
+
+    pub(crate) fn write_buffers(&self, renderer_state: &WGPUState, material_buffers: &MaterialBuffers) {
+        match self.material_type {
+            MaterialType::SolidColorMaterial => {
+                SolidColorMaterial::write_buffers(renderer_state, &self.render_properties, self.alpha_mode, material_buffers);
+            },
+            MaterialType::PbrMaterial => {
+                PbrMaterial::write_buffers(renderer_state, &self.render_properties, self.alpha_mode, material_buffers);
+            },
+            MaterialType::TexturedMaterial => {
+                TexturedMaterial::write_buffers(renderer_state, &self.render_properties, self.alpha_mode, material_buffers);
             }
         }
     }
@@ -45,15 +196,43 @@ impl Material {
         Self {
             material_handle,
             material_type,
-            render_properties
+            render_properties,
+            alpha_mode: AlphaMode::default(),
+            rasterizer: RasterizerState::default()
         }
     }
 
-    pub(crate) fn get_pipeline_id(&self) -> PipelineType {
-        match &self.material_type {
-            MaterialType::SolidColorMaterial=> {
-                return PipelineType::SolidColorMaterial
-            }
+    pub(crate) fn create_bind_group_layout(renderer_state: &WGPUState, material_type: &MaterialType) -> wgpu::BindGroupLayout {
+        match material_type {
+            MaterialType::SolidColorMaterial => SolidColorMaterial::get_bind_group_layout(renderer_state),
+            MaterialType::PbrMaterial => PbrMaterial::get_bind_group_layout(renderer_state),
+            MaterialType::TexturedMaterial => TexturedMaterial::get_bind_group_layout(renderer_state)
+        }
+    }
+
+    // The topology comes from the geometry the material is drawn with
+    pub(crate) fn get_pipeline_key(&self, target: &TargetState, topology: Topology) -> PipelineKey {
+        let (vert_shader, frag_shader, vertex_layout) = match &self.material_type {
+            MaterialType::SolidColorMaterial => (SolidColorMaterial::VERT_SHADER, SolidColorMaterial::FRAG_SHADER, VertexLayout::Position),
+            MaterialType::PbrMaterial => (PbrMaterial::VERT_SHADER, PbrMaterial::FRAG_SHADER, VertexLayout::PositionNormal),
+            MaterialType::TexturedMaterial => (TexturedMaterial::VERT_SHADER, TexturedMaterial::FRAG_SHADER, VertexLayout::PositionNormalUv)
+        };
+
+        PipelineKey {
+            vert_shader,
+            frag_shader: Some(frag_shader),
+            material_type: self.material_type,
+            vertex_layout,
+            topology: topology.primitive_topology(),
+            blend: self.alpha_mode.blend_state(),
+            depth: target.depth_format.map(|format| DepthState {
+                format,
+                write_enabled: !self.alpha_mode.is_transparent(),
+                compare: wgpu::CompareFunction::Less
+            }),
+            rasterizer: self.rasterizer,
+            sample_count: target.sample_count,
+            target_format: target.format
         }
     }
 
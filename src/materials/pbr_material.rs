@@ -0,0 +1,108 @@
+use wgpu::util::DeviceExt;
+use crate::shaders::{ShaderType};
+use crate::WGPUState;
+use crate::materials::{
+    AlphaMode,
+    RenderProperties,
+    MaterialBuffers
+};
+
+// Metallic/roughness shading. Its only light for now is the scene's environment.
+pub struct PbrMaterial {}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PbrUniforms {
+    albedo: [f32; 4],
+    alpha_cutoff: f32,
+    metallic: f32,
+    roughness: f32,
+    _padding: f32
+}
+
+impl PbrUniforms {
+    fn new(render_properties: &RenderProperties, alpha_mode: AlphaMode) -> Self {
+        Self {
+            albedo: render_properties.albedo.to_array(),
+            alpha_cutoff: alpha_mode.alpha_cutoff(),
+            metallic: render_properties.metallic,
+            roughness: render_properties.roughness,
+            _padding: 0.0
+        }
+    }
+}
+
+impl PbrMaterial {
+    pub const VERT_SHADER: ShaderType = ShaderType::PbrVert;
+    pub const FRAG_SHADER: ShaderType = ShaderType::PbrFrag;
+
+    pub fn get_bind_group_layout(renderer_state: &WGPUState) -> wgpu::BindGroupLayout {
+        renderer_state.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("uniform_bind_group_layout"),
+            })
+    }
+
+    pub(in crate::materials) fn create_buffers(renderer_state: &WGPUState, render_properties: &RenderProperties) -> MaterialBuffers {
+        let uniform_buffer = renderer_state.device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Uniform Buffer"),
+                contents: bytemuck::cast_slice(&[PbrUniforms::new(render_properties, AlphaMode::default())]),
+                usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            }
+        );
+
+        let uniform_bind_group_layout = renderer_state.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }
+            ],
+            label: Some("uniform_bind_group_layout"),
+        });
+        
+        let uniform_bind_group = renderer_state.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &uniform_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                }
+            ],
+            label: Some("uniform_bind_group"),
+        });
+
+        MaterialBuffers {
+            uniform_buffer,
+            uniform_bind_group
+        }   
+    }
+
+    pub(in crate::materials) fn write_buffers(renderer_state: &WGPUState, 
+                                              render_properties: &RenderProperties, 
+                                              alpha_mode: AlphaMode,
+                                              material_buffers: &MaterialBuffers) {
+        renderer_state.queue.write_buffer(
+            &material_buffers.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[PbrUniforms::new(render_properties, alpha_mode)]),
+        );
+    }
+
+}
\ No newline at end of file
@@ -1,20 +1,38 @@
 use wgpu::util::DeviceExt;
-use crate::pipelines::{
-    PipelineConfig
-};
 use crate::shaders::{ShaderType};
 use crate::WGPUState;
 use crate::materials::{
+    AlphaMode,
     RenderProperties,
     MaterialBuffers
 };
 
 pub struct SolidColorMaterial {}
 
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SolidColorUniforms {
+    albedo: [f32; 4],
+    alpha_cutoff: f32,
+    _padding: [f32; 3]
+}
+
+impl SolidColorUniforms {
+    fn new(render_properties: &RenderProperties, alpha_mode: AlphaMode) -> Self {
+        Self {
+            albedo: render_properties.albedo.to_array(),
+            alpha_cutoff: alpha_mode.alpha_cutoff(),
+            _padding: [0.0; 3]
+        }
+    }
+}
+
 impl SolidColorMaterial {
-    pub fn get_pipeline_config(renderer_state: &WGPUState) -> PipelineConfig {
-        let uniform_bind_group_layout =
-            renderer_state.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+    pub const VERT_SHADER: ShaderType = ShaderType::BasicVert;
+    pub const FRAG_SHADER: ShaderType = ShaderType::BasicFrag;
+
+    pub fn get_bind_group_layout(renderer_state: &WGPUState) -> wgpu::BindGroupLayout {
+        renderer_state.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[wgpu::BindGroupLayoutEntry {
                     binding: 0,
                     visibility: wgpu::ShaderStage::FRAGMENT,
@@ -26,20 +44,14 @@ impl SolidColorMaterial {
                     count: None,
                 }],
                 label: Some("uniform_bind_group_layout"),
-            });
-
-        PipelineConfig {
-            vert_shader: ShaderType::BasicVert,
-            frag_shader: Some(ShaderType::BasicFrag),
-            uniform_buffer_layout: Some(uniform_bind_group_layout)
-        }
+            })
     }
 
     pub(in crate::materials) fn create_buffers(renderer_state: &WGPUState, render_properties: &RenderProperties) -> MaterialBuffers {
         let uniform_buffer = renderer_state.device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
                 label: Some("Uniform Buffer"),
-                contents: bytemuck::cast_slice(&render_properties.albedo.to_array()),
+                contents: bytemuck::cast_slice(&[SolidColorUniforms::new(render_properties, AlphaMode::default())]),
                 usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
             }
         );
@@ -77,4 +89,15 @@ impl SolidColorMaterial {
         }   
     }
 
+    pub(in crate::materials) fn write_buffers(renderer_state: &WGPUState, 
+                                              render_properties: &RenderProperties, 
+                                              alpha_mode: AlphaMode,
+                                              material_buffers: &MaterialBuffers) {
+        renderer_state.queue.write_buffer(
+            &material_buffers.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[SolidColorUniforms::new(render_properties, alpha_mode)]),
+        );
+    }
+
 }
\ No newline at end of file
@@ -0,0 +1,163 @@
+use std::num::NonZeroU32;
+use wgpu::util::DeviceExt;
+use crate::shaders::{ShaderType};
+use crate::WGPUState;
+use crate::capture::Image;
+use crate::materials::{
+    AlphaMode,
+    RenderProperties,
+    MaterialBuffers
+};
+
+// Unlit like SolidColorMaterial, with the albedo multiplied by an sRGB texture
+pub struct TexturedMaterial {}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TexturedUniforms {
+    albedo: [f32; 4],
+    alpha_cutoff: f32,
+    _padding: [f32; 3]
+}
+
+impl TexturedUniforms {
+    fn new(render_properties: &RenderProperties, alpha_mode: AlphaMode) -> Self {
+        Self {
+            albedo: render_properties.albedo.to_array(),
+            alpha_cutoff: alpha_mode.alpha_cutoff(),
+            _padding: [0.0; 3]
+        }
+    }
+}
+
+impl TexturedMaterial {
+    pub const VERT_SHADER: ShaderType = ShaderType::TexturedVert;
+    pub const FRAG_SHADER: ShaderType = ShaderType::TexturedFrag;
+
+    pub fn get_bind_group_layout(renderer_state: &WGPUState) -> wgpu::BindGroupLayout {
+        renderer_state.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler {
+                        filtering: true,
+                        comparison: false,
+                    },
+                    count: None,
+                }
+            ],
+            label: Some("textured_bind_group_layout"),
+        })
+    }
+
+    // Without a texture it's a white pixel, which draws the same as SolidColorMaterial
+    pub(in crate::materials) fn create_buffers(renderer_state: &WGPUState, render_properties: &RenderProperties, texture: Option<&Image>) -> MaterialBuffers {
+        let white = Image { width: 1, height: 1, pixels: vec![255; 4] };
+        let texture = texture.unwrap_or(&white);
+
+        let uniform_buffer = renderer_state.device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Uniform Buffer"),
+                contents: bytemuck::cast_slice(&[TexturedUniforms::new(render_properties, AlphaMode::default())]),
+                usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            }
+        );
+
+        let size = wgpu::Extent3d {
+            width: texture.width,
+            height: texture.height,
+            depth_or_array_layers: 1
+        };
+        let albedo_texture = renderer_state.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("albedo_texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST
+        });
+        renderer_state.queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &albedo_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            &texture.pixels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(4 * texture.width),
+                rows_per_image: NonZeroU32::new(texture.height),
+            },
+            size
+        );
+        let view = albedo_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // UVs outside 0-1 tile, which is what OBJ files expect
+        let sampler = renderer_state.device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("albedo_sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        // The bind group keeps the texture and sampler alive
+        let uniform_bind_group = renderer_state.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &TexturedMaterial::get_bind_group_layout(renderer_state),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                }
+            ],
+            label: Some("textured_bind_group"),
+        });
+
+        MaterialBuffers {
+            uniform_buffer,
+            uniform_bind_group
+        }
+    }
+
+    pub(in crate::materials) fn write_buffers(renderer_state: &WGPUState,
+                                              render_properties: &RenderProperties,
+                                              alpha_mode: AlphaMode,
+                                              material_buffers: &MaterialBuffers) {
+        renderer_state.queue.write_buffer(
+            &material_buffers.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[TexturedUniforms::new(render_properties, alpha_mode)]),
+        );
+    }
+}
@@ -8,28 +8,48 @@ use crate::materials::{
     MaterialType
 };
 use crate::pipelines::PipelineStore;
+use crate::lod::Lod;
+use crate::RendererError;
 use std::{mem::size_of_val};
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub struct Mesh {
     pub geometry: GeometryHandle,
-    pub material: Material
+    pub material: Material,
+    pub transform: glam::Mat4,
+    // Lower detail geometry to swap in further away, picked every draw
+    pub lod: Option<Lod>
 }
 
 impl Mesh {
     pub fn new(geometry: GeometryHandle, material: Material) -> Self {
         Self {
             geometry,
-            material
+            material,
+            transform: glam::Mat4::IDENTITY,
+            lod: None
         }
     }
 
-    pub fn render<'a, 'b>(&'a self, 
-                          renderpass: &mut wgpu::RenderPass<'b>)
+    // Level 0 is the mesh's own geometry
+    pub(crate) fn lod_geometry(&self, level: usize) -> GeometryHandle {
+        match (level, &self.lod) {
+            (0, _) | (_, None) => self.geometry,
+            (level, Some(lod)) => lod.levels.get(level - 1).map_or(self.geometry, |lod_level| lod_level.geometry)
+        }
+    }
+
+    // Draws geometry, which is this mesh's own or one of its LODs
+    pub(crate) fn render<'b>(&self,
+                             geometry: GeometryHandle,
+                             geometry_store: &GeometryStore,
+                             renderpass: &mut wgpu::RenderPass<'b>) -> Result<(), RendererError>
     {
-        let start = self.geometry.indices_range.start as u32;
-        let end = start + self.geometry.indices_range.size as u32;
-        let offset = self.geometry.vertex_position_range.start as i32;
+        let geometry = geometry_store.get(geometry)?;
+        let start = geometry.indices_range.start as u32;
+        let end = start + geometry.indices_range.size as u32;
+        let offset = geometry.vertex_position_range.start as i32;
         renderpass.draw_indexed(start..end, offset, 0..1);
+        Ok(())
     }   
 }
\ No newline at end of file
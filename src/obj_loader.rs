@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use crate::error::RendererError;
+use crate::geometry::Geometry;
+use crate::gltf_loader::UriResolver;
+
+// The parts of an MTL material the renderer can use
+#[derive(Debug, Clone)]
+pub(crate) struct MtlMaterial {
+    // Kd, with d (or 1 - Tr) as alpha
+    pub albedo: glam::Vec4,
+    // map_Kd, as written in the file. Only PNGs are read.
+    pub diffuse_texture: Option<String>
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        Self {
+            albedo: glam::Vec4::ONE,
+            diffuse_texture: None
+        }
+    }
+}
+
+// One per object, group or material change that has faces
+pub(crate) struct ObjPrimitive {
+    pub geometry: Geometry,
+    pub material: Option<MtlMaterial>
+}
+
+// A face corner's position, uv and normal indices, already made zero based
+type Corner = (usize, Option<usize>, Option<usize>);
+
+#[derive(Default)]
+struct PrimitiveBuilder {
+    material: Option<String>,
+    corners: Vec<Corner>,
+    // Faces as fans of corners, triangulated when the primitive is finished
+    faces: Vec<std::ops::Range<usize>>
+}
+
+// Triangulates polygons as fans and gives every distinct position/uv/normal combination
+// its own vertex
+pub(crate) fn read(bytes: &[u8], resolver: &dyn UriResolver) -> Result<Vec<ObjPrimitive>, RendererError> {
+    let text = String::from_utf8_lossy(bytes);
+
+    let mut positions = Vec::new();
+    let mut uvs = Vec::new();
+    let mut normals = Vec::new();
+    let mut materials = HashMap::new();
+    let mut finished = Vec::new();
+    let mut current = PrimitiveBuilder::default();
+
+    for (line_number, line) in text.lines().enumerate() {
+        let invalid = |reason: &str| RendererError::InvalidAsset(format!("obj line {}: {}", line_number + 1, reason));
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut parts = line.split_whitespace();
+        let keyword = match parts.next() {
+            Some(keyword) => keyword,
+            None => continue
+        };
+        let args: Vec<&str> = parts.collect();
+
+        match keyword {
+            "v" => positions.push(parse_vec3(&args).ok_or_else(|| invalid("bad vertex"))?),
+            "vn" => normals.push(parse_vec3(&args).ok_or_else(|| invalid("bad normal"))?),
+            "vt" => {
+                let u = args.first().and_then(|u| u.parse::<f32>().ok()).ok_or_else(|| invalid("bad uv"))?;
+                let v = args.get(1).and_then(|v| v.parse::<f32>().ok()).unwrap_or(0.0);
+                // OBJ puts v = 0 at the bottom
+                uvs.push(glam::Vec2::new(u, 1.0 - v));
+            },
+            "f" => {
+                if args.len() < 3 {
+                    return Err(invalid("faces need at least 3 corners"));
+                }
+                let start = current.corners.len();
+                for corner in &args {
+                    let corner = parse_corner(corner, positions.len(), uvs.len(), normals.len())
+                        .ok_or_else(|| invalid("bad face corner"))?;
+                    current.corners.push(corner);
+                }
+                current.faces.push(start..current.corners.len());
+            },
+            "o" | "g" => {
+                let material = current.material.clone();
+                finish_primitive(&mut finished, &mut current);
+                current.material = material;
+            },
+            "usemtl" => {
+                finish_primitive(&mut finished, &mut current);
+                // Names can have spaces, the same as newmtl reads them
+                current.material = Some(args.join(" ")).filter(|name| !name.is_empty());
+            },
+            "mtllib" => {
+                for library in &args {
+                    let library = resolver.resolve(library)?;
+                    materials.extend(read_mtl(&library));
+                }
+            },
+            // Smoothing groups, lines, points and the rest aren't used
+            _ => {}
+        }
+    }
+    finish_primitive(&mut finished, &mut current);
+
+    Ok(finished.into_iter()
+        .map(|builder| ObjPrimitive {
+            geometry: build_geometry(&builder, &positions, &uvs, &normals),
+            material: builder.material.as_ref().map(|name| materials.get(name).cloned().unwrap_or_else(|| {
+                log::warn!("obj material {} isn't in any mtllib", name);
+                MtlMaterial::default()
+            }))
+        })
+        .collect())
+}
+
+// Everything in the file as one geometry with the first primitive's material, for places that
+// only take one mesh. Normals and uvs are kept only if every primitive has them.
+pub(crate) fn read_merged(bytes: &[u8], resolver: &dyn UriResolver) -> Result<ObjPrimitive, RendererError> {
+    let primitives = read(bytes, resolver)?;
+    let material = primitives.first().and_then(|primitive| primitive.material.clone());
+    let has_normals = primitives.iter().all(|primitive| !primitive.geometry.vertex_normals.is_empty());
+    let has_uvs = primitives.iter().all(|primitive| !primitive.geometry.vertex_uvs.is_empty());
+
+    let mut geometry = Geometry::default();
+    for primitive in primitives {
+        let offset = geometry.vertex_positions.len() as u32;
+        geometry.indices.extend(primitive.geometry.indices.iter().map(|index| index + offset));
+        geometry.vertex_positions.extend(primitive.geometry.vertex_positions);
+        if has_normals {
+            geometry.vertex_normals.extend(primitive.geometry.vertex_normals);
+        }
+        if has_uvs {
+            geometry.vertex_uvs.extend(primitive.geometry.vertex_uvs);
+        }
+    }
+    if geometry.indices.is_empty() {
+        return Err(RendererError::InvalidAsset("obj: no faces".to_string()));
+    }
+
+    Ok(ObjPrimitive { geometry, material })
+}
+
+fn finish_primitive(finished: &mut Vec<PrimitiveBuilder>, current: &mut PrimitiveBuilder) {
+    let builder = std::mem::take(current);
+    if !builder.faces.is_empty() {
+        finished.push(builder);
+    }
+}
+
+fn build_geometry(builder: &PrimitiveBuilder, positions: &[glam::Vec3], uvs: &[glam::Vec2], normals: &[glam::Vec3]) -> Geometry {
+    let mut vertices: HashMap<Corner, u32> = HashMap::new();
+    let mut geometry = Geometry::default();
+    // Normals and uvs are all or nothing, missing normals get computed when the geometry is loaded
+    let has_uvs = builder.corners.iter().all(|(_, uv, _)| uv.is_some());
+    let has_normals = builder.corners.iter().all(|(_, _, normal)| normal.is_some());
+
+    let mut vertex_index = |corner: Corner, geometry: &mut Geometry| -> u32 {
+        *vertices.entry(corner).or_insert_with(|| {
+            let (position, uv, normal) = corner;
+            geometry.vertex_positions.push(positions[position]);
+            if has_uvs {
+                geometry.vertex_uvs.push(uvs[uv.unwrap()]);
+            }
+            if has_normals {
+                geometry.vertex_normals.push(normals[normal.unwrap()]);
+            }
+            (geometry.vertex_positions.len() - 1) as u32
+        })
+    };
+
+    for face in &builder.faces {
+        let corners = &builder.corners[face.clone()];
+        let first = vertex_index(corners[0], &mut geometry);
+        for pair in corners[1..].windows(2) {
+            let second = vertex_index(pair[0], &mut geometry);
+            let third = vertex_index(pair[1], &mut geometry);
+            geometry.indices.extend_from_slice(&[first, second, third]);
+        }
+    }
+
+    geometry
+}
+
+fn parse_vec3(args: &[&str]) -> Option<glam::Vec3> {
+    if args.len() < 3 {
+        return None;
+    }
+    Some(glam::Vec3::new(args[0].parse().ok()?, args[1].parse().ok()?, args[2].parse().ok()?))
+}
+
+// v, v/vt, v//vn or v/vt/vn. Indices start at 1, negative ones count back from the end.
+fn parse_corner(corner: &str, position_count: usize, uv_count: usize, normal_count: usize) -> Option<Corner> {
+    let resolve = |index: &str, count: usize| -> Option<usize> {
+        let index: i64 = index.parse().ok()?;
+        let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+        if resolved >= 0 && (resolved as usize) < count { Some(resolved as usize) } else { None }
+    };
+
+    let mut parts = corner.split('/');
+    let position = resolve(parts.next()?, position_count)?;
+    let uv = match parts.next() {
+        Some("") | None => None,
+        Some(uv) => Some(resolve(uv, uv_count)?)
+    };
+    let normal = match parts.next() {
+        Some("") | None => None,
+        Some(normal) => Some(resolve(normal, normal_count)?)
+    };
+
+    Some((position, uv, normal))
+}
+
+// Anything it doesn't understand is skipped rather than failing the model
+fn read_mtl(bytes: &[u8]) -> HashMap<String, MtlMaterial> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut parts = line.split_whitespace();
+        let keyword = match parts.next() {
+            Some(keyword) => keyword,
+            None => continue
+        };
+        let args: Vec<&str> = parts.collect();
+
+        if keyword == "newmtl" {
+            if let Some((name, material)) = current.take() {
+                materials.insert(name, material);
+            }
+            current = Some((args.join(" "), MtlMaterial::default()));
+            continue;
+        }
+
+        let material = match &mut current {
+            Some((_, material)) => material,
+            None => continue
+        };
+        match keyword {
+            "Kd" => {
+                if let Some(color) = parse_vec3(&args) {
+                    material.albedo = color.extend(material.albedo.w);
+                }
+            },
+            "d" => {
+                if let Some(alpha) = args.first().and_then(|d| d.parse::<f32>().ok()) {
+                    material.albedo.w = alpha;
+                }
+            },
+            "Tr" => {
+                if let Some(transparency) = args.first().and_then(|tr| tr.parse::<f32>().ok()) {
+                    material.albedo.w = 1.0 - transparency;
+                }
+            },
+            // Options like -s come before the file name, which is always last
+            "map_Kd" => material.diffuse_texture = args.last().map(|name| name.to_string()),
+            _ => {}
+        }
+    }
+    if let Some((name, material)) = current {
+        materials.insert(name, material);
+    }
+
+    materials
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::{Vec2, Vec3, Vec4};
+
+    const MTL: &str = "\
+newmtl red paint
+Kd 1 0 0
+d 0.5
+map_Kd -s 2 2 1 red.png
+
+newmtl blue
+Kd 0 0 1
+Tr 0.25
+";
+
+    fn resolve(uri: &str) -> Result<Vec<u8>, RendererError> {
+        match uri {
+            "materials.mtl" => Ok(MTL.as_bytes().to_vec()),
+            _ => Err(RendererError::InvalidAsset(format!("no {}", uri)))
+        }
+    }
+
+    #[test]
+    fn groups_and_materials_split_primitives() {
+        let obj = "\
+mtllib materials.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+o first
+usemtl red paint
+f 1 2 3
+g second
+f 1 3 4
+usemtl blue
+f 1 2 4
+usemtl missing
+f 2 3 4
+";
+        let primitives = read(obj.as_bytes(), &resolve).unwrap();
+        assert_eq!(primitives.len(), 4);
+        assert!(primitives.iter().all(|primitive| primitive.geometry.indices.len() == 3));
+
+        // The material carries on into the next group
+        let red = primitives[0].material.as_ref().unwrap();
+        assert_eq!(red.albedo, Vec4::new(1.0, 0.0, 0.0, 0.5));
+        assert_eq!(red.diffuse_texture.as_deref(), Some("red.png"));
+        assert_eq!(primitives[1].material.as_ref().unwrap().albedo, red.albedo);
+
+        let blue = primitives[2].material.as_ref().unwrap();
+        assert_eq!(blue.albedo, Vec4::new(0.0, 0.0, 1.0, 0.75));
+        assert_eq!(blue.diffuse_texture, None);
+        // Unknown materials fall back to white
+        assert_eq!(primitives[3].material.as_ref().unwrap().albedo, Vec4::ONE);
+    }
+
+    #[test]
+    fn merges_into_one_geometry() {
+        let obj = "\
+mtllib materials.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+usemtl blue
+f 1 2 3
+g other
+usemtl red paint
+f 1 3 4
+";
+        let merged = read_merged(obj.as_bytes(), &resolve).unwrap();
+        assert_eq!(merged.geometry.vertex_positions.len(), 6);
+        assert_eq!(merged.geometry.indices, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(merged.material.unwrap().albedo, Vec4::new(0.0, 0.0, 1.0, 0.75));
+        assert!(read_merged(b"v 0 0 0\n", &resolve).is_err());
+    }
+
+    #[test]
+    fn negative_indices_count_back() {
+        let obj = "\
+v 0 0 0
+v 1 0 0
+v 1 1 0
+vt 0 0
+vt 1 1
+vn 0 0 1
+f -3/-2/-1 -2/-2/-1 -1/-1/-1
+";
+        let primitives = read(obj.as_bytes(), &resolve).unwrap();
+        let geometry = &primitives[0].geometry;
+        assert_eq!(geometry.vertex_positions, vec![Vec3::ZERO, Vec3::X, Vec3::new(1.0, 1.0, 0.0)]);
+        // v is flipped so (0, 0) is the top left
+        assert_eq!(geometry.vertex_uvs, vec![Vec2::Y, Vec2::Y, Vec2::X]);
+        assert_eq!(geometry.vertex_normals, vec![Vec3::Z; 3]);
+        assert_eq!(geometry.indices, vec![0, 1, 2]);
+
+        // Past the start of what's been read so far
+        assert!(read(b"v 0 0 0\nv 1 0 0\nf -1 -2 -3\n", &resolve).is_err());
+        assert!(read(b"v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 4\n", &resolve).is_err());
+    }
+
+    #[test]
+    fn shared_corners_are_deduplicated() {
+        // A quad fanned into two triangles, plus a corner reused with a different normal
+        let obj = "\
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vn 0 0 1
+vn 0 0 -1
+f 1//1 2//1 3//1 4//1
+f 1//2 3//2 2//2
+";
+        let primitives = read(obj.as_bytes(), &resolve).unwrap();
+        let geometry = &primitives[0].geometry;
+        assert_eq!(geometry.indices.len(), 9);
+        // 4 corners facing +Z and 3 facing -Z, same positions but not the same vertices
+        assert_eq!(geometry.vertex_positions.len(), 7);
+        assert_eq!(&geometry.indices[..6], &[0, 1, 2, 0, 2, 3]);
+        assert!(geometry.indices[6..].iter().all(|&index| index >= 4));
+    }
+}
@@ -1,51 +1,163 @@
 use std::collections::HashMap;
-use wgpu::util::DeviceExt;
 use crate::WGPUState;
 use crate::shaders::{ShaderStore, ShaderType};
-use crate::materials::SolidColorMaterial;
+use crate::materials::{Material, MaterialType, RasterizerState};
 
-pub struct PipelineConfig {
-    pub vert_shader: ShaderType,
-    pub frag_shader: Option<ShaderType>,
-    pub uniform_buffer_layout: Option<wgpu::BindGroupLayout>
+// Where a pass draws to. This is the part of a PipelineKey that comes from the target
+// rather than the material, so the same material drawn into two targets gets two pipelines.
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+pub struct TargetState {
+    pub format: wgpu::TextureFormat,
+    pub depth_format: Option<wgpu::TextureFormat>,
+    pub sample_count: u32
+}
+
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+pub enum VertexLayout {
+    Position,
+    // Positions in slot 0 and normals in slot 1
+    PositionNormal,
+    // Then uvs in slot 2
+    PositionNormalUv
+}
+
+impl VertexLayout {
+    fn buffers(&self) -> Vec<wgpu::VertexBufferLayout<'static>> {
+        match self {
+            VertexLayout::Position => vec![
+                wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: &[
+                        wgpu::VertexAttribute {
+                            offset: 0,
+                            shader_location: 0,
+                            format: wgpu::VertexFormat::Float32x3,
+                        }
+                    ],
+                }
+            ],
+            VertexLayout::PositionNormal => vec![
+                wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: &[
+                        wgpu::VertexAttribute {
+                            offset: 0,
+                            shader_location: 0,
+                            format: wgpu::VertexFormat::Float32x3,
+                        }
+                    ],
+                },
+                wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: &[
+                        wgpu::VertexAttribute {
+                            offset: 0,
+                            shader_location: 1,
+                            format: wgpu::VertexFormat::Float32x3,
+                        }
+                    ],
+                }
+            ],
+            VertexLayout::PositionNormalUv => {
+                let mut buffers = VertexLayout::PositionNormal.buffers();
+                buffers.push(wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: &[
+                        wgpu::VertexAttribute {
+                            offset: 0,
+                            shader_location: 2,
+                            format: wgpu::VertexFormat::Float32x2,
+                        }
+                    ],
+                });
+                buffers
+            }
+        }
+    }
+}
+
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+pub struct DepthState {
+    pub format: wgpu::TextureFormat,
+    pub write_enabled: bool,
+    pub compare: wgpu::CompareFunction
 }
 
-#[derive(Hash, Eq, PartialEq, Debug, Clone)]
-pub enum PipelineType {
-    SolidColorMaterial
+// Everything that goes into a render pipeline. The material type stands in for the
+// material's bind group layout.
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+pub struct PipelineKey {
+    pub vert_shader: ShaderType,
+    pub frag_shader: Option<ShaderType>,
+    pub material_type: MaterialType,
+    pub vertex_layout: VertexLayout,
+    pub topology: wgpu::PrimitiveTopology,
+    pub blend: Option<wgpu::BlendState>,
+    pub depth: Option<DepthState>,
+    pub rasterizer: RasterizerState,
+    pub sample_count: u32,
+    pub target_format: wgpu::TextureFormat
 }
 
+// One pipeline per distinct key, built the first time the key is asked for.
+// Opaque and Mask only differ in the material's cutoff, so they end up sharing one.
 pub struct PipelineStore {
-    store: HashMap<PipelineType, wgpu::RenderPipeline>
+    store: HashMap<PipelineKey, wgpu::RenderPipeline>,
+    // Every pipeline for a material type shares its layout rather than making its own
+    material_layouts: HashMap<MaterialType, wgpu::BindGroupLayout>
 }
 
 impl PipelineStore {
-    pub fn new(renderer_state: &WGPUState, shaders: &ShaderStore) -> Self {
-        let mut store = HashMap::new();
-    
-        let pipeline_config = SolidColorMaterial::get_pipeline_config(renderer_state);        
-        store.insert(PipelineType::SolidColorMaterial, 
-                     PipelineStore::create_pipeline(renderer_state, shaders, pipeline_config));
-        
+    pub fn new() -> Self {
         Self {
-            store
+            store: HashMap::new(),
+            material_layouts: HashMap::new()
+        }
+    }
+
+    // Pipelines have to exist before a render pass borrows them, so this runs ahead of the pass.
+    // scene_layouts are the bind groups shared by every pipeline, bound after the material's
+    pub fn prepare(&mut self,
+                   renderer_state: &WGPUState,
+                   shaders: &ShaderStore,
+                   scene_layouts: &[&wgpu::BindGroupLayout],
+                   key: &PipelineKey)
+    {
+        if self.store.contains_key(key) {
+            return;
         }
+
+        let material_layout = self.material_layouts.entry(key.material_type)
+            .or_insert_with(|| Material::create_bind_group_layout(renderer_state, &key.material_type));
+        let pipeline = PipelineStore::create_pipeline(renderer_state, shaders, material_layout, scene_layouts, key);
+        self.store.insert(*key, pipeline);
     }
 
-    pub fn get(&self, pipeline_type: PipelineType) -> &wgpu::RenderPipeline{
-        self.store.get(&pipeline_type).unwrap()
+    pub fn get(&self, key: &PipelineKey) -> Option<&wgpu::RenderPipeline> {
+        self.store.get(key)
     }
 
     fn create_pipeline(renderer_state : &WGPUState, 
                        shader_store: &ShaderStore,
-                       pipeline_config: PipelineConfig) -> wgpu::RenderPipeline              
+                       material_layout: &wgpu::BindGroupLayout,
+                       scene_layouts: &[&wgpu::BindGroupLayout],
+                       key: &PipelineKey) -> wgpu::RenderPipeline              
     {
-
-        let mut layouts = Vec::<&wgpu::BindGroupLayout>::new();
-        if let Some(ref uniform_buffer_layout) = pipeline_config.uniform_buffer_layout {
-            layouts.push(uniform_buffer_layout);
+        // Line and point modes are an optional feature, draw filled rather than fail
+        let mut polygon_mode = key.rasterizer.polygon_mode;
+        if polygon_mode != wgpu::PolygonMode::Fill && !renderer_state.features.contains(wgpu::Features::NON_FILL_POLYGON_MODE) {
+            log::warn!("{:?} polygon mode isn't supported by this adapter, falling back to Fill", polygon_mode);
+            polygon_mode = wgpu::PolygonMode::Fill;
         }
 
+        // Set 0 is the material, then the scene's per-object, camera and environment data
+        let mut layouts = vec![material_layout];
+        layouts.extend_from_slice(scene_layouts);
+
         let render_pipeline_layout =
             renderer_state.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
@@ -53,54 +165,48 @@ impl PipelineStore {
                 push_constant_ranges: &[],
             });
         
-        let swapchain_format : [wgpu::ColorTargetState; 1] = [renderer_state.swapchain_format.into()];
-        let fragment_shader_module = if let Some(frag_shader) = pipeline_config.frag_shader {
-            Some(wgpu::FragmentState {
-                module: &shader_store.get(frag_shader),
+        let target_format : [wgpu::ColorTargetState; 1] = [wgpu::ColorTargetState {
+            format: key.target_format,
+            blend: key.blend,
+            write_mask: wgpu::ColorWrite::ALL
+        }];
+        let fragment_shader_module = key.frag_shader.map(|frag_shader| {
+            wgpu::FragmentState {
+                module: shader_store.get(frag_shader),
                 entry_point: "main",
-                targets: &swapchain_format
-            })
-        }
-        else {
-            None
-        };
-
-        let vertex_buffer_layout = wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-            step_mode: wgpu::InputStepMode::Vertex,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 0,
-                    format: wgpu::VertexFormat::Float32x3,
-                }
-            ],
-        };
+                targets: &target_format
+            }
+        });
+
+        let vertex_buffers = key.vertex_layout.buffers();
 
         let render_pipeline = renderer_state.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
                 label: Some("Render Pipeline"),
                 layout: Some(&render_pipeline_layout),
                 vertex: wgpu::VertexState {
-                    module: &shader_store.get(pipeline_config.vert_shader),
+                    module: shader_store.get(key.vert_shader),
                     entry_point: "main", 
-                    buffers: &[
-                        vertex_buffer_layout
-                        //InstanceRaw::desc()
-                    ], 
+                    buffers: &vertex_buffers, 
                 },
                 fragment: fragment_shader_module,
                 primitive: wgpu::PrimitiveState {
-                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    topology: key.topology,
                     strip_index_format: None,
-                    front_face: wgpu::FrontFace::Ccw, 
-                    cull_mode: Some(wgpu::Face::Back),
-                    polygon_mode: wgpu::PolygonMode::Fill,
+                    front_face: key.rasterizer.front_face, 
+                    cull_mode: key.rasterizer.cull_mode,
+                    polygon_mode,
                     clamp_depth: false,
                     conservative: false
                 },
-                depth_stencil: None,
+                depth_stencil: key.depth.map(|depth| wgpu::DepthStencilState {
+                    format: depth.format,
+                    depth_write_enabled: depth.write_enabled,
+                    depth_compare: depth.compare,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
                 multisample: wgpu::MultisampleState {
-                    count: 1, 
+                    count: key.sample_count, 
                     mask: !0, 
                     alpha_to_coverage_enabled: false, 
                 },
@@ -0,0 +1,376 @@
+use crate::error::RendererError;
+use crate::geometry::{Geometry, Topology};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "char" | "int8" => Some(ScalarType::I8),
+            "uchar" | "uint8" => Some(ScalarType::U8),
+            "short" | "int16" => Some(ScalarType::I16),
+            "ushort" | "uint16" => Some(ScalarType::U16),
+            "int" | "int32" => Some(ScalarType::I32),
+            "uint" | "uint32" => Some(ScalarType::U32),
+            "float" | "float32" => Some(ScalarType::F32),
+            "double" | "float64" => Some(ScalarType::F64),
+            _ => None
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8
+        }
+    }
+
+    // Colors stored as integers are 0..max, floats are already 0..1
+    fn normalize(&self, value: f64) -> f32 {
+        match self {
+            ScalarType::U8 | ScalarType::I8 => (value / 255.0) as f32,
+            ScalarType::U16 | ScalarType::I16 => (value / 65535.0) as f32,
+            _ => value as f32
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum PropertyType {
+    Scalar(ScalarType),
+    List { count: ScalarType, item: ScalarType }
+}
+
+#[derive(Debug, Clone)]
+struct Property {
+    name: String,
+    ty: PropertyType
+}
+
+#[derive(Debug, Clone)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>
+}
+
+// Reads values one at a time from either encoding
+struct ValueReader<'a> {
+    format: Format,
+    data: &'a [u8],
+    position: usize,
+    tokens: std::str::SplitWhitespace<'a>
+}
+
+impl<'a> ValueReader<'a> {
+    fn read(&mut self, ty: ScalarType) -> Option<f64> {
+        if self.format == Format::Ascii {
+            return self.tokens.next()?.parse().ok();
+        }
+
+        let bytes = self.data.get(self.position..self.position + ty.size())?;
+        self.position += ty.size();
+        let little = self.format == Format::BinaryLittleEndian;
+        macro_rules! decode {
+            ($t:ty, $n:expr) => {{
+                let mut raw = [0u8; $n];
+                raw.copy_from_slice(bytes);
+                (if little { <$t>::from_le_bytes(raw) } else { <$t>::from_be_bytes(raw) }) as f64
+            }};
+        }
+        Some(match ty {
+            ScalarType::I8 => bytes[0] as i8 as f64,
+            ScalarType::U8 => bytes[0] as f64,
+            ScalarType::I16 => decode!(i16, 2),
+            ScalarType::U16 => decode!(u16, 2),
+            ScalarType::I32 => decode!(i32, 4),
+            ScalarType::U32 => decode!(u32, 4),
+            ScalarType::F32 => decode!(f32, 4),
+            ScalarType::F64 => decode!(f64, 8)
+        })
+    }
+}
+
+// ASCII and binary PLY. Reads positions, normals, uvs and colors from the vertex element
+// and polygons from the face element, fanned into triangles. Without faces it's a point cloud.
+pub(crate) fn read(bytes: &[u8]) -> Result<Geometry, RendererError> {
+    let invalid = |reason: &str| RendererError::InvalidAsset(format!("ply: {}", reason));
+
+    let header_end = find_header_end(bytes).ok_or_else(|| invalid("no end_header"))?;
+    let header = String::from_utf8_lossy(&bytes[..header_end]);
+    let mut lines = header.lines();
+    if lines.next().map(str::trim) != Some("ply") {
+        return Err(invalid("missing ply magic"));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts.as_slice() {
+            ["format", "ascii", _] => format = Some(Format::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(Format::BinaryLittleEndian),
+            ["format", "binary_big_endian", _] => format = Some(Format::BinaryBigEndian),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| invalid("bad element count"))?,
+                properties: Vec::new()
+            }),
+            ["property", "list", count, item, name] => {
+                let element = elements.last_mut().ok_or_else(|| invalid("property before any element"))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    ty: PropertyType::List {
+                        count: ScalarType::parse(count).ok_or_else(|| invalid("unknown list count type"))?,
+                        item: ScalarType::parse(item).ok_or_else(|| invalid("unknown list item type"))?
+                    }
+                });
+            },
+            ["property", ty, name] => {
+                let element = elements.last_mut().ok_or_else(|| invalid("property before any element"))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    ty: PropertyType::Scalar(ScalarType::parse(ty).ok_or_else(|| invalid("unknown property type"))?)
+                });
+            },
+            _ => {}
+        }
+    }
+    let format = format.ok_or_else(|| invalid("missing format"))?;
+
+    let body = &bytes[header_end..];
+    let ascii_body = if format == Format::Ascii { std::str::from_utf8(body).map_err(|_| invalid("ascii body isn't utf-8"))? } else { "" };
+    let mut reader = ValueReader {
+        format,
+        data: body,
+        position: 0,
+        tokens: ascii_body.split_whitespace()
+    };
+
+    let mut geometry = Geometry::default();
+    let mut has_faces = false;
+    for element in &elements {
+        match element.name.as_str() {
+            "vertex" => read_vertices(&mut reader, element, &mut geometry).ok_or_else(|| invalid("truncated vertex data"))?,
+            "face" => {
+                has_faces = true;
+                read_faces(&mut reader, element, geometry.vertex_positions.len(), &mut geometry.indices).ok_or_else(|| invalid("bad face data"))?;
+            },
+            // Still has to be read through to get to what comes after it
+            _ => skip_element(&mut reader, element).ok_or_else(|| invalid("truncated element data"))?
+        }
+    }
+
+    if !has_faces {
+        geometry.topology = Topology::Points;
+        geometry.indices = (0..geometry.vertex_positions.len() as u32).collect();
+    }
+
+    Ok(geometry)
+}
+
+// Where the body starts, just past the end_header line
+fn find_header_end(bytes: &[u8]) -> Option<usize> {
+    const END: &[u8] = b"end_header";
+    let at = bytes.windows(END.len()).position(|window| window == END)?;
+    let newline = bytes[at..].iter().position(|b| *b == b'\n')?;
+    Some(at + newline + 1)
+}
+
+fn read_vertices(reader: &mut ValueReader, element: &Element, geometry: &mut Geometry) -> Option<()> {
+    let find = |names: &[&str]| element.properties.iter().position(|property| names.contains(&property.name.as_str()));
+    let position = [find(&["x"])?, find(&["y"])?, find(&["z"])?];
+    let normal = find(&["nx"]).zip(find(&["ny"])).zip(find(&["nz"])).map(|((x, y), z)| [x, y, z]);
+    let uv = find(&["u", "s", "texture_u"]).zip(find(&["v", "t", "texture_v"]));
+    let color = find(&["red", "r"]).zip(find(&["green", "g"])).zip(find(&["blue", "b"])).map(|((r, g), b)| [r, g, b]);
+    let alpha = find(&["alpha", "a"]);
+
+    let mut values = vec![0.0f32; element.properties.len()];
+    for _ in 0..element.count {
+        for (i, property) in element.properties.iter().enumerate() {
+            match property.ty {
+                PropertyType::Scalar(ty) => {
+                    let value = reader.read(ty)?;
+                    let is_color = color.map_or(false, |color| color.contains(&i)) || alpha == Some(i);
+                    values[i] = if is_color { ty.normalize(value) } else { value as f32 };
+                },
+                PropertyType::List { count, item } => {
+                    for _ in 0..reader.read(count)? as usize {
+                        reader.read(item)?;
+                    }
+                }
+            }
+        }
+
+        geometry.vertex_positions.push(glam::Vec3::new(values[position[0]], values[position[1]], values[position[2]]));
+        if let Some(normal) = normal {
+            geometry.vertex_normals.push(glam::Vec3::new(values[normal[0]], values[normal[1]], values[normal[2]]));
+        }
+        if let Some((u, v)) = uv {
+            // PLY puts v = 0 at the bottom like OBJ
+            geometry.vertex_uvs.push(glam::Vec2::new(values[u], 1.0 - values[v]));
+        }
+        if let Some(color) = color {
+            let a = alpha.map(|alpha| values[alpha]).unwrap_or(1.0);
+            geometry.vertex_colors.push(glam::Vec4::new(values[color[0]], values[color[1]], values[color[2]], a));
+        }
+    }
+
+    Some(())
+}
+
+fn read_faces(reader: &mut ValueReader, element: &Element, vertex_count: usize, indices: &mut Vec<u32>) -> Option<()> {
+    let mut polygon = Vec::new();
+    for _ in 0..element.count {
+        for property in &element.properties {
+            match property.ty {
+                PropertyType::List { count, item } => {
+                    polygon.clear();
+                    for _ in 0..reader.read(count)? as usize {
+                        polygon.push(reader.read(item)?);
+                    }
+                    if property.name == "vertex_indices" || property.name == "vertex_index" {
+                        // Checked as read, a negative index cast to u32 would turn into 0
+                        if polygon.iter().any(|index| *index < 0.0 || *index >= vertex_count as f64) {
+                            return None;
+                        }
+                        for i in 1..polygon.len().saturating_sub(1) {
+                            indices.extend_from_slice(&[polygon[0] as u32, polygon[i] as u32, polygon[i + 1] as u32]);
+                        }
+                    }
+                },
+                PropertyType::Scalar(ty) => {
+                    reader.read(ty)?;
+                }
+            }
+        }
+    }
+
+    Some(())
+}
+
+fn skip_element(reader: &mut ValueReader, element: &Element) -> Option<()> {
+    for _ in 0..element.count {
+        for property in &element.properties {
+            match property.ty {
+                PropertyType::Scalar(ty) => {
+                    reader.read(ty)?;
+                },
+                PropertyType::List { count, item } => {
+                    for _ in 0..reader.read(count)? as usize {
+                        reader.read(item)?;
+                    }
+                }
+            }
+        }
+    }
+
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::{Vec3, Vec4};
+
+    const QUAD_HEADER: &str = "ply\nformat {}\ncomment a quad\nelement vertex 4\n\
+        property float x\nproperty float y\nproperty float z\n\
+        property uchar red\nproperty uchar green\nproperty uchar blue\n\
+        element face 1\nproperty list uchar int vertex_indices\nend_header\n";
+    const QUAD_POSITIONS: [Vec3; 4] = [Vec3::ZERO, Vec3::X, Vec3::ONE, Vec3::Y];
+    const QUAD_COLORS: [[u8; 3]; 4] = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 255]];
+
+    fn header(format: &str) -> Vec<u8> {
+        QUAD_HEADER.replace("{}", format).into_bytes()
+    }
+
+    fn check_quad(geometry: &Geometry) {
+        assert_eq!(geometry.topology, Topology::Triangles);
+        assert_eq!(geometry.vertex_positions, QUAD_POSITIONS.to_vec());
+        // The quad is fanned into two triangles
+        assert_eq!(geometry.indices, vec![0, 1, 2, 0, 2, 3]);
+        let colors: Vec<Vec4> = QUAD_COLORS.iter()
+            .map(|color| Vec4::new(color[0] as f32 / 255.0, color[1] as f32 / 255.0, color[2] as f32 / 255.0, 1.0))
+            .collect();
+        assert_eq!(geometry.vertex_colors, colors);
+    }
+
+    #[test]
+    fn reads_ascii() {
+        let mut bytes = header("ascii 1.0");
+        for (position, color) in QUAD_POSITIONS.iter().zip(QUAD_COLORS.iter()) {
+            bytes.extend_from_slice(format!("{} {} {} {} {} {}\n", position.x, position.y, position.z, color[0], color[1], color[2]).as_bytes());
+        }
+        bytes.extend_from_slice(b"4 0 1 2 3\n");
+        check_quad(&read(&bytes).unwrap());
+    }
+
+    #[test]
+    fn reads_binary_either_endianness() {
+        for (format, little) in [("binary_little_endian 1.0", true), ("binary_big_endian 1.0", false)] {
+            let mut bytes = header(format);
+            let f32_bytes = |value: f32| if little { value.to_le_bytes() } else { value.to_be_bytes() };
+            let i32_bytes = |value: i32| if little { value.to_le_bytes() } else { value.to_be_bytes() };
+            for (position, color) in QUAD_POSITIONS.iter().zip(QUAD_COLORS.iter()) {
+                for value in [position.x, position.y, position.z] {
+                    bytes.extend_from_slice(&f32_bytes(value));
+                }
+                bytes.extend_from_slice(color);
+            }
+            bytes.push(4);
+            for index in 0..4 {
+                bytes.extend_from_slice(&i32_bytes(index));
+            }
+            check_quad(&read(&bytes).unwrap());
+        }
+    }
+
+    #[test]
+    fn reads_faceless_files_as_point_clouds() {
+        let bytes = b"ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\nend_header\n\
+            0 0 0\n1 0 0\n0 1 0\n";
+        let mut geometry = read(bytes).unwrap();
+        assert_eq!(geometry.topology, Topology::Points);
+        assert_eq!(geometry.indices, vec![0, 1, 2]);
+        assert!(geometry.vertex_colors.is_empty());
+
+        // The three points aren't a triangle, there's no face to take a normal from
+        geometry.compute_normals();
+        assert_eq!(geometry.vertex_normals, vec![Vec3::Y; 3]);
+    }
+
+    #[test]
+    fn rejects_truncated_and_out_of_range_data() {
+        let mut truncated = header("binary_little_endian 1.0");
+        truncated.extend_from_slice(&[0; 10]);
+        assert!(read(&truncated).is_err());
+
+        let mut out_of_range = header("ascii 1.0");
+        out_of_range.extend_from_slice(b"0 0 0 0 0 0\n1 0 0 0 0 0\n1 1 0 0 0 0\n0 1 0 0 0 0\n3 0 1 4\n");
+        assert!(read(&out_of_range).is_err());
+
+        let mut negative = header("ascii 1.0");
+        negative.extend_from_slice(b"0 0 0 0 0 0\n1 0 0 0 0 0\n1 1 0 0 0 0\n0 1 0 0 0 0\n3 -1 1 2\n");
+        assert!(read(&negative).is_err());
+        assert!(read(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\n").is_err());
+    }
+}
@@ -0,0 +1,316 @@
+use crate::error::RendererError;
+use crate::handles::next_resource_id;
+
+pub enum PostEffect {
+    Bloom(BloomSettings),
+    Fxaa(FxaaSettings),
+    ColorGrading(ColorGradingSettings),
+    Vignette(VignetteSettings),
+    ChromaticAberration(ChromaticAberrationSettings),
+    Custom(CustomEffect)
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct BloomSettings {
+    // Brightness where pixels start to bleed
+    pub threshold: f32,
+    // Width of the soft ramp around the threshold
+    pub knee: f32,
+    pub intensity: f32,
+    // Blur passes over the half resolution bright pass, more is wider
+    pub blur_passes: u32
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            threshold: 0.8,
+            knee: 0.2,
+            intensity: 0.6,
+            blur_passes: 2
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct FxaaSettings {
+    // Longest edge search in texels
+    pub span_max: f32,
+    pub reduce_mul: f32,
+    pub reduce_min: f32
+}
+
+impl Default for FxaaSettings {
+    fn default() -> Self {
+        Self {
+            span_max: 8.0,
+            reduce_mul: 1.0 / 8.0,
+            reduce_min: 1.0 / 128.0
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ColorGradingSettings {
+    pub lut: ColorGradingLut,
+    // 0 leaves the image alone, 1 is fully graded
+    pub intensity: f32
+}
+
+// A size^3 RGBA8 lookup table, red varying fastest, then green, then blue.
+#[derive(Debug, Clone)]
+pub struct ColorGradingLut {
+    size: u32,
+    data: Vec<[u8; 4]>,
+    id: u64
+}
+
+impl ColorGradingLut {
+    pub fn new(size: u32, data: Vec<[u8; 4]>) -> Option<Self> {
+        if size < 2 || data.len() != (size * size * size) as usize {
+            return None;
+        }
+
+        Some(Self {
+            size,
+            data,
+            id: next_resource_id()
+        })
+    }
+
+    pub fn identity(size: u32) -> Self {
+        let size = size.max(2);
+        let max = (size - 1) as f32;
+        let mut data = Vec::with_capacity((size * size * size) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.push([
+                        (r as f32 / max * 255.0).round() as u8,
+                        (g as f32 / max * 255.0).round() as u8,
+                        (b as f32 / max * 255.0).round() as u8,
+                        255
+                    ]);
+                }
+            }
+        }
+
+        Self {
+            size,
+            data,
+            id: next_resource_id()
+        }
+    }
+
+    // The usual strip layout exported by grading tools: size slices of size x size laid
+    // out left to right, so the image is size*size wide and size tall. RGBA8 pixels.
+    pub fn from_strip(width: u32, height: u32, pixels: &[u8]) -> Option<Self> {
+        let size = height;
+        if width != size * size || pixels.len() != (width * height * 4) as usize {
+            return None;
+        }
+
+        let mut data = Vec::with_capacity((size * size * size) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let x = b * size + r;
+                    let i = ((g * width + x) * 4) as usize;
+                    data.push([pixels[i], pixels[i + 1], pixels[i + 2], pixels[i + 3]]);
+                }
+            }
+        }
+
+        ColorGradingLut::new(size, data)
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub(crate) fn data(&self) -> &[[u8; 4]] {
+        &self.data
+    }
+
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct VignetteSettings {
+    pub color: glam::Vec4,
+    pub intensity: f32,
+    // Distance from the center, 1 being the corners, where darkening starts
+    pub radius: f32,
+    pub softness: f32
+}
+
+impl Default for VignetteSettings {
+    fn default() -> Self {
+        Self {
+            color: glam::Vec4::new(0.0, 0.0, 0.0, 1.0),
+            intensity: 0.8,
+            radius: 0.5,
+            softness: 0.5
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ChromaticAberrationSettings {
+    // Channel offset at the screen edge, in uv units
+    pub intensity: f32
+}
+
+impl Default for ChromaticAberrationSettings {
+    fn default() -> Self {
+        Self {
+            intensity: 0.005
+        }
+    }
+}
+
+// A user supplied SPIR-V fragment shader, drawn with the fullscreen triangle. It gets
+//   layout(location=0) in vec2 v_uv;
+//   layout(set = 0, binding = 0) uniform texture2D t_input;
+//   layout(set = 0, binding = 1) uniform sampler s_input;
+//   layout(set = 0, binding = 2) uniform Params { ... };  // the bytes in `params`
+// and writes location 0.
+#[derive(Debug, Clone)]
+pub struct CustomEffect {
+    spirv: Vec<u8>,
+    pub params: Vec<u8>,
+    id: u64
+}
+
+impl CustomEffect {
+    pub const MAX_PARAMS_SIZE: usize = 256;
+
+    // The shader is parsed and validated the same way wgpu does it, so one it would reject
+    // fails here rather than inside wgpu when the effect is first drawn
+    pub fn new(spirv: Vec<u8>, params: Vec<u8>) -> Result<Self, RendererError> {
+        if params.len() > CustomEffect::MAX_PARAMS_SIZE {
+            return Err(RendererError::InvalidShader(format!("{} bytes of params, at most {} fit", params.len(), CustomEffect::MAX_PARAMS_SIZE)));
+        }
+        validate_spirv(&spirv)?;
+
+        Ok(Self {
+            spirv,
+            params,
+            id: next_resource_id()
+        })
+    }
+
+    pub(crate) fn spirv(&self) -> &[u8] {
+        &self.spirv
+    }
+
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+// A fragment shader with a main entry point that naga, and so wgpu, accepts
+fn validate_spirv(spirv: &[u8]) -> Result<(), RendererError> {
+    const SPIRV_MAGIC: [u8; 4] = [0x03, 0x02, 0x23, 0x07];
+    if spirv.len() % 4 != 0 || !spirv.starts_with(&SPIRV_MAGIC) {
+        return Err(RendererError::InvalidShader("not SPIR-V".to_string()));
+    }
+
+    // The options wgpu parses with
+    let options = naga::front::spv::Options {
+        adjust_coordinate_space: false,
+        strict_capabilities: true,
+        flow_graph_dump_prefix: None
+    };
+    let module = naga::front::spv::parse_u8_slice(spirv, &options)
+        .map_err(|err| RendererError::InvalidShader(format!("{:?}", err)))?;
+    naga::valid::Validator::new(naga::valid::ValidationFlags::all()).validate(&module)
+        .map_err(|err| RendererError::InvalidShader(format!("{:?}", err)))?;
+
+    if !module.entry_points.iter().any(|entry_point| entry_point.name == "main" && entry_point.stage == naga::ShaderStage::Fragment) {
+        return Err(RendererError::InvalidShader("no fragment entry point called main".to_string()));
+    }
+    Ok(())
+}
+
+// Uniform blocks, laid out to match the post_*.frag shaders
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct BloomThresholdUniforms {
+    pub threshold: f32,
+    pub knee: f32,
+    pub _padding: [f32; 2]
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct BlurUniforms {
+    pub direction: [f32; 2],
+    pub _padding: [f32; 2]
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct BloomCompositeUniforms {
+    pub intensity: f32,
+    pub _padding: [f32; 3]
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct FxaaUniforms {
+    pub span_max: f32,
+    pub reduce_mul: f32,
+    pub reduce_min: f32,
+    pub _padding: f32
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct ColorGradingUniforms {
+    pub intensity: f32,
+    pub lut_size: f32,
+    pub _padding: [f32; 2]
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct VignetteUniforms {
+    pub color: [f32; 4],
+    pub intensity: f32,
+    pub radius: f32,
+    pub softness: f32,
+    pub _padding: f32
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct ChromaticAberrationUniforms {
+    pub intensity: f32,
+    pub _padding: [f32; 3]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_effects_need_a_valid_fragment_shader() {
+        let fragment = include_bytes!("../shaders/post_vignette.frag.spv").to_vec();
+        assert!(CustomEffect::new(fragment.clone(), vec![0; 16]).is_ok());
+
+        let vertex = include_bytes!("../shaders/fullscreen.vert.spv").to_vec();
+        assert!(matches!(CustomEffect::new(vertex, Vec::new()), Err(RendererError::InvalidShader(_))));
+
+        // The magic number and a length that's a multiple of 4 aren't enough
+        let mut truncated = fragment.clone();
+        truncated.truncate(64);
+        assert!(matches!(CustomEffect::new(truncated, Vec::new()), Err(RendererError::InvalidShader(_))));
+        assert!(matches!(CustomEffect::new(b"not spirv".to_vec(), Vec::new()), Err(RendererError::InvalidShader(_))));
+
+        assert!(CustomEffect::new(fragment, vec![0; CustomEffect::MAX_PARAMS_SIZE + 1]).is_err());
+    }
+}
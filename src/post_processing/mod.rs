@@ -0,0 +1,615 @@
+use std::collections::HashMap;
+use std::num::NonZeroU32;
+use crate::WGPUState;
+use crate::error::RendererError;
+use crate::handles::{Handle, Pool};
+use crate::shaders::{ShaderStore, ShaderType};
+use crate::texture::Texture;
+
+mod effects;
+
+pub use effects::{
+    BloomSettings,
+    ChromaticAberrationSettings,
+    ColorGradingLut,
+    ColorGradingSettings,
+    CustomEffect,
+    FxaaSettings,
+    PostEffect,
+    VignetteSettings
+};
+use effects::{
+    BloomCompositeUniforms,
+    BloomThresholdUniforms,
+    BlurUniforms,
+    ChromaticAberrationUniforms,
+    ColorGradingUniforms,
+    FxaaUniforms,
+    VignetteUniforms
+};
+
+pub type EffectId = Handle<PostEffect>;
+
+struct EffectEntry {
+    effect: PostEffect,
+    enabled: bool
+}
+
+// Every effect gets its own uniform buffer with a slot per pass it draws. Bloom is the
+// greediest with threshold, two blur directions and the composite.
+const SLOT_SIZE: wgpu::BufferAddress = wgpu::BIND_BUFFER_ALIGNMENT;
+const SLOTS_PER_EFFECT: wgpu::BufferAddress = 4;
+
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+enum PostShader {
+    Builtin(ShaderType),
+    Custom(u64)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum LayoutKind {
+    // input, sampler, params
+    Basic,
+    // plus a second 2D texture at binding 3
+    Texture,
+    // plus a 3D LUT at binding 3
+    Lut
+}
+
+impl PostShader {
+    fn layout_kind(&self) -> LayoutKind {
+        match self {
+            PostShader::Builtin(ShaderType::PostBloomCompositeFrag) => LayoutKind::Texture,
+            PostShader::Builtin(ShaderType::PostColorGradingFrag) => LayoutKind::Lut,
+            _ => LayoutKind::Basic
+        }
+    }
+}
+
+fn effect_shaders(effect: &PostEffect) -> Vec<PostShader> {
+    match effect {
+        PostEffect::Bloom(_) => vec![
+            PostShader::Builtin(ShaderType::PostBloomThresholdFrag),
+            PostShader::Builtin(ShaderType::PostBloomBlurFrag),
+            PostShader::Builtin(ShaderType::PostBloomCompositeFrag)
+        ],
+        PostEffect::Fxaa(_) => vec![PostShader::Builtin(ShaderType::PostFxaaFrag)],
+        PostEffect::ColorGrading(_) => vec![PostShader::Builtin(ShaderType::PostColorGradingFrag)],
+        PostEffect::Vignette(_) => vec![PostShader::Builtin(ShaderType::PostVignetteFrag)],
+        PostEffect::ChromaticAberration(_) => vec![PostShader::Builtin(ShaderType::PostChromaticAberrationFrag)],
+        PostEffect::Custom(custom) => vec![PostShader::Custom(custom.id())]
+    }
+}
+
+struct EffectResources {
+    uniform_buffer: wgpu::Buffer,
+    // Uploaded LUT and the id of the ColorGradingLut it came from
+    lut: Option<(u64, Texture)>
+}
+
+// Swap chain sized textures the effects bounce between, plus two half resolution ones for bloom
+struct PostTargets {
+    generation: u64,
+    ping_pong: [Texture; 2],
+    bloom: [Texture; 2]
+}
+
+impl PostTargets {
+    fn new(renderer_state: &WGPUState) -> Self {
+        let device = &renderer_state.device;
+        let sc_desc = &renderer_state.sc_desc;
+        let format = renderer_state.swapchain_format;
+        // A 1 pixel side would round down to nothing, which isn't a texture
+        let (half_width, half_height) = ((sc_desc.width / 2).max(1), (sc_desc.height / 2).max(1));
+
+        Self {
+            generation: renderer_state.targets_generation,
+            ping_pong: [
+                Texture::create_render_target(device, sc_desc, format, "post_ping"),
+                Texture::create_render_target(device, sc_desc, format, "post_pong")
+            ],
+            bloom: [
+                Texture::create_sized_render_target(device, half_width, half_height, format, "bloom_bright"),
+                Texture::create_sized_render_target(device, half_width, half_height, format, "bloom_blurred")
+            ]
+        }
+    }
+}
+
+// Fullscreen effects run in order after the scene (and tonemapping, with HDR on). The
+// first one reads the scene, each later one reads the previous output and the last
+// writes to the frame. Disabled effects are skipped without being removed.
+pub struct PostProcessStack {
+    effects: Pool<EffectEntry, PostEffect>,
+    order: Vec<EffectId>,
+    basic_layout: wgpu::BindGroupLayout,
+    texture_layout: wgpu::BindGroupLayout,
+    lut_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    pipelines: HashMap<PostShader, wgpu::RenderPipeline>,
+    custom_shaders: HashMap<u64, wgpu::ShaderModule>,
+    resources: HashMap<EffectId, EffectResources>,
+    targets: Option<PostTargets>
+}
+
+impl PostProcessStack {
+    pub(crate) fn new(renderer_state: &WGPUState, owner: u32) -> Self {
+        let device = &renderer_state.device;
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post Process Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            effects: Pool::new(owner),
+            order: Vec::new(),
+            basic_layout: create_bind_group_layout(device, None, "post_basic_bind_group_layout"),
+            texture_layout: create_bind_group_layout(device, Some(wgpu::TextureViewDimension::D2), "post_texture_bind_group_layout"),
+            lut_layout: create_bind_group_layout(device, Some(wgpu::TextureViewDimension::D3), "post_lut_bind_group_layout"),
+            sampler,
+            pipelines: HashMap::new(),
+            custom_shaders: HashMap::new(),
+            resources: HashMap::new(),
+            targets: None
+        }
+    }
+
+    // Adds an enabled effect at the end of the chain
+    pub fn push(&mut self, effect: PostEffect) -> EffectId {
+        let id = self.effects.insert(EffectEntry { effect, enabled: true });
+        self.order.push(id);
+        id
+    }
+
+    // Adds an enabled effect at a position in the chain, clamped to the end
+    pub fn insert(&mut self, index: usize, effect: PostEffect) -> EffectId {
+        let id = self.effects.insert(EffectEntry { effect, enabled: true });
+        self.order.insert(index.min(self.order.len()), id);
+        id
+    }
+
+    pub fn remove(&mut self, id: EffectId) -> Result<PostEffect, RendererError> {
+        let entry = self.effects.remove(id)?;
+        self.order.retain(|other| *other != id);
+        self.resources.remove(&id);
+        Ok(entry.effect)
+    }
+
+    pub fn set_enabled(&mut self, id: EffectId, enabled: bool) -> Result<(), RendererError> {
+        self.effects.get_mut(id)?.enabled = enabled;
+        Ok(())
+    }
+
+    pub fn is_enabled(&self, id: EffectId) -> Result<bool, RendererError> {
+        Ok(self.effects.get(id)?.enabled)
+    }
+
+    pub fn get(&self, id: EffectId) -> Result<&PostEffect, RendererError> {
+        Ok(&self.effects.get(id)?.effect)
+    }
+
+    // Parameter changes are picked up on the next draw
+    pub fn get_mut(&mut self, id: EffectId) -> Result<&mut PostEffect, RendererError> {
+        Ok(&mut self.effects.get_mut(id)?.effect)
+    }
+
+    // Effects in the order they run, disabled ones included
+    pub fn iter(&self) -> impl Iterator<Item = (EffectId, &PostEffect)> + '_ {
+        self.order.iter().filter_map(move |id| {
+            self.effects.get(*id).ok().map(|entry| (*id, &entry.effect))
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    pub(crate) fn has_enabled_effects(&self) -> bool {
+        self.effects.iter().any(|(_, entry)| entry.enabled)
+    }
+
+    // Builds whatever the enabled effects need and writes their parameters. Runs ahead of
+    // encode, same as pipelines for the scene.
+    pub(crate) fn prepare(&mut self, renderer_state: &WGPUState, shader_store: &ShaderStore) {
+        let device = &renderer_state.device;
+        let queue = &renderer_state.queue;
+
+        let up_to_date = matches!(&self.targets, Some(targets) if targets.generation == renderer_state.targets_generation);
+        if !up_to_date {
+            self.targets = Some(PostTargets::new(renderer_state));
+        }
+
+        // Custom shaders that were removed or swapped for a new one don't need their pipelines anymore
+        let custom_ids: Vec<u64> = self.effects.iter()
+            .filter_map(|(_, entry)| match &entry.effect {
+                PostEffect::Custom(custom) => Some(custom.id()),
+                _ => None
+            })
+            .collect();
+        self.custom_shaders.retain(|id, _| custom_ids.contains(id));
+        self.pipelines.retain(|shader, _| match shader {
+            PostShader::Custom(id) => custom_ids.contains(id),
+            PostShader::Builtin(_) => true
+        });
+
+        for id in &self.order {
+            let entry = match self.effects.get(*id) {
+                Ok(entry) if entry.enabled => entry,
+                _ => continue
+            };
+
+            for shader in effect_shaders(&entry.effect) {
+                if self.pipelines.contains_key(&shader) {
+                    continue;
+                }
+
+                let module = match shader {
+                    PostShader::Builtin(shader_type) => shader_store.get(shader_type),
+                    PostShader::Custom(custom_id) => {
+                        let spirv = match &entry.effect {
+                            PostEffect::Custom(custom) => custom.spirv(),
+                            _ => unreachable!()
+                        };
+                        self.custom_shaders.entry(custom_id).or_insert_with(|| {
+                            device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+                                label: Some("Custom Post Effect"),
+                                source: wgpu::util::make_spirv(spirv),
+                                flags: wgpu::ShaderFlags::VALIDATION,
+                            })
+                        })
+                    }
+                };
+                let layout = match shader.layout_kind() {
+                    LayoutKind::Basic => &self.basic_layout,
+                    LayoutKind::Texture => &self.texture_layout,
+                    LayoutKind::Lut => &self.lut_layout
+                };
+                let pipeline = create_pipeline(renderer_state, shader_store, layout, module);
+                self.pipelines.insert(shader, pipeline);
+            }
+
+            let resources = self.resources.entry(*id).or_insert_with(|| EffectResources {
+                uniform_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Post Effect Uniform Buffer"),
+                    size: SLOT_SIZE * SLOTS_PER_EFFECT,
+                    usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+                    mapped_at_creation: false
+                }),
+                lut: None
+            });
+            let buffer = &resources.uniform_buffer;
+
+            match &entry.effect {
+                PostEffect::Bloom(settings) => {
+                    queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[BloomThresholdUniforms {
+                        threshold: settings.threshold,
+                        knee: settings.knee,
+                        _padding: [0.0; 2]
+                    }]));
+                    queue.write_buffer(buffer, SLOT_SIZE, bytemuck::cast_slice(&[BlurUniforms {
+                        direction: [1.0, 0.0],
+                        _padding: [0.0; 2]
+                    }]));
+                    queue.write_buffer(buffer, SLOT_SIZE * 2, bytemuck::cast_slice(&[BlurUniforms {
+                        direction: [0.0, 1.0],
+                        _padding: [0.0; 2]
+                    }]));
+                    queue.write_buffer(buffer, SLOT_SIZE * 3, bytemuck::cast_slice(&[BloomCompositeUniforms {
+                        intensity: settings.intensity,
+                        _padding: [0.0; 3]
+                    }]));
+                },
+                PostEffect::Fxaa(settings) => {
+                    queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[FxaaUniforms {
+                        span_max: settings.span_max,
+                        reduce_mul: settings.reduce_mul,
+                        reduce_min: settings.reduce_min,
+                        _padding: 0.0
+                    }]));
+                },
+                PostEffect::ColorGrading(settings) => {
+                    queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[ColorGradingUniforms {
+                        intensity: settings.intensity,
+                        lut_size: settings.lut.size() as f32,
+                        _padding: [0.0; 2]
+                    }]));
+
+                    let uploaded = matches!(&resources.lut, Some((lut_id, _)) if *lut_id == settings.lut.id());
+                    if !uploaded {
+                        resources.lut = Some((settings.lut.id(), create_lut_texture(renderer_state, &settings.lut)));
+                    }
+                },
+                PostEffect::Vignette(settings) => {
+                    queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[VignetteUniforms {
+                        color: settings.color.into(),
+                        intensity: settings.intensity,
+                        radius: settings.radius,
+                        softness: settings.softness,
+                        _padding: 0.0
+                    }]));
+                },
+                PostEffect::ChromaticAberration(settings) => {
+                    queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[ChromaticAberrationUniforms {
+                        intensity: settings.intensity,
+                        _padding: [0.0; 3]
+                    }]));
+                },
+                PostEffect::Custom(custom) => {
+                    // Buffer writes have to be a multiple of 4 bytes
+                    let mut params = custom.params.clone();
+                    params.resize((params.len() + 3) / 4 * 4, 0);
+                    if !params.is_empty() {
+                        queue.write_buffer(buffer, 0, &params);
+                    }
+                }
+            }
+        }
+    }
+
+    // Where the scene should be drawn when there are effects to run
+    pub(crate) fn input_view(&self) -> &wgpu::TextureView {
+        &self.targets.as_ref().expect("post processing used before prepare").ping_pong[0].view
+    }
+
+    pub(crate) fn encode(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let targets = self.targets.as_ref().expect("post processing encoded before prepare");
+        let enabled: Vec<_> = self.order.iter()
+            .filter_map(|id| match self.effects.get(*id) {
+                Ok(entry) if entry.enabled => Some((*id, &entry.effect)),
+                _ => None
+            })
+            .collect();
+
+        let mut source = 0;
+        for (i, (id, effect)) in enabled.iter().enumerate() {
+            let input = &targets.ping_pong[source].view;
+            let destination = if i + 1 == enabled.len() {
+                output
+            }
+            else {
+                &targets.ping_pong[1 - source].view
+            };
+            let resources = &self.resources[id];
+            let buffer = &resources.uniform_buffer;
+
+            match effect {
+                PostEffect::Bloom(settings) => {
+                    let [bright, blurred] = &targets.bloom;
+                    let threshold = PostShader::Builtin(ShaderType::PostBloomThresholdFrag);
+                    let blur = PostShader::Builtin(ShaderType::PostBloomBlurFrag);
+                    let composite = PostShader::Builtin(ShaderType::PostBloomCompositeFrag);
+
+                    self.draw(device, encoder, "Bloom Threshold Pass", threshold, input, None, buffer, 0, &bright.view);
+                    for _ in 0..settings.blur_passes {
+                        self.draw(device, encoder, "Bloom Blur Pass", blur, &bright.view, None, buffer, 1, &blurred.view);
+                        self.draw(device, encoder, "Bloom Blur Pass", blur, &blurred.view, None, buffer, 2, &bright.view);
+                    }
+                    self.draw(device, encoder, "Bloom Composite Pass", composite, input, Some(&bright.view), buffer, 3, destination);
+                },
+                PostEffect::Fxaa(_) => {
+                    let shader = PostShader::Builtin(ShaderType::PostFxaaFrag);
+                    self.draw(device, encoder, "FXAA Pass", shader, input, None, buffer, 0, destination);
+                },
+                PostEffect::ColorGrading(_) => {
+                    let shader = PostShader::Builtin(ShaderType::PostColorGradingFrag);
+                    let (_, lut) = resources.lut.as_ref().expect("color grading LUT wasn't uploaded");
+                    self.draw(device, encoder, "Color Grading Pass", shader, input, Some(&lut.view), buffer, 0, destination);
+                },
+                PostEffect::Vignette(_) => {
+                    let shader = PostShader::Builtin(ShaderType::PostVignetteFrag);
+                    self.draw(device, encoder, "Vignette Pass", shader, input, None, buffer, 0, destination);
+                },
+                PostEffect::ChromaticAberration(_) => {
+                    let shader = PostShader::Builtin(ShaderType::PostChromaticAberrationFrag);
+                    self.draw(device, encoder, "Chromatic Aberration Pass", shader, input, None, buffer, 0, destination);
+                },
+                PostEffect::Custom(custom) => {
+                    let shader = PostShader::Custom(custom.id());
+                    self.draw(device, encoder, "Custom Post Effect Pass", shader, input, None, buffer, 0, destination);
+                }
+            }
+
+            source = 1 - source;
+        }
+    }
+
+    // One fullscreen triangle. The inputs change between passes and every frame, so the
+    // bind group is made on the spot.
+    #[allow(clippy::too_many_arguments)]
+    fn draw(&self,
+            device: &wgpu::Device,
+            encoder: &mut wgpu::CommandEncoder,
+            label: &str,
+            shader: PostShader,
+            input: &wgpu::TextureView,
+            extra: Option<&wgpu::TextureView>,
+            uniform_buffer: &wgpu::Buffer,
+            slot: wgpu::BufferAddress,
+            output: &wgpu::TextureView)
+    {
+        let pipeline = self.pipelines.get(&shader).expect("post effect pipeline wasn't prepared");
+        let layout = match shader.layout_kind() {
+            LayoutKind::Basic => &self.basic_layout,
+            LayoutKind::Texture => &self.texture_layout,
+            LayoutKind::Lut => &self.lut_layout
+        };
+
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(input),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: uniform_buffer,
+                    offset: slot * SLOT_SIZE,
+                    size: wgpu::BufferSize::new(SLOT_SIZE),
+                }),
+            }
+        ];
+        if let Some(extra) = extra {
+            entries.push(wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(extra),
+            });
+        }
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &entries,
+            label: Some("post_bind_group"),
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_bind_group_layout(device: &wgpu::Device, extra: Option<wgpu::TextureViewDimension>, label: &str) -> wgpu::BindGroupLayout {
+    let mut entries = vec![
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::Sampler {
+                filtering: true,
+                comparison: false,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    ];
+    if let Some(view_dimension) = extra {
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: 3,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        });
+    }
+
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &entries,
+        label: Some(label),
+    })
+}
+
+fn create_pipeline(renderer_state: &WGPUState,
+                   shader_store: &ShaderStore,
+                   layout: &wgpu::BindGroupLayout,
+                   fragment: &wgpu::ShaderModule) -> wgpu::RenderPipeline
+{
+    let device = &renderer_state.device;
+
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Post Effect Pipeline Layout"),
+        bind_group_layouts: &[layout],
+        push_constant_ranges: &[],
+    });
+
+    // Every effect target, including the frame, is in the swap chain format
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Post Effect Pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: shader_store.get(ShaderType::FullscreenVert),
+            entry_point: "main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: fragment,
+            entry_point: "main",
+            targets: &[renderer_state.swapchain_format.into()],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+    })
+}
+
+fn create_lut_texture(renderer_state: &WGPUState, lut: &ColorGradingLut) -> Texture {
+    let size = wgpu::Extent3d {
+        width: lut.size(),
+        height: lut.size(),
+        depth_or_array_layers: lut.size()
+    };
+
+    let texture = renderer_state.device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("color_grading_lut"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST
+    });
+
+    renderer_state.queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        bytemuck::cast_slice(lut.data()),
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: NonZeroU32::new(4 * lut.size()),
+            rows_per_image: NonZeroU32::new(lut.size()),
+        },
+        size
+    );
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    Texture {
+        texture,
+        view
+    }
+}
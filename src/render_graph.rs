@@ -0,0 +1,597 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use crate::WGPUState;
+use crate::error::RendererError;
+use crate::texture::Texture;
+
+// Graph local id for a texture or buffer. Only means something inside the frame's graph
+// that handed it out.
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+pub struct ResourceId(usize);
+
+// Graph local id for a pass, for ordering other passes against it
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+pub struct PassId(usize);
+
+#[derive(Debug, Clone, Copy)]
+pub struct TextureDesc {
+    pub format: wgpu::TextureFormat,
+    // Fraction of the swap chain size, 1.0 matches it and 0.5 is half resolution
+    pub scale: f32,
+    pub sample_count: u32,
+    pub usage: wgpu::TextureUsage
+}
+
+impl TextureDesc {
+    // Full size, single sampled, something you can render to and sample afterwards
+    pub fn new(format: wgpu::TextureFormat) -> Self {
+        Self {
+            format,
+            scale: 1.0,
+            sample_count: 1,
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BufferDesc {
+    pub size: wgpu::BufferAddress,
+    pub usage: wgpu::BufferUsage
+}
+
+// The renderer's own resources and passes, handed to custom passes so they can hook in
+// around the built-in ones. scene_color is what the scene gets resolved into, and may be the
+// same resource as output when there's no HDR or post processing. depth is multisampled
+// whenever MSAA is on. The passes that are optional are None on frames they don't run.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameResources {
+    pub output: ResourceId,
+    pub scene_color: ResourceId,
+    pub depth: ResourceId,
+    pub output_format: wgpu::TextureFormat,
+    pub scene_format: wgpu::TextureFormat,
+    pub sample_count: u32,
+    pub opaque_pass: PassId,
+    pub transparent_pass: Option<PassId>,
+    pub tonemap_pass: Option<PassId>,
+    pub post_processing_pass: Option<PassId>
+}
+
+// Something an application wants drawn every frame. setup declares what it reads and
+// writes (and creates any transient targets it needs), the graph then runs execute at the
+// right point between the built-in passes.
+pub trait CustomPass {
+    fn name(&self) -> &str;
+    fn setup(&mut self, builder: &mut PassBuilder, frame: &FrameResources);
+    fn execute(&mut self, ctx: &mut PassContext) -> Result<(), RendererError>;
+}
+
+enum Resource<'a> {
+    ImportedTexture(&'a wgpu::TextureView),
+    Texture(TextureDesc),
+    Buffer(BufferDesc)
+}
+
+type ExecuteFn<'a> = Box<dyn FnOnce(&mut PassContext) -> Result<(), RendererError> + 'a>;
+
+struct PassNode<'a> {
+    name: String,
+    reads: Vec<ResourceId>,
+    writes: Vec<ResourceId>,
+    before: Option<PassId>,
+    execute: ExecuteFn<'a>
+}
+
+pub struct PassBuilder<'g, 'a> {
+    graph: &'g mut RenderGraph<'a>,
+    reads: Vec<ResourceId>,
+    writes: Vec<ResourceId>,
+    before: Option<PassId>
+}
+
+impl<'g, 'a> PassBuilder<'g, 'a> {
+    // Transient texture, only valid during this frame. It starts with garbage in it since
+    // the memory is shared with other transients that aren't alive at the same time.
+    pub fn create_texture(&mut self, desc: TextureDesc) -> ResourceId {
+        self.graph.create_texture(desc)
+    }
+
+    pub fn create_buffer(&mut self, desc: BufferDesc) -> ResourceId {
+        self.graph.create_buffer(desc)
+    }
+
+    pub fn read(&mut self, id: ResourceId) {
+        if !self.reads.contains(&id) {
+            self.reads.push(id);
+        }
+    }
+
+    // Passes that write a resource run before every pass that only reads it. Passes that
+    // both write it run in the order they were added.
+    pub fn write(&mut self, id: ResourceId) {
+        if !self.writes.contains(&id) {
+            self.writes.push(id);
+        }
+    }
+
+    // Runs this pass before the other one, as if it had been added just ahead of it. That's
+    // how a custom pass gets in before the built-in ones, a depth prepass before the opaque
+    // pass for example, since custom passes are always added after them.
+    pub fn run_before(&mut self, pass: PassId) {
+        self.before = Some(pass);
+    }
+}
+
+enum Resolved<'r> {
+    Texture(&'r wgpu::TextureView),
+    Buffer(&'r wgpu::Buffer)
+}
+
+pub struct PassContext<'r> {
+    pub device: &'r wgpu::Device,
+    pub queue: &'r wgpu::Queue,
+    pub encoder: &'r mut wgpu::CommandEncoder,
+    resolved: &'r [Option<Resolved<'r>>],
+    size: (u32, u32)
+}
+
+impl<'r> PassContext<'r> {
+    pub fn texture_view(&self, id: ResourceId) -> &'r wgpu::TextureView {
+        match self.resolved[id.0] {
+            Some(Resolved::Texture(view)) => view,
+            Some(Resolved::Buffer(_)) => panic!("render graph resource {:?} is a buffer, not a texture", id),
+            None => panic!("render graph resource {:?} isn't used by any pass", id)
+        }
+    }
+
+    pub fn buffer(&self, id: ResourceId) -> &'r wgpu::Buffer {
+        match self.resolved[id.0] {
+            Some(Resolved::Buffer(buffer)) => buffer,
+            Some(Resolved::Texture(_)) => panic!("render graph resource {:?} is a texture, not a buffer", id),
+            None => panic!("render graph resource {:?} isn't used by any pass", id)
+        }
+    }
+
+    // Swap chain size, transient textures are scaled from this
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+}
+
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+enum TransientKey {
+    Texture {
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        usage: wgpu::TextureUsage
+    },
+    Buffer {
+        size: wgpu::BufferAddress,
+        usage: wgpu::BufferUsage
+    }
+}
+
+enum Physical {
+    Texture(Texture),
+    Buffer(wgpu::Buffer)
+}
+
+// Transient textures and buffers, kept between frames so the graph isn't reallocating
+// every draw. Anything a frame didn't use gets dropped at the end of it, that's how old
+// sizes go away after a resize.
+#[derive(Default)]
+pub(crate) struct TransientPool {
+    entries: Vec<(TransientKey, Physical)>
+}
+
+// A frame's worth of passes. Built fresh every draw, borrowing whatever the passes need.
+pub(crate) struct RenderGraph<'a> {
+    resources: Vec<Resource<'a>>,
+    passes: Vec<PassNode<'a>>,
+    // Passes in the order they count as added, which is what ties are broken by. Usually
+    // just the order of passes, except where run_before moved one.
+    sequence: Vec<usize>
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self {
+            resources: Vec::new(),
+            passes: Vec::new(),
+            sequence: Vec::new()
+        }
+    }
+
+    // Textures that live outside the graph, like the frame or the renderer's own targets
+    pub fn import_texture(&mut self, view: &'a wgpu::TextureView) -> ResourceId {
+        self.resources.push(Resource::ImportedTexture(view));
+        ResourceId(self.resources.len() - 1)
+    }
+
+    pub fn create_texture(&mut self, desc: TextureDesc) -> ResourceId {
+        self.resources.push(Resource::Texture(desc));
+        ResourceId(self.resources.len() - 1)
+    }
+
+    pub fn create_buffer(&mut self, desc: BufferDesc) -> ResourceId {
+        self.resources.push(Resource::Buffer(desc));
+        ResourceId(self.resources.len() - 1)
+    }
+
+    pub fn add_pass<S, E>(&mut self, name: &str, setup: S, execute: E) -> PassId
+        where S: FnOnce(&mut PassBuilder<'_, 'a>),
+              E: FnOnce(&mut PassContext) -> Result<(), RendererError> + 'a
+    {
+        let (reads, writes, before) = self.declare(setup);
+        self.push_pass(PassNode {
+            name: name.to_string(),
+            reads,
+            writes,
+            before,
+            execute: Box::new(execute)
+        })
+    }
+
+    pub fn add_custom_pass(&mut self, pass: &'a mut dyn CustomPass, frame: &FrameResources) -> PassId {
+        let (reads, writes, before) = self.declare(|builder| pass.setup(builder, frame));
+        self.push_pass(PassNode {
+            name: pass.name().to_string(),
+            reads,
+            writes,
+            before,
+            execute: Box::new(move |ctx| pass.execute(ctx))
+        })
+    }
+
+    fn declare<S>(&mut self, setup: S) -> (Vec<ResourceId>, Vec<ResourceId>, Option<PassId>)
+        where S: FnOnce(&mut PassBuilder<'_, 'a>)
+    {
+        let mut builder = PassBuilder {
+            graph: self,
+            reads: Vec::new(),
+            writes: Vec::new(),
+            before: None
+        };
+        setup(&mut builder);
+        (builder.reads, builder.writes, builder.before)
+    }
+
+    fn push_pass(&mut self, node: PassNode<'a>) -> PassId {
+        let id = self.passes.len();
+        match node.before.and_then(|before| self.sequence.iter().position(|pass| *pass == before.0)) {
+            Some(position) => self.sequence.insert(position, id),
+            None => self.sequence.push(id)
+        }
+        self.passes.push(node);
+        PassId(id)
+    }
+
+    // Writers of a resource come before its readers, and writers of the same resource keep
+    // the order they were added in. Ties go to whichever pass was added first. Passes moved
+    // with run_before count as added where they were moved to.
+    fn sorted_passes(&self) -> Result<Vec<usize>, RendererError> {
+        let pass_count = self.passes.len();
+        let mut edges = vec![Vec::new(); pass_count];
+        let mut incoming = vec![0usize; pass_count];
+        let mut rank = vec![0; pass_count];
+        for (position, pass) in self.sequence.iter().enumerate() {
+            rank[*pass] = position;
+        }
+
+        for (pass, node) in self.passes.iter().enumerate() {
+            if let Some(before) = node.before {
+                edges[pass].push(before.0);
+            }
+        }
+
+        for resource in 0..self.resources.len() {
+            let id = ResourceId(resource);
+            let writers: Vec<usize> = self.sequence.iter().copied().filter(|p| self.passes[*p].writes.contains(&id)).collect();
+            let readers: Vec<usize> = self.sequence.iter().copied()
+                .filter(|p| self.passes[*p].reads.contains(&id) && !self.passes[*p].writes.contains(&id))
+                .collect();
+
+            for pair in writers.windows(2) {
+                edges[pair[0]].push(pair[1]);
+            }
+            for writer in &writers {
+                for reader in &readers {
+                    edges[*writer].push(*reader);
+                }
+            }
+        }
+
+        for targets in &edges {
+            for target in targets {
+                incoming[*target] += 1;
+            }
+        }
+
+        let mut ready: BinaryHeap<Reverse<(usize, usize)>> = (0..pass_count)
+            .filter(|p| incoming[*p] == 0)
+            .map(|p| Reverse((rank[p], p)))
+            .collect();
+        let mut order = Vec::with_capacity(pass_count);
+        while let Some(Reverse((_, pass))) = ready.pop() {
+            order.push(pass);
+            for target in &edges[pass] {
+                incoming[*target] -= 1;
+                if incoming[*target] == 0 {
+                    ready.push(Reverse((rank[*target], *target)));
+                }
+            }
+        }
+
+        if order.len() != pass_count {
+            return Err(RendererError::RenderGraphCycle);
+        }
+        Ok(order)
+    }
+
+    // Orders the passes, hands out transient resources and records everything into the encoder
+    pub fn execute(self,
+                   renderer_state: &WGPUState,
+                   pool: &mut TransientPool,
+                   encoder: &mut wgpu::CommandEncoder) -> Result<(), RendererError>
+    {
+        let order = self.sorted_passes()?;
+        let size = (renderer_state.sc_desc.width, renderer_state.sc_desc.height);
+
+        // Entries created this frame go on the end, so old indices are still good
+        let mut keys: Vec<TransientKey> = pool.entries.iter().map(|(key, _)| *key).collect();
+        let assigned = self.assign_transients(&order, size, &mut keys);
+        for key in &keys[pool.entries.len()..] {
+            pool.entries.push((*key, create_physical(&renderer_state.device, key)));
+        }
+        let mut used = vec![false; pool.entries.len()];
+        for entry in assigned.iter().flatten() {
+            used[*entry] = true;
+        }
+
+        // Transients no pass declared never got anything
+        let resolved: Vec<Option<Resolved>> = self.resources.iter().enumerate().map(|(resource, kind)| match kind {
+            Resource::ImportedTexture(view) => Some(Resolved::Texture(view)),
+            Resource::Texture(_) | Resource::Buffer(_) => match assigned[resource].map(|entry| &pool.entries[entry].1) {
+                Some(Physical::Texture(texture)) => Some(Resolved::Texture(&texture.view)),
+                Some(Physical::Buffer(buffer)) => Some(Resolved::Buffer(buffer)),
+                None => None
+            }
+        }).collect();
+
+        let mut passes: Vec<Option<PassNode>> = self.passes.into_iter().map(Some).collect();
+        for pass in order {
+            let node = passes[pass].take().unwrap();
+            encoder.push_debug_group(&node.name);
+            let mut ctx = PassContext {
+                device: &renderer_state.device,
+                queue: &renderer_state.queue,
+                encoder,
+                resolved: &resolved,
+                size
+            };
+            (node.execute)(&mut ctx)?;
+            encoder.pop_debug_group();
+        }
+
+        drop(resolved);
+        pool.entries = pool.entries.drain(..)
+            .zip(used)
+            .filter_map(|(entry, used)| if used { Some(entry) } else { None })
+            .collect();
+
+        Ok(())
+    }
+
+    // Transients that are never alive at the same time and have the same shape share one
+    // physical resource. keys are the pool's entries, anything that needs a new one gets it
+    // pushed on the end. Returns the entry for each resource, None for imported ones and
+    // transients no pass uses.
+    fn assign_transients(&self, order: &[usize], size: (u32, u32), keys: &mut Vec<TransientKey>) -> Vec<Option<usize>> {
+        // The span of passes, in execution order, that touch each resource
+        let mut lifetimes: Vec<Option<(usize, usize)>> = vec![None; self.resources.len()];
+        for (position, pass) in order.iter().enumerate() {
+            let node = &self.passes[*pass];
+            for id in node.reads.iter().chain(node.writes.iter()) {
+                let lifetime = lifetimes[id.0].get_or_insert((position, position));
+                lifetime.1 = position;
+            }
+        }
+
+        // Each entry is busy until the last pass of whoever has it
+        let mut busy_until: Vec<Option<usize>> = vec![None; keys.len()];
+        let mut assigned: Vec<Option<usize>> = vec![None; self.resources.len()];
+        let mut by_start: Vec<usize> = (0..self.resources.len()).filter(|r| lifetimes[*r].is_some()).collect();
+        by_start.sort_by_key(|r| lifetimes[*r].map(|(start, _)| start));
+
+        for resource in by_start {
+            let key = match &self.resources[resource] {
+                Resource::Texture(desc) => TransientKey::Texture {
+                    width: ((size.0 as f32 * desc.scale) as u32).max(1),
+                    height: ((size.1 as f32 * desc.scale) as u32).max(1),
+                    format: desc.format,
+                    sample_count: desc.sample_count,
+                    usage: desc.usage
+                },
+                Resource::Buffer(desc) => TransientKey::Buffer {
+                    size: desc.size,
+                    usage: desc.usage
+                },
+                _ => continue
+            };
+            let (start, end) = lifetimes[resource].unwrap();
+
+            let free = keys.iter().enumerate().position(|(entry, entry_key)| {
+                *entry_key == key && busy_until[entry].map_or(true, |until| until < start)
+            });
+            let entry = match free {
+                Some(entry) => entry,
+                None => {
+                    keys.push(key);
+                    busy_until.push(None);
+                    keys.len() - 1
+                }
+            };
+            busy_until[entry] = Some(end);
+            assigned[resource] = Some(entry);
+        }
+
+        assigned
+    }
+}
+
+fn create_physical(device: &wgpu::Device, key: &TransientKey) -> Physical {
+    match *key {
+        TransientKey::Texture { width, height, format, sample_count, usage } => {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("render_graph_transient"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1
+                },
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            Physical::Texture(Texture { texture, view })
+        },
+        TransientKey::Buffer { size, usage } => {
+            Physical::Buffer(device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("render_graph_transient"),
+                size,
+                usage,
+                mapped_at_creation: false
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLOR: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+    // Passes that only declare what they touch, they're never executed here
+    fn pass(graph: &mut RenderGraph, name: &str, reads: &[ResourceId], writes: &[ResourceId]) -> PassId {
+        graph.add_pass(name, |builder| {
+            reads.iter().for_each(|id| builder.read(*id));
+            writes.iter().for_each(|id| builder.write(*id));
+        }, |_| Ok(()))
+    }
+
+    fn names(graph: &RenderGraph) -> Vec<String> {
+        graph.sorted_passes().unwrap().iter().map(|pass| graph.passes[*pass].name.clone()).collect()
+    }
+
+    #[test]
+    fn writers_run_before_readers() {
+        let mut graph = RenderGraph::new();
+        let color = graph.create_texture(TextureDesc::new(COLOR));
+        let bloom = graph.create_texture(TextureDesc::new(COLOR));
+        pass(&mut graph, "composite", &[color, bloom], &[]);
+        pass(&mut graph, "bloom", &[color], &[bloom]);
+        pass(&mut graph, "scene", &[], &[color]);
+        assert_eq!(names(&graph), vec!["scene", "bloom", "composite"]);
+    }
+
+    #[test]
+    fn writers_keep_the_order_they_were_added_in() {
+        let mut graph = RenderGraph::new();
+        let color = graph.create_texture(TextureDesc::new(COLOR));
+        pass(&mut graph, "opaque", &[], &[color]);
+        pass(&mut graph, "transparent", &[color], &[color]);
+        pass(&mut graph, "overlay", &[], &[color]);
+        // Unrelated passes are left in the order they came
+        pass(&mut graph, "first unrelated", &[], &[]);
+        pass(&mut graph, "second unrelated", &[], &[]);
+        assert_eq!(names(&graph), vec!["opaque", "transparent", "overlay", "first unrelated", "second unrelated"]);
+    }
+
+    #[test]
+    fn cycles_are_reported() {
+        let mut graph = RenderGraph::new();
+        let a = graph.create_texture(TextureDesc::new(COLOR));
+        let b = graph.create_texture(TextureDesc::new(COLOR));
+        pass(&mut graph, "a to b", &[a], &[b]);
+        pass(&mut graph, "b to a", &[b], &[a]);
+        assert!(matches!(graph.sorted_passes(), Err(RendererError::RenderGraphCycle)));
+    }
+
+    #[test]
+    fn passes_can_run_before_ones_added_earlier() {
+        let mut graph = RenderGraph::new();
+        let color = graph.create_texture(TextureDesc::new(COLOR));
+        let depth = graph.create_texture(TextureDesc::new(Texture::DEPTH_FORMAT));
+        let opaque = pass(&mut graph, "opaque", &[], &[color, depth]);
+        pass(&mut graph, "transparent", &[], &[color, depth]);
+        // Like a custom depth prepass, added after the built-in passes
+        graph.add_pass("prepass", |builder| {
+            builder.write(depth);
+            builder.run_before(opaque);
+        }, |_| Ok(()));
+        pass(&mut graph, "after", &[], &[color]);
+        assert_eq!(names(&graph), vec!["prepass", "opaque", "transparent", "after"]);
+
+        // Also when they don't share anything
+        let mut graph = RenderGraph::new();
+        let first = pass(&mut graph, "first", &[], &[]);
+        graph.add_pass("second", |builder| builder.run_before(first), |_| Ok(()));
+        assert_eq!(names(&graph), vec!["second", "first"]);
+    }
+
+    #[test]
+    fn run_before_cant_break_a_read() {
+        let mut graph = RenderGraph::new();
+        let color = graph.create_texture(TextureDesc::new(COLOR));
+        let producer = pass(&mut graph, "producer", &[], &[color]);
+        graph.add_pass("consumer", |builder| {
+            builder.read(color);
+            builder.run_before(producer);
+        }, |_| Ok(()));
+        assert!(matches!(graph.sorted_passes(), Err(RendererError::RenderGraphCycle)));
+    }
+
+    #[test]
+    fn transients_that_dont_overlap_share_memory() {
+        let mut graph = RenderGraph::new();
+        let first = graph.create_texture(TextureDesc::new(COLOR));
+        let second = graph.create_texture(TextureDesc::new(COLOR));
+        let third = graph.create_texture(TextureDesc::new(COLOR));
+        let half = graph.create_texture(TextureDesc { scale: 0.5, ..TextureDesc::new(COLOR) });
+        let unused = graph.create_texture(TextureDesc::new(COLOR));
+        pass(&mut graph, "a", &[], &[first]);
+        pass(&mut graph, "b", &[first], &[second]);
+        pass(&mut graph, "c", &[second], &[third, half]);
+
+        let order = graph.sorted_passes().unwrap();
+        let mut keys = Vec::new();
+        let assigned = graph.assign_transients(&order, (64, 32), &mut keys);
+        // first is done by the time third starts, second overlaps both
+        assert_eq!(assigned[first.0], assigned[third.0]);
+        assert_ne!(assigned[first.0], assigned[second.0]);
+        // A different size never shares
+        assert_ne!(assigned[half.0], assigned[first.0]);
+        assert_ne!(assigned[half.0], assigned[second.0]);
+        assert_eq!(assigned[unused.0], None);
+        assert_eq!(keys.len(), 3);
+        assert_eq!(keys[assigned[half.0].unwrap()], TransientKey::Texture {
+            width: 32,
+            height: 16,
+            format: COLOR,
+            sample_count: 1,
+            usage: TextureDesc::new(COLOR).usage
+        });
+
+        // The next frame reuses what the pool already has
+        let assigned_again = graph.assign_transients(&order, (64, 32), &mut keys);
+        assert_eq!(assigned_again, assigned);
+        assert_eq!(keys.len(), 3);
+    }
+}
@@ -1,9 +1,274 @@
+use std::mem::size_of;
+use std::num::NonZeroU64;
 use crate::Mesh;
+use crate::Camera;
+use crate::Background;
+use crate::Environment;
+use crate::WGPUState;
+use crate::RendererError;
+use crate::handles::{self, Handle, Pool};
+use crate::materials::MaterialBuffers;
+use crate::geometry::{GeometryHandle, GeometryStore};
+use crate::asset_loader::LoadedMaterial;
+use crate::materials::RenderProperties;
+use std::collections::HashMap;
+
+// Stamped with the scene's id, so a handle from one scene can't be used on another
+pub type MeshHandle = Handle<Mesh>;
+
+const STARTING_OBJECTS: usize = 64;
+// Dynamic uniform offsets must be aligned to this, so every object gets a full slot
+const OBJECT_SLOT_SIZE: wgpu::BufferAddress = wgpu::BIND_BUFFER_ALIGNMENT;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ObjectUniforms {
+    model: [[f32; 4]; 4]
+}
+
+struct SceneEntry {
+    mesh: Mesh,
+    dirty: bool,
+    // Index into the mesh's LODs it was last drawn with, 0 is its own geometry
+    lod_level: usize,
+    // Set once a background loaded mesh has been given its file's material
+    loaded_material_applied: bool
+}
+
+// Per-object data living on the GPU. One slot per arena slot, so a handle's slot
+// never moves while it's alive and only dirty entries need to be rewritten.
+struct ObjectBuffers {
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    capacity: usize
+}
 
 // Do I just want to make Meshes as a trait and then light as a separate array in the scene?
 // It might be much easier
 // Mesh trait which exposes geometry, material and children and then we can have InstancedMesh, AnimatedMesh, whatever
-pub struct Scene<'a> {
-    pub meshes: Vec<&'a Mesh>,
+pub struct Scene {
+    pub camera: Camera,
+    pub background: Background,
+    // Ambient light for lit materials, they get a flat gray without one
+    pub environment: Option<Environment>,
+    meshes: Pool<SceneEntry, Mesh>,
+    object_buffers: Option<ObjectBuffers>
     //pub lights: Vec<&'a dyn Object>
-}
\ No newline at end of file
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self {
+            camera: Camera::default(),
+            background: Background::default(),
+            environment: None,
+            meshes: Pool::new(handles::next_owner_id()),
+            object_buffers: None
+        }
+    }
+
+    pub fn insert(&mut self, mesh: Mesh) -> MeshHandle {
+        self.meshes.insert(SceneEntry { mesh, dirty: true, lod_level: 0, loaded_material_applied: false })
+    }
+
+    pub fn remove(&mut self, handle: MeshHandle) -> Result<Mesh, RendererError> {
+        self.meshes.remove(handle).map(|entry| entry.mesh)
+    }
+
+    pub fn get(&self, handle: MeshHandle) -> Result<&Mesh, RendererError> {
+        self.meshes.get(handle).map(|entry| &entry.mesh)
+    }
+
+    // Anything handed out mutably is assumed changed and gets re-uploaded on the next draw
+    pub fn get_mut(&mut self, handle: MeshHandle) -> Result<&mut Mesh, RendererError> {
+        self.meshes.get_mut(handle).map(|entry| {
+            entry.dirty = true;
+            &mut entry.mesh
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (MeshHandle, &Mesh)> {
+        self.meshes.iter().map(|(handle, entry)| (handle, &entry.mesh))
+    }
+
+    pub fn lod_level(&self, handle: MeshHandle) -> Result<usize, RendererError> {
+        self.meshes.get(handle).map(|entry| entry.lod_level)
+    }
+
+    pub fn len(&self) -> usize {
+        self.meshes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.meshes.is_empty()
+    }
+
+    // Uploads whatever changed since the last call. Grows the object buffer when the
+    // arena outgrows it, which means re-uploading everything into the new buffer.
+    pub(crate) fn prepare(&mut self,
+                          renderer_state: &WGPUState,
+                          object_layout: &wgpu::BindGroupLayout,
+                          material_buffers: &Pool<MaterialBuffers>) -> Result<(), RendererError>
+    {
+        let required = self.meshes.capacity();
+        let needs_realloc = match &self.object_buffers {
+            Some(object_buffers) => object_buffers.capacity < required,
+            None => true
+        };
+
+        if needs_realloc {
+            let capacity = required.max(STARTING_OBJECTS).next_power_of_two();
+            self.object_buffers = Some(ObjectBuffers::new(renderer_state, object_layout, capacity));
+            for (_, entry) in self.meshes.iter_mut() {
+                entry.dirty = true;
+            }
+        }
+
+        let object_buffers = self.object_buffers.as_ref().unwrap();
+        for (handle, entry) in self.meshes.iter_mut() {
+            if !entry.dirty {
+                continue;
+            }
+
+            let uniforms = ObjectUniforms {
+                model: entry.mesh.transform.to_cols_array_2d()
+            };
+            renderer_state.queue.write_buffer(
+                &object_buffers.buffer,
+                Scene::slot_offset(handle),
+                bytemuck::cast_slice(&[uniforms])
+            );
+
+            let material = &entry.mesh.material;
+            material.write_buffers(renderer_state, material_buffers.get(material.material_handle)?);
+
+            entry.dirty = false;
+        }
+
+        Ok(())
+    }
+
+    // Meshes loaded in the background are drawn with a placeholder material until their file
+    // is in, then get the file's once. default is for files without a color of their own.
+    pub(crate) fn apply_loaded_materials(&mut self, materials: &HashMap<GeometryHandle, LoadedMaterial>, default: RenderProperties) {
+        if materials.is_empty() {
+            return;
+        }
+
+        for (_, entry) in self.meshes.iter_mut() {
+            if entry.loaded_material_applied {
+                continue;
+            }
+            if let Some(loaded) = materials.get(&entry.mesh.geometry) {
+                let material = &mut entry.mesh.material;
+                material.render_properties = loaded.render_properties.unwrap_or(default);
+                material.alpha_mode = loaded.alpha_mode;
+                material.rasterizer = loaded.rasterizer;
+                entry.loaded_material_applied = true;
+                entry.dirty = true;
+            }
+        }
+    }
+
+    // Picks each mesh's LOD from where the camera is now, starting from the level it had last frame
+    pub(crate) fn select_lods(&mut self, geometry_store: &GeometryStore) -> Result<(), RendererError> {
+        let eye = self.camera.eye;
+        let tan_half_fovy = (self.camera.fovy / 2.0).tan();
+        for (_, entry) in self.meshes.iter_mut() {
+            let lod = match &entry.mesh.lod {
+                Some(lod) => lod,
+                None => continue
+            };
+
+            let (center, radius) = geometry_store.get(entry.mesh.geometry)?.bounding_sphere;
+            let transform = entry.mesh.transform;
+            let center = transform.transform_point3(center);
+            let scale = transform.x_axis.truncate().length()
+                .max(transform.y_axis.truncate().length())
+                .max(transform.z_axis.truncate().length());
+            let radius = radius * scale;
+            let center_distance = center.distance(eye);
+            // Inside the bounds it covers the whole screen
+            let screen_size = if center_distance > radius { radius / (center_distance * tan_half_fovy) } else { f32::MAX };
+
+            entry.lod_level = lod.select(entry.lod_level, (center_distance - radius).max(0.0), screen_size);
+        }
+
+        Ok(())
+    }
+
+    // Every mesh with the geometry it's drawn with this frame
+    pub(crate) fn draw_list(&self) -> impl Iterator<Item = (MeshHandle, &Mesh, GeometryHandle)> {
+        self.meshes.iter().map(|(handle, entry)| (handle, &entry.mesh, entry.mesh.lod_geometry(entry.lod_level)))
+    }
+
+    pub(crate) fn object_bind_group(&self) -> &wgpu::BindGroup {
+        &self.object_buffers.as_ref().expect("scene drawn before prepare").bind_group
+    }
+
+    pub(crate) fn object_offset(&self, handle: MeshHandle) -> u32 {
+        Scene::slot_offset(handle) as u32
+    }
+
+    fn slot_offset(handle: MeshHandle) -> wgpu::BufferAddress {
+        handle.slot() as wgpu::BufferAddress * OBJECT_SLOT_SIZE
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ObjectBuffers {
+    fn new(renderer_state: &WGPUState, object_layout: &wgpu::BindGroupLayout, capacity: usize) -> Self {
+        let buffer = renderer_state.device.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("Object Buffer"),
+                size: capacity as wgpu::BufferAddress * OBJECT_SLOT_SIZE,
+                usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+                mapped_at_creation: false
+            }
+        );
+
+        let bind_group = renderer_state.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: object_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &buffer,
+                        offset: 0,
+                        size: NonZeroU64::new(size_of::<ObjectUniforms>() as u64)
+                    }),
+                }
+            ],
+            label: Some("object_bind_group"),
+        });
+
+        Self {
+            buffer,
+            bind_group,
+            capacity
+        }
+    }
+}
+
+pub(crate) fn create_object_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: NonZeroU64::new(size_of::<ObjectUniforms>() as u64),
+                },
+                count: None,
+            }
+        ],
+        label: Some("object_bind_group_layout"),
+    })
+}
@@ -1,10 +1,31 @@
 use std::collections::HashMap;
 use crate::WGPUState;
 
-#[derive(Hash, Eq, PartialEq, Debug, Clone)]
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
 pub enum ShaderType {
     BasicVert,
-    BasicFrag
+    BasicFrag,
+    FullscreenVert,
+    TonemapFrag,
+    PostBloomThresholdFrag,
+    PostBloomBlurFrag,
+    PostBloomCompositeFrag,
+    PostFxaaFrag,
+    PostColorGradingFrag,
+    PostVignetteFrag,
+    PostChromaticAberrationFrag,
+    BackgroundGradientFrag,
+    BackgroundEquirectFrag,
+    BackgroundCubeFrag,
+    EnvEquirectToCubeFrag,
+    EnvDownsampleFrag,
+    EnvIrradianceFrag,
+    EnvPrefilterFrag,
+    EnvBrdfFrag,
+    PbrVert,
+    PbrFrag,
+    TexturedVert,
+    TexturedFrag
 }
 
 pub struct ShaderStore {
@@ -19,6 +40,48 @@ impl ShaderStore {
                      renderer_state.device.create_shader_module(&wgpu::include_spirv!("shaders/shader.vert.spv")));
         store.insert(ShaderType::BasicFrag,
                      renderer_state.device.create_shader_module(&wgpu::include_spirv!("shaders/shader.frag.spv")));
+        store.insert(ShaderType::FullscreenVert,
+                     renderer_state.device.create_shader_module(&wgpu::include_spirv!("shaders/fullscreen.vert.spv")));
+        store.insert(ShaderType::TonemapFrag,
+                     renderer_state.device.create_shader_module(&wgpu::include_spirv!("shaders/tonemap.frag.spv")));
+        store.insert(ShaderType::PostBloomThresholdFrag,
+                     renderer_state.device.create_shader_module(&wgpu::include_spirv!("shaders/post_bloom_threshold.frag.spv")));
+        store.insert(ShaderType::PostBloomBlurFrag,
+                     renderer_state.device.create_shader_module(&wgpu::include_spirv!("shaders/post_bloom_blur.frag.spv")));
+        store.insert(ShaderType::PostBloomCompositeFrag,
+                     renderer_state.device.create_shader_module(&wgpu::include_spirv!("shaders/post_bloom_composite.frag.spv")));
+        store.insert(ShaderType::PostFxaaFrag,
+                     renderer_state.device.create_shader_module(&wgpu::include_spirv!("shaders/post_fxaa.frag.spv")));
+        store.insert(ShaderType::PostColorGradingFrag,
+                     renderer_state.device.create_shader_module(&wgpu::include_spirv!("shaders/post_color_grading.frag.spv")));
+        store.insert(ShaderType::PostVignetteFrag,
+                     renderer_state.device.create_shader_module(&wgpu::include_spirv!("shaders/post_vignette.frag.spv")));
+        store.insert(ShaderType::PostChromaticAberrationFrag,
+                     renderer_state.device.create_shader_module(&wgpu::include_spirv!("shaders/post_chromatic_aberration.frag.spv")));
+        store.insert(ShaderType::BackgroundGradientFrag,
+                     renderer_state.device.create_shader_module(&wgpu::include_spirv!("shaders/background_gradient.frag.spv")));
+        store.insert(ShaderType::BackgroundEquirectFrag,
+                     renderer_state.device.create_shader_module(&wgpu::include_spirv!("shaders/background_equirect.frag.spv")));
+        store.insert(ShaderType::BackgroundCubeFrag,
+                     renderer_state.device.create_shader_module(&wgpu::include_spirv!("shaders/background_cube.frag.spv")));
+        store.insert(ShaderType::EnvEquirectToCubeFrag,
+                     renderer_state.device.create_shader_module(&wgpu::include_spirv!("shaders/env_equirect_to_cube.frag.spv")));
+        store.insert(ShaderType::EnvDownsampleFrag,
+                     renderer_state.device.create_shader_module(&wgpu::include_spirv!("shaders/env_downsample.frag.spv")));
+        store.insert(ShaderType::EnvIrradianceFrag,
+                     renderer_state.device.create_shader_module(&wgpu::include_spirv!("shaders/env_irradiance.frag.spv")));
+        store.insert(ShaderType::EnvPrefilterFrag,
+                     renderer_state.device.create_shader_module(&wgpu::include_spirv!("shaders/env_prefilter.frag.spv")));
+        store.insert(ShaderType::EnvBrdfFrag,
+                     renderer_state.device.create_shader_module(&wgpu::include_spirv!("shaders/env_brdf.frag.spv")));
+        store.insert(ShaderType::PbrVert,
+                     renderer_state.device.create_shader_module(&wgpu::include_spirv!("shaders/pbr.vert.spv")));
+        store.insert(ShaderType::PbrFrag,
+                     renderer_state.device.create_shader_module(&wgpu::include_spirv!("shaders/pbr.frag.spv")));
+        store.insert(ShaderType::TexturedVert,
+                     renderer_state.device.create_shader_module(&wgpu::include_spirv!("shaders/textured.vert.spv")));
+        store.insert(ShaderType::TexturedFrag,
+                     renderer_state.device.create_shader_module(&wgpu::include_spirv!("shaders/textured.frag.spv")));
         
         Self {
             store
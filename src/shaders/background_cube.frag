@@ -0,0 +1,27 @@
+// background_cube.frag
+#version 450
+
+layout(location=0) in vec2 v_uv;
+layout(location=0) out vec4 f_color;
+
+layout(set = 0, binding = 0)
+uniform Background {
+    mat4 inv_view_proj;
+    vec4 top;
+    vec4 bottom;
+};
+
+layout(set = 0, binding = 1) uniform textureCube t_background;
+layout(set = 0, binding = 2) uniform sampler s_background;
+
+// World space direction through this pixel
+vec3 view_direction() {
+    vec2 ndc = vec2(v_uv.x * 2.0 - 1.0, 1.0 - v_uv.y * 2.0);
+    vec4 near_point = inv_view_proj * vec4(ndc, 0.0, 1.0);
+    vec4 far_point = inv_view_proj * vec4(ndc, 1.0, 1.0);
+    return normalize(far_point.xyz / far_point.w - near_point.xyz / near_point.w);
+}
+
+void main() {
+    f_color = vec4(texture(samplerCube(t_background, s_background), view_direction()).rgb, 1.0);
+}
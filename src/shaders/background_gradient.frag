@@ -0,0 +1,16 @@
+// background_gradient.frag
+#version 450
+
+layout(location=0) in vec2 v_uv;
+layout(location=0) out vec4 f_color;
+
+layout(set = 0, binding = 0)
+uniform Background {
+    mat4 inv_view_proj;
+    vec4 top;
+    vec4 bottom;
+};
+
+void main() {
+    f_color = mix(top, bottom, v_uv.y);
+}
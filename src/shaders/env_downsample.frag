@@ -0,0 +1,33 @@
+// env_downsample.frag
+#version 450
+
+layout(location=0) in vec2 v_uv;
+layout(location=0) out vec4 f_color;
+
+// Only the mip above the one being drawn is visible through this view
+layout(set = 0, binding = 0) uniform textureCube t_source;
+layout(set = 0, binding = 1) uniform sampler s_source;
+
+layout(set = 0, binding = 2)
+uniform Params {
+    uint face;
+    float roughness;
+    float source_size;
+};
+
+// Direction through a texel of a cubemap face, faces in +X, -X, +Y, -Y, +Z, -Z order
+vec3 cube_direction(uint face, vec2 uv) {
+    vec2 p = uv * 2.0 - 1.0;
+    vec3 direction;
+    if (face == 0u) direction = vec3(1.0, -p.y, -p.x);
+    else if (face == 1u) direction = vec3(-1.0, -p.y, p.x);
+    else if (face == 2u) direction = vec3(p.x, 1.0, p.y);
+    else if (face == 3u) direction = vec3(p.x, -1.0, -p.y);
+    else if (face == 4u) direction = vec3(p.x, -p.y, 1.0);
+    else direction = vec3(-p.x, -p.y, -1.0);
+    return normalize(direction);
+}
+
+void main() {
+    f_color = vec4(textureLod(samplerCube(t_source, s_source), cube_direction(face, v_uv), 0.0).rgb, 1.0);
+}
@@ -0,0 +1,54 @@
+// env_irradiance.frag
+#version 450
+
+layout(location=0) in vec2 v_uv;
+layout(location=0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform textureCube t_source;
+layout(set = 0, binding = 1) uniform sampler s_source;
+
+layout(set = 0, binding = 2)
+uniform Params {
+    uint face;
+    float roughness;
+    float source_size;
+};
+
+const float PI = 3.14159265359;
+
+// Direction through a texel of a cubemap face, faces in +X, -X, +Y, -Y, +Z, -Z order
+vec3 cube_direction(uint face, vec2 uv) {
+    vec2 p = uv * 2.0 - 1.0;
+    vec3 direction;
+    if (face == 0u) direction = vec3(1.0, -p.y, -p.x);
+    else if (face == 1u) direction = vec3(-1.0, -p.y, p.x);
+    else if (face == 2u) direction = vec3(p.x, 1.0, p.y);
+    else if (face == 3u) direction = vec3(p.x, -1.0, -p.y);
+    else if (face == 4u) direction = vec3(p.x, -p.y, 1.0);
+    else direction = vec3(-p.x, -p.y, -1.0);
+    return normalize(direction);
+}
+
+// Cosine weighted average of the hemisphere around each direction
+void main() {
+    vec3 n = cube_direction(face, v_uv);
+    vec3 up = abs(n.y) < 0.999 ? vec3(0.0, 1.0, 0.0) : vec3(0.0, 0.0, 1.0);
+    vec3 right = normalize(cross(up, n));
+    up = cross(n, right);
+
+    // Reading from a mip close to 64x64 keeps small bright spots from aliasing
+    float lod = max(log2(source_size / 64.0), 0.0);
+    float delta = 0.025;
+    vec3 irradiance = vec3(0.0);
+    float count = 0.0;
+    for (float phi = 0.0; phi < 2.0 * PI; phi += delta) {
+        for (float theta = 0.0; theta < 0.5 * PI; theta += delta) {
+            vec3 tangent_sample = vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            vec3 direction = tangent_sample.x * right + tangent_sample.y * up + tangent_sample.z * n;
+            irradiance += textureLod(samplerCube(t_source, s_source), direction, lod).rgb * cos(theta) * sin(theta);
+            count += 1.0;
+        }
+    }
+
+    f_color = vec4(PI * irradiance / count, 1.0);
+}
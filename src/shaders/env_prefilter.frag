@@ -0,0 +1,94 @@
+// env_prefilter.frag
+#version 450
+
+layout(location=0) in vec2 v_uv;
+layout(location=0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform textureCube t_source;
+layout(set = 0, binding = 1) uniform sampler s_source;
+
+layout(set = 0, binding = 2)
+uniform Params {
+    uint face;
+    float roughness;
+    float source_size;
+};
+
+const float PI = 3.14159265359;
+const uint SAMPLE_COUNT = 512u;
+
+// Direction through a texel of a cubemap face, faces in +X, -X, +Y, -Y, +Z, -Z order
+vec3 cube_direction(uint face, vec2 uv) {
+    vec2 p = uv * 2.0 - 1.0;
+    vec3 direction;
+    if (face == 0u) direction = vec3(1.0, -p.y, -p.x);
+    else if (face == 1u) direction = vec3(-1.0, -p.y, p.x);
+    else if (face == 2u) direction = vec3(p.x, 1.0, p.y);
+    else if (face == 3u) direction = vec3(p.x, -1.0, -p.y);
+    else if (face == 4u) direction = vec3(p.x, -p.y, 1.0);
+    else direction = vec3(-p.x, -p.y, -1.0);
+    return normalize(direction);
+}
+
+float radical_inverse(uint bits) {
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return float(bits) * 2.3283064365386963e-10;
+}
+
+vec2 hammersley(uint i, uint count) {
+    return vec2(float(i) / float(count), radical_inverse(i));
+}
+
+// Half vector around n, distributed like GGX for this roughness
+vec3 importance_sample_ggx(vec2 xi, vec3 n, float roughness) {
+    float a = roughness * roughness;
+    float phi = 2.0 * PI * xi.x;
+    float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    vec3 h = vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+
+    vec3 up = abs(n.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, n));
+    vec3 bitangent = cross(n, tangent);
+    return normalize(tangent * h.x + bitangent * h.y + n * h.z);
+}
+
+float distribution_ggx(float n_dot_h, float roughness) {
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Split sum prefilter, assuming the view direction is the normal. Samples come from the
+// source mip whose texels cover about as much as the sample does, which keeps bright
+// spots in HDR maps from turning into speckles.
+void main() {
+    vec3 n = cube_direction(face, v_uv);
+    vec3 v = n;
+    float texel_solid_angle = 4.0 * PI / (6.0 * source_size * source_size);
+
+    vec3 color = vec3(0.0);
+    float weight = 0.0;
+    for (uint i = 0u; i < SAMPLE_COUNT; i++) {
+        vec3 h = importance_sample_ggx(hammersley(i, SAMPLE_COUNT), n, roughness);
+        vec3 l = normalize(2.0 * dot(v, h) * h - v);
+        float n_dot_l = max(dot(n, l), 0.0);
+        if (n_dot_l > 0.0) {
+            float n_dot_h = max(dot(n, h), 0.0);
+            float h_dot_v = max(dot(h, v), 0.0);
+            float pdf = distribution_ggx(n_dot_h, roughness) * n_dot_h / (4.0 * h_dot_v) + 0.0001;
+            float sample_solid_angle = 1.0 / (float(SAMPLE_COUNT) * pdf + 0.0001);
+            float lod = roughness == 0.0 ? 0.0 : 0.5 * log2(sample_solid_angle / texel_solid_angle);
+
+            color += textureLod(samplerCube(t_source, s_source), l, lod).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+
+    f_color = vec4(color / max(weight, 0.0001), 1.0);
+}
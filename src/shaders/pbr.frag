@@ -0,0 +1,61 @@
+// pbr.frag
+#version 450
+
+layout(location=0) in vec3 v_world_position;
+layout(location=1) in vec3 v_normal;
+
+layout(location=0) out vec4 f_color;
+
+layout(set = 0, binding = 0)
+uniform Material {
+    vec4 albedo;
+    float alpha_cutoff;
+    float metallic;
+    float roughness;
+};
+
+layout(set = 2, binding = 0)
+uniform Camera {
+    mat4 view_proj;
+    vec4 eye;
+};
+
+layout(set = 3, binding = 0)
+uniform Environment {
+    float intensity;
+    float max_lod;
+};
+layout(set = 3, binding = 1) uniform textureCube t_irradiance;
+layout(set = 3, binding = 2) uniform textureCube t_prefiltered;
+layout(set = 3, binding = 3) uniform texture2D t_brdf;
+layout(set = 3, binding = 4) uniform sampler s_environment;
+
+vec3 fresnel_schlick_roughness(float cos_theta, vec3 f0, float roughness) {
+    return f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Metallic/roughness with only the environment lighting it, split sum for the specular part
+void main() {
+    if (albedo.a < alpha_cutoff) {
+        discard;
+    }
+
+    vec3 n = normalize(v_normal);
+    if (!gl_FrontFacing) {
+        n = -n;
+    }
+    vec3 v = normalize(eye.xyz - v_world_position);
+    vec3 r = reflect(-v, n);
+    float n_dot_v = max(dot(n, v), 0.0);
+
+    vec3 f0 = mix(vec3(0.04), albedo.rgb, metallic);
+    vec3 f = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+    vec3 k_d = (1.0 - f) * (1.0 - metallic);
+
+    vec3 diffuse = texture(samplerCube(t_irradiance, s_environment), n).rgb * albedo.rgb;
+    vec3 prefiltered = textureLod(samplerCube(t_prefiltered, s_environment), r, roughness * max_lod).rgb;
+    vec2 brdf = texture(sampler2D(t_brdf, s_environment), vec2(n_dot_v, roughness)).rg;
+    vec3 specular = prefiltered * (f * brdf.x + brdf.y);
+
+    f_color = vec4((k_d * diffuse + specular) * intensity, albedo.a);
+}
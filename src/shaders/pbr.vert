@@ -0,0 +1,27 @@
+// pbr.vert
+#version 450
+
+layout(location=0) in vec3 a_position;
+layout(location=1) in vec3 a_normal;
+
+layout(location=0) out vec3 v_world_position;
+layout(location=1) out vec3 v_normal;
+
+layout(set = 1, binding = 0)
+uniform Object {
+    mat4 model;
+};
+
+layout(set = 2, binding = 0)
+uniform Camera {
+    mat4 view_proj;
+    vec4 eye;
+};
+
+void main() {
+    vec4 world_position = model * vec4(a_position, 1.0);
+    v_world_position = world_position.xyz;
+    // Handles non-uniform scale
+    v_normal = transpose(inverse(mat3(model))) * a_normal;
+    gl_Position = view_proj * world_position;
+}
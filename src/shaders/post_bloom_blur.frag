@@ -0,0 +1,28 @@
+// post_bloom_blur.frag
+#version 450
+
+layout(location=0) in vec2 v_uv;
+layout(location=0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform texture2D t_input;
+layout(set = 0, binding = 1) uniform sampler s_input;
+
+layout(set = 0, binding = 2)
+uniform Params {
+    vec2 direction;
+};
+
+// One direction of a separable 9 tap gaussian
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(sampler2D(t_input, s_input), 0));
+    vec2 step_size = direction * texel;
+    float weights[5] = float[](0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+
+    vec3 color = texture(sampler2D(t_input, s_input), v_uv).rgb * weights[0];
+    for (int i = 1; i < 5; i++) {
+        vec2 offset = step_size * float(i);
+        color += texture(sampler2D(t_input, s_input), v_uv + offset).rgb * weights[i];
+        color += texture(sampler2D(t_input, s_input), v_uv - offset).rgb * weights[i];
+    }
+    f_color = vec4(color, 1.0);
+}
@@ -0,0 +1,21 @@
+// post_bloom_composite.frag
+#version 450
+
+layout(location=0) in vec2 v_uv;
+layout(location=0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform texture2D t_input;
+layout(set = 0, binding = 1) uniform sampler s_input;
+
+layout(set = 0, binding = 2)
+uniform Params {
+    float intensity;
+};
+
+layout(set = 0, binding = 3) uniform texture2D t_bloom;
+
+void main() {
+    vec4 input_color = texture(sampler2D(t_input, s_input), v_uv);
+    vec3 bloom = texture(sampler2D(t_bloom, s_input), v_uv).rgb;
+    f_color = vec4(input_color.rgb + bloom * intensity, input_color.a);
+}
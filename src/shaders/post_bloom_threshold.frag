@@ -0,0 +1,31 @@
+// post_bloom_threshold.frag
+#version 450
+
+layout(location=0) in vec2 v_uv;
+layout(location=0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform texture2D t_input;
+layout(set = 0, binding = 1) uniform sampler s_input;
+
+layout(set = 0, binding = 2)
+uniform Params {
+    float threshold;
+    float knee;
+};
+
+// Drawn at half resolution, so average four texels of the input on the way down
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(sampler2D(t_input, s_input), 0));
+    vec3 color = 0.25 * (
+        texture(sampler2D(t_input, s_input), v_uv + vec2(-0.5, -0.5) * texel).rgb +
+        texture(sampler2D(t_input, s_input), v_uv + vec2(0.5, -0.5) * texel).rgb +
+        texture(sampler2D(t_input, s_input), v_uv + vec2(-0.5, 0.5) * texel).rgb +
+        texture(sampler2D(t_input, s_input), v_uv + vec2(0.5, 0.5) * texel).rgb);
+
+    // Soft knee around the threshold so bright areas don't pop in
+    float brightness = max(color.r, max(color.g, color.b));
+    float soft = clamp(brightness - threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee + 0.00001);
+    float contribution = max(soft, brightness - threshold) / max(brightness, 0.00001);
+    f_color = vec4(color * contribution, 1.0);
+}
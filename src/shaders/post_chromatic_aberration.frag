@@ -0,0 +1,22 @@
+// post_chromatic_aberration.frag
+#version 450
+
+layout(location=0) in vec2 v_uv;
+layout(location=0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform texture2D t_input;
+layout(set = 0, binding = 1) uniform sampler s_input;
+
+layout(set = 0, binding = 2)
+uniform Params {
+    float intensity;
+};
+
+void main() {
+    // Channels drift apart towards the edges of the screen
+    vec2 offset = (v_uv - vec2(0.5)) * intensity;
+    float r = texture(sampler2D(t_input, s_input), v_uv + offset).r;
+    vec4 g = texture(sampler2D(t_input, s_input), v_uv);
+    float b = texture(sampler2D(t_input, s_input), v_uv - offset).b;
+    f_color = vec4(r, g.g, b, g.a);
+}
@@ -0,0 +1,24 @@
+// post_color_grading.frag
+#version 450
+
+layout(location=0) in vec2 v_uv;
+layout(location=0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform texture2D t_input;
+layout(set = 0, binding = 1) uniform sampler s_input;
+
+layout(set = 0, binding = 2)
+uniform Params {
+    float intensity;
+    float lut_size;
+};
+
+layout(set = 0, binding = 3) uniform texture3D t_lut;
+
+void main() {
+    vec4 input_color = texture(sampler2D(t_input, s_input), v_uv);
+    // Land on texel centers so the ends of the LUT aren't blended with the border
+    vec3 uvw = clamp(input_color.rgb, 0.0, 1.0) * ((lut_size - 1.0) / lut_size) + 0.5 / lut_size;
+    vec3 graded = texture(sampler3D(t_lut, s_input), uvw).rgb;
+    f_color = vec4(mix(input_color.rgb, graded, intensity), input_color.a);
+}
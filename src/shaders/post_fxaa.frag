@@ -0,0 +1,54 @@
+// post_fxaa.frag
+#version 450
+
+layout(location=0) in vec2 v_uv;
+layout(location=0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform texture2D t_input;
+layout(set = 0, binding = 1) uniform sampler s_input;
+
+layout(set = 0, binding = 2)
+uniform Params {
+    float span_max;
+    float reduce_mul;
+    float reduce_min;
+};
+
+vec3 sample_at(vec2 uv) {
+    return texture(sampler2D(t_input, s_input), uv).rgb;
+}
+
+// The classic low quality FXAA: find the edge direction from the luma of the
+// four diagonal neighbours and blur along it
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(sampler2D(t_input, s_input), 0));
+    const vec3 luma = vec3(0.299, 0.587, 0.114);
+
+    vec4 center = texture(sampler2D(t_input, s_input), v_uv);
+    float luma_nw = dot(sample_at(v_uv + vec2(-1.0, -1.0) * texel), luma);
+    float luma_ne = dot(sample_at(v_uv + vec2(1.0, -1.0) * texel), luma);
+    float luma_sw = dot(sample_at(v_uv + vec2(-1.0, 1.0) * texel), luma);
+    float luma_se = dot(sample_at(v_uv + vec2(1.0, 1.0) * texel), luma);
+    float luma_m = dot(center.rgb, luma);
+
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    vec2 dir = vec2(-((luma_nw + luma_ne) - (luma_sw + luma_se)),
+                    (luma_nw + luma_sw) - (luma_ne + luma_se));
+    float dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * (0.25 * reduce_mul), reduce_min);
+    float rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * rcp_dir_min, vec2(-span_max), vec2(span_max)) * texel;
+
+    vec3 rgb_a = 0.5 * (sample_at(v_uv + dir * (1.0 / 3.0 - 0.5)) +
+                        sample_at(v_uv + dir * (2.0 / 3.0 - 0.5)));
+    vec3 rgb_b = rgb_a * 0.5 + 0.25 * (sample_at(v_uv + dir * -0.5) +
+                                       sample_at(v_uv + dir * 0.5));
+    float luma_b = dot(rgb_b, luma);
+
+    if (luma_b < luma_min || luma_b > luma_max) {
+        f_color = vec4(rgb_a, center.a);
+    } else {
+        f_color = vec4(rgb_b, center.a);
+    }
+}
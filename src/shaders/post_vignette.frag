@@ -0,0 +1,24 @@
+// post_vignette.frag
+#version 450
+
+layout(location=0) in vec2 v_uv;
+layout(location=0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform texture2D t_input;
+layout(set = 0, binding = 1) uniform sampler s_input;
+
+layout(set = 0, binding = 2)
+uniform Params {
+    vec4 color;
+    float intensity;
+    float radius;
+    float softness;
+};
+
+void main() {
+    vec4 input_color = texture(sampler2D(t_input, s_input), v_uv);
+    // 1.0 in the corners
+    float dist = distance(v_uv, vec2(0.5)) * 1.41421356;
+    float amount = smoothstep(radius, radius + softness, dist) * intensity;
+    f_color = vec4(mix(input_color.rgb, color.rgb, amount), input_color.a);
+}
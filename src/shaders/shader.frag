@@ -6,8 +6,12 @@ layout(location=0) out vec4 f_color;
 layout(set = 0, binding = 0) 
 uniform Uniforms {
     vec4 in_color;
+    float alpha_cutoff;
 };
 
 void main() {
+    if (in_color.a < alpha_cutoff) {
+        discard;
+    }
     f_color = in_color;
 }
@@ -3,6 +3,17 @@
 
 layout(location=0) in vec3 a_position;
 
+layout(set = 1, binding = 0)
+uniform Object {
+    mat4 model;
+};
+
+layout(set = 2, binding = 0)
+uniform Camera {
+    mat4 view_proj;
+    vec4 eye;
+};
+
 void main() {
-    gl_Position = vec4(a_position, 1.0);
+    gl_Position = view_proj * model * vec4(a_position, 1.0);
 }
@@ -0,0 +1,22 @@
+// textured.frag
+#version 450
+
+layout(location=0) in vec2 v_uv;
+
+layout(location=0) out vec4 f_color;
+
+layout(set = 0, binding = 0) 
+uniform Uniforms {
+    vec4 in_color;
+    float alpha_cutoff;
+};
+layout(set = 0, binding = 1) uniform texture2D t_albedo;
+layout(set = 0, binding = 2) uniform sampler s_albedo;
+
+void main() {
+    vec4 color = in_color * texture(sampler2D(t_albedo, s_albedo), v_uv);
+    if (color.a < alpha_cutoff) {
+        discard;
+    }
+    f_color = color;
+}
@@ -0,0 +1,24 @@
+// textured.vert
+#version 450
+
+layout(location=0) in vec3 a_position;
+layout(location=1) in vec3 a_normal;
+layout(location=2) in vec2 a_uv;
+
+layout(location=0) out vec2 v_uv;
+
+layout(set = 1, binding = 0)
+uniform Object {
+    mat4 model;
+};
+
+layout(set = 2, binding = 0)
+uniform Camera {
+    mat4 view_proj;
+    vec4 eye;
+};
+
+void main() {
+    v_uv = a_uv;
+    gl_Position = view_proj * model * vec4(a_position, 1.0);
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use crate::error::RendererError;
+use crate::geometry::Geometry;
+
+// Binary or ASCII STL. Every facet lists its own corners, so corners at the same position
+// are welded into one vertex. Facet normals are dropped, the welded mesh gets smooth ones.
+pub(crate) fn read(bytes: &[u8]) -> Result<Geometry, RendererError> {
+    let triangles = if is_binary(bytes) {
+        read_binary(bytes)
+    }
+    else {
+        read_ascii(bytes)?
+    };
+    // Usually a binary file with padding after the triangles, which reads as ascii when it starts with "solid"
+    if triangles.is_empty() {
+        return Err(RendererError::InvalidAsset("stl: no triangles, or a binary file whose size doesn't match its triangle count".to_string()));
+    }
+
+    let mut vertices: HashMap<[u32; 3], u32> = HashMap::new();
+    let mut geometry = Geometry::default();
+    for corner in triangles.iter().flatten() {
+        // Welds exact matches only, -0.0 and 0.0 are the same point though
+        let key = [corner.x + 0.0, corner.y + 0.0, corner.z + 0.0].map(f32::to_bits);
+        let index = *vertices.entry(key).or_insert_with(|| {
+            geometry.vertex_positions.push(*corner);
+            (geometry.vertex_positions.len() - 1) as u32
+        });
+        geometry.indices.push(index);
+    }
+
+    Ok(geometry)
+}
+
+// Binary files can start with "solid" too, so go by whether the size matches the
+// triangle count in the header
+fn is_binary(bytes: &[u8]) -> bool {
+    if bytes.len() < 84 {
+        return false;
+    }
+    let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+    bytes.len() == 84 + count * 50
+}
+
+// 80 byte header, triangle count, then a normal, three corners and two attribute bytes per triangle
+fn read_binary(bytes: &[u8]) -> Vec<[glam::Vec3; 3]> {
+    let read_f32 = |at: usize| f32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+    let read_vec3 = |at: usize| glam::Vec3::new(read_f32(at), read_f32(at + 4), read_f32(at + 8));
+
+    (0..(bytes.len() - 84) / 50)
+        .map(|i| {
+            let at = 84 + i * 50;
+            [read_vec3(at + 12), read_vec3(at + 24), read_vec3(at + 36)]
+        })
+        .collect()
+}
+
+fn read_ascii(bytes: &[u8]) -> Result<Vec<[glam::Vec3; 3]>, RendererError> {
+    let text = String::from_utf8_lossy(bytes);
+    if !text.trim_start().starts_with("solid") {
+        return Err(RendererError::InvalidAsset("stl: neither binary nor ascii".to_string()));
+    }
+
+    let mut triangles = Vec::new();
+    let mut corners = Vec::with_capacity(3);
+    for (line_number, line) in text.lines().enumerate() {
+        let mut parts = line.split_whitespace();
+        match parts.next() {
+            Some("vertex") => {
+                let coords: Vec<f32> = parts.filter_map(|part| part.parse().ok()).collect();
+                if coords.len() != 3 {
+                    return Err(RendererError::InvalidAsset(format!("stl line {}: bad vertex", line_number + 1)));
+                }
+                corners.push(glam::Vec3::new(coords[0], coords[1], coords[2]));
+            },
+            Some("endloop") => {
+                // Facets are meant to be triangles, anything bigger is fanned
+                for i in 1..corners.len().saturating_sub(1) {
+                    triangles.push([corners[0], corners[i], corners[i + 1]]);
+                }
+                corners.clear();
+            },
+            _ => {}
+        }
+    }
+
+    Ok(triangles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec3;
+
+    // Two triangles sharing an edge, the quad from (0, 0, 0) to (1, 1, 0)
+    const QUAD: [[Vec3; 3]; 2] = [
+        [Vec3::ZERO, Vec3::X, Vec3::ONE],
+        [Vec3::ZERO, Vec3::ONE, Vec3::Y]
+    ];
+
+    fn binary(triangles: &[[Vec3; 3]]) -> Vec<u8> {
+        // Starts with "solid" like plenty of exporters write, it has to still be read as binary
+        let mut bytes = b"solid exported".to_vec();
+        bytes.resize(80, 0);
+        bytes.extend_from_slice(&(triangles.len() as u32).to_le_bytes());
+        for triangle in triangles {
+            for value in std::iter::once(Vec3::Z).chain(triangle.iter().copied()).flat_map(|v| [v.x, v.y, v.z]) {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            bytes.extend_from_slice(&[0, 0]);
+        }
+        bytes
+    }
+
+    fn ascii(triangles: &[[Vec3; 3]]) -> Vec<u8> {
+        let mut text = String::from("solid quad\n");
+        for triangle in triangles {
+            text.push_str("  facet normal 0 0 1\n    outer loop\n");
+            for corner in triangle {
+                text.push_str(&format!("      vertex {} {} {}\n", corner.x, corner.y, corner.z));
+            }
+            text.push_str("    endloop\n  endfacet\n");
+        }
+        text.push_str("endsolid quad\n");
+        text.into_bytes()
+    }
+
+    fn check_quad(geometry: &Geometry) {
+        // The shared corners are welded
+        assert_eq!(geometry.vertex_positions.len(), 4);
+        assert_eq!(geometry.indices.len(), 6);
+        let corners: Vec<Vec3> = geometry.indices.iter().map(|&index| geometry.vertex_positions[index as usize]).collect();
+        assert_eq!(corners, QUAD.iter().flatten().copied().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn reads_binary() {
+        check_quad(&read(&binary(&QUAD)).unwrap());
+    }
+
+    #[test]
+    fn reads_ascii() {
+        check_quad(&read(&ascii(&QUAD)).unwrap());
+    }
+
+    #[test]
+    fn rejects_bad_vertices_and_other_files() {
+        let broken = String::from_utf8(ascii(&QUAD)).unwrap().replacen("vertex 1 0 0", "vertex 1 zero", 1);
+        assert!(read(broken.as_bytes()).is_err());
+        assert!(read(b"not an stl file").is_err());
+    }
+
+    #[test]
+    fn rejects_files_without_triangles() {
+        // Padding after the triangles means the size no longer matches, so it's tried as ascii
+        let mut padded = binary(&QUAD);
+        padded.extend_from_slice(&[0; 16]);
+        assert!(read(&padded).is_err());
+        assert!(read(&ascii(&[])).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+pub struct Texture {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView
+}
+
+impl Texture {
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+    pub fn create_depth_texture(device: &wgpu::Device, sc_desc: &wgpu::SwapChainDescriptor, sample_count: u32, label: &str) -> Self {
+        let size = wgpu::Extent3d {
+            width: sc_desc.width,
+            height: sc_desc.height,
+            depth_or_array_layers: 1
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: Texture::DEPTH_FORMAT,
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            view
+        }
+    }
+
+    // Multisampled color target the size of the swap chain, only ever rendered to and resolved
+    pub fn create_msaa_texture(device: &wgpu::Device, 
+                               sc_desc: &wgpu::SwapChainDescriptor, 
+                               format: wgpu::TextureFormat, 
+                               sample_count: u32, 
+                               label: &str) -> Self {
+        let size = wgpu::Extent3d {
+            width: sc_desc.width,
+            height: sc_desc.height,
+            depth_or_array_layers: 1
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            view
+        }
+    }
+
+    // Stands in for the swap chain image when there isn't one, and can be copied out of
+    pub fn create_offscreen_texture(device: &wgpu::Device, sc_desc: &wgpu::SwapChainDescriptor, label: &str) -> Self {
+        let size = wgpu::Extent3d {
+            width: sc_desc.width.max(1),
+            height: sc_desc.height.max(1),
+            depth_or_array_layers: 1
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: sc_desc.format,
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::COPY_SRC
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            view
+        }
+    }
+
+    // Offscreen color target the size of the swap chain that later passes can sample
+    pub fn create_render_target(device: &wgpu::Device, 
+                                sc_desc: &wgpu::SwapChainDescriptor, 
+                                format: wgpu::TextureFormat, 
+                                label: &str) -> Self {
+        Texture::create_sized_render_target(device, sc_desc.width, sc_desc.height, format, label)
+    }
+
+    // Same as above for targets that don't match the swap chain, like the half resolution bloom ones
+    pub fn create_sized_render_target(device: &wgpu::Device, 
+                                      width: u32, 
+                                      height: u32, 
+                                      format: wgpu::TextureFormat, 
+                                      label: &str) -> Self {
+        let size = wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            view
+        }
+    }
+}
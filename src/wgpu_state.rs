@@ -1,66 +1,233 @@
 use winit::window::Window;
+use crate::config::RendererConfig;
+use crate::error::RendererError;
+use crate::texture::Texture;
+use crate::tonemapping::TonemapPass;
 
 pub struct WGPUState {
     pub device: wgpu::Device,
-    pub surface: wgpu::Surface,
+    // Both None when rendering headless
+    pub surface: Option<wgpu::Surface>,
+    pub swap_chain: Option<wgpu::SwapChain>,
     pub queue: wgpu::Queue,
+    // Still describes the frame when headless, there's just no swap chain made from it
     pub sc_desc: wgpu::SwapChainDescriptor,
-    pub swap_chain: wgpu::SwapChain,
     pub swapchain_format: wgpu::TextureFormat,
+    pub features: wgpu::Features,
+    pub limits: wgpu::Limits,
+    pub adapter_info: wgpu::AdapterInfo,
+    pub sample_count: u32,
+    // Only there when sample_count > 1, resolved into the frame at the end of each pass
+    pub msaa_texture: Option<Texture>,
+    pub hdr: bool,
+    // Only there with HDR on, the scene is drawn here and tonemapped into the frame
+    pub hdr_texture: Option<Texture>,
+    pub depth_texture: Texture,
+    // Only there when headless, it takes the swap chain's place as the frame
+    pub offscreen_texture: Option<Texture>,
+    // Whether the offscreen texture holds a drawn frame, it's blank when it's just been made
+    pub offscreen_drawn: bool,
+    // Bumped every time the targets above are recreated, so anything bound to them knows to rebuild
+    pub targets_generation: u64,
     pub size: winit::dpi::PhysicalSize<u32>,
 }
 
 impl WGPUState {
-    pub async fn new(window: &Window) -> Self {
-        let size = window.inner_size();
-
+    pub async fn new(window: &Window, config: &RendererConfig) -> Result<Self, RendererError> {
         // The instance is a handle to our GPU
-        // BackendBit::PRIMARY => Vulkan + Metal + DX12 + Browser WebGPU
-        let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
+        let instance = wgpu::Instance::new(config.backends);
         let surface = unsafe { instance.create_surface(window) };
-        let adapter = instance.request_adapter(
-            &wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-            },
-        ).await.unwrap();
+        let (adapter, device, queue) = request_device(&instance, Some(&surface), config).await?;
 
-        let (device, queue) = adapter.request_device(
-            &wgpu::DeviceDescriptor {
-                features: wgpu::Features::empty(),
-                limits: wgpu::Limits::default(),
-                label: None,
-            },
-            None,
-        ).await.unwrap();
+        let swapchain_format = match config.swapchain_format {
+            Some(format) => format,
+            None => adapter.get_swap_chain_preferred_format(&surface).ok_or(RendererError::NoSwapChainFormat)?
+        };
+
+        Ok(WGPUState::from_device(adapter, device, queue, Some(surface), swapchain_format, window.inner_size(), config))
+    }
 
-        let swapchain_format = adapter.get_swap_chain_preferred_format(&surface).unwrap();
+    // No window, frames are drawn into an offscreen texture of this size
+    pub async fn new_headless(size: winit::dpi::PhysicalSize<u32>, config: &RendererConfig) -> Result<Self, RendererError> {
+        let instance = wgpu::Instance::new(config.backends);
+        let (adapter, device, queue) = request_device(&instance, None, config).await?;
+        let swapchain_format = config.swapchain_format.unwrap_or(wgpu::TextureFormat::Rgba8UnormSrgb);
 
+        Ok(WGPUState::from_device(adapter, device, queue, None, swapchain_format, size, config))
+    }
+
+    fn from_device(adapter: wgpu::Adapter,
+                   device: wgpu::Device,
+                   queue: wgpu::Queue,
+                   surface: Option<wgpu::Surface>,
+                   swapchain_format: wgpu::TextureFormat,
+                   size: winit::dpi::PhysicalSize<u32>,
+                   config: &RendererConfig) -> Self
+    {
         let sc_desc = wgpu::SwapChainDescriptor {
             usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
             format: swapchain_format,
             width: size.width,
             height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
+            present_mode: config.present_mode,
+        };
+        let swap_chain = surface.as_ref().map(|surface| device.create_swap_chain(surface, &sc_desc));
+        let offscreen_texture = if surface.is_none() {
+            Some(Texture::create_offscreen_texture(&device, &sc_desc, "offscreen_texture"))
+        }
+        else {
+            None
         };
-        let swap_chain = device.create_swap_chain(&surface, &sc_desc);
+        let sample_count = 1;
+        let depth_texture = Texture::create_depth_texture(&device, &sc_desc, sample_count, "depth_texture");
 
         Self {
+            features: device.features(),
+            limits: device.limits(),
+            adapter_info: adapter.get_info(),
             device,
             surface,
+            swap_chain,
             queue,
             sc_desc,
-            swap_chain,
             swapchain_format,
+            sample_count,
+            msaa_texture: None,
+            hdr: false,
+            hdr_texture: None,
+            depth_texture,
+            offscreen_texture,
+            offscreen_drawn: false,
+            targets_generation: 0,
             size
         }
     }
 
+    // WebGPU only guarantees 1 and 4, and wgpu can't be asked whether the adapter handles
+    // anything else for the formats we render to, so those are all we take
+    pub fn supports_sample_count(&self, sample_count: u32) -> bool {
+        check_sample_count(sample_count).is_ok()
+    }
+
+    pub fn set_sample_count(&mut self, sample_count: u32) {
+        self.sample_count = sample_count;
+        self.create_render_targets();
+    }
+
+    pub fn set_hdr(&mut self, hdr: bool) {
+        if hdr != self.hdr {
+            self.hdr = hdr;
+            self.create_render_targets();
+        }
+    }
+
+    // The format the scene itself is drawn in
+    pub fn color_format(&self) -> wgpu::TextureFormat {
+        if self.hdr {
+            TonemapPass::HDR_FORMAT
+        }
+        else {
+            self.swapchain_format
+        }
+    }
+
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         self.size = new_size;
         self.sc_desc.width = new_size.width;
         self.sc_desc.height = new_size.height;
-        self.swap_chain = self.device.create_swap_chain(&self.surface, &self.sc_desc);
-        //self.depth_texture = texture::Texture::create_depth_texture(&self.device, &self.sc_desc, "depth_texture");
+        if let Some(surface) = &self.surface {
+            self.swap_chain = Some(self.device.create_swap_chain(surface, &self.sc_desc));
+        }
+        self.create_render_targets();
     }
-}
\ No newline at end of file
+
+    fn create_render_targets(&mut self) {
+        self.depth_texture = Texture::create_depth_texture(&self.device, &self.sc_desc, self.sample_count, "depth_texture");
+        self.msaa_texture = if self.sample_count > 1 {
+            Some(Texture::create_msaa_texture(&self.device, &self.sc_desc, self.color_format(), self.sample_count, "msaa_texture"))
+        }
+        else {
+            None
+        };
+        if self.offscreen_texture.is_some() {
+            self.offscreen_texture = Some(Texture::create_offscreen_texture(&self.device, &self.sc_desc, "offscreen_texture"));
+            self.offscreen_drawn = false;
+        }
+        self.hdr_texture = if self.hdr {
+            Some(Texture::create_render_target(&self.device, &self.sc_desc, TonemapPass::HDR_FORMAT, "hdr_texture"))
+        }
+        else {
+            None
+        };
+        self.targets_generation += 1;
+    }
+}
+
+// Picks the adapter the config asks for and creates its device. Without a surface any
+// adapter will do, there's nothing it has to present to.
+async fn request_device(instance: &wgpu::Instance,
+                        surface: Option<&wgpu::Surface>,
+                        config: &RendererConfig) -> Result<(wgpu::Adapter, wgpu::Device, wgpu::Queue), RendererError>
+{
+    let adapter = if config.force_fallback_adapter {
+        // wgpu can't be asked for a fallback adapter directly, so look for a software one
+        instance.enumerate_adapters(config.backends)
+            .find(|adapter| adapter.get_info().device_type == wgpu::DeviceType::Cpu
+                  && surface.map_or(true, |surface| adapter.get_swap_chain_preferred_format(surface).is_some()))
+    }
+    else {
+        instance.request_adapter(
+            &wgpu::RequestAdapterOptions {
+                power_preference: config.power_preference,
+                compatible_surface: surface,
+            },
+        ).await
+    };
+    let adapter = adapter.ok_or(RendererError::NoAdapter)?;
+    let adapter_info = adapter.get_info();
+    log::info!("Using {} ({:?}, {:?})", adapter_info.name, adapter_info.device_type, adapter_info.backend);
+
+    let missing_features = config.required_features - adapter.features();
+    if !missing_features.is_empty() {
+        return Err(RendererError::MissingFeatures(missing_features));
+    }
+    let features = config.required_features | (adapter.features() & config.optional_features);
+
+    let (device, queue) = adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            features,
+            limits: config.limits.clone(),
+            label: None,
+        },
+        None,
+    ).await?;
+
+    Ok((adapter, device, queue))
+}
+
+// 2 and 8 are real MSAA levels most hardware has, they get their own error so it's clear
+// they were turned down because they can't be checked rather than because they're unsupported
+pub(crate) fn check_sample_count(sample_count: u32) -> Result<(), RendererError> {
+    match sample_count {
+        1 | 4 => Ok(()),
+        2 | 8 => Err(RendererError::UncheckedSampleCount(sample_count)),
+        _ => Err(RendererError::UnsupportedSampleCount(sample_count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_guaranteed_sample_counts_are_taken() {
+        assert!(check_sample_count(1).is_ok());
+        assert!(check_sample_count(4).is_ok());
+        for count in [2, 8] {
+            assert!(matches!(check_sample_count(count), Err(RendererError::UncheckedSampleCount(c)) if c == count));
+        }
+        for count in [0, 3, 16] {
+            assert!(matches!(check_sample_count(count), Err(RendererError::UnsupportedSampleCount(c)) if c == count));
+        }
+    }
+}
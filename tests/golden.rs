@@ -0,0 +1,144 @@
+// Golden image tests. Each fixture scene is drawn through a headless renderer on a software
+// adapter and compared against tests/golden/<name>.png.
+//
+// They need an adapter, so they're ignored by a plain cargo test:
+//
+// cargo test --test golden -- --ignored             compares against the references
+// BLESS=1 cargo test --test golden -- --ignored     writes the references from what's rendered now
+//
+// A missing reference fails unless BLESS is set, so is not having an adapter. On a mismatch
+// the rendered frame and a diff (mismatched pixels in red) are written to target/golden/.
+//
+// The references for box and monkey still have to be blessed and committed. They must come
+// from the fallback adapter these tests force (lavapipe on Linux), any other adapter's output
+// isn't a fair reference for CI.
+
+use std::path::{Path, PathBuf};
+use trips::{Image, Renderer, RendererConfig, RendererError, Scene};
+
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 256;
+// Per pixel YIQ difference, as a fraction of the largest possible one, that counts as a mismatch
+const PIXEL_THRESHOLD: f32 = 0.1;
+// Share of mismatched pixels a render is allowed before it fails, software rasterizers
+// don't all agree on edges
+const ALLOWED_MISMATCH: f32 = 0.005;
+
+fn headless_renderer() -> Renderer {
+    let config = RendererConfig {
+        force_fallback_adapter: true,
+        ..RendererConfig::default()
+    };
+
+    match Renderer::new_headless(WIDTH, HEIGHT, config) {
+        Ok(renderer) => renderer,
+        Err(RendererError::NoAdapter) => panic!("no software adapter available, golden image tests need one (lavapipe, SwiftShader or WARP)"),
+        Err(err) => panic!("couldn't create a headless renderer: {}", err)
+    }
+}
+
+fn manifest_path(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+// YIQ color difference from pixelmatch, with alpha blended against white first
+fn color_delta(a: [u8; 4], b: [u8; 4]) -> f32 {
+    let blend = |p: [u8; 4]| {
+        let alpha = p[3] as f32 / 255.0;
+        [
+            255.0 + (p[0] as f32 - 255.0) * alpha,
+            255.0 + (p[1] as f32 - 255.0) * alpha,
+            255.0 + (p[2] as f32 - 255.0) * alpha
+        ]
+    };
+    let y = |c: [f32; 3]| c[0] * 0.298_895_3 + c[1] * 0.586_622_5 + c[2] * 0.114_482_2;
+    let i = |c: [f32; 3]| c[0] * 0.595_978 - c[1] * 0.274_176_1 - c[2] * 0.321_801_9;
+    let q = |c: [f32; 3]| c[0] * 0.211_470_2 - c[1] * 0.522_617_1 + c[2] * 0.311_146_9;
+
+    let (a, b) = (blend(a), blend(b));
+    let (dy, di, dq) = (y(a) - y(b), i(a) - i(b), q(a) - q(b));
+    // 35215 is the delta between black and white
+    (0.5053 * dy * dy + 0.299 * di * di + 0.1957 * dq * dq) / 35215.0
+}
+
+// Mismatched pixels in red over a faded copy of the reference
+fn compare(expected: &Image, actual: &Image) -> (usize, Image) {
+    let mut mismatched = 0;
+    let mut diff = Vec::with_capacity(expected.pixels.len());
+    for (e, a) in expected.pixels.chunks_exact(4).zip(actual.pixels.chunks_exact(4)) {
+        let (e, a) = ([e[0], e[1], e[2], e[3]], [a[0], a[1], a[2], a[3]]);
+        if color_delta(e, a) > PIXEL_THRESHOLD * PIXEL_THRESHOLD {
+            mismatched += 1;
+            diff.extend_from_slice(&[255, 0, 0, 255]);
+        }
+        else {
+            let gray = (255.0 - (255.0 - (e[0] as f32 * 0.3 + e[1] as f32 * 0.59 + e[2] as f32 * 0.11)) * 0.1) as u8;
+            diff.extend_from_slice(&[gray, gray, gray, 255]);
+        }
+    }
+
+    (mismatched, Image {
+        width: expected.width,
+        height: expected.height,
+        pixels: diff
+    })
+}
+
+fn check_golden(name: &str, actual: &Image) {
+    let reference = manifest_path(&format!("tests/golden/{}.png", name));
+    if std::env::var_os("BLESS").is_some() {
+        std::fs::create_dir_all(reference.parent().unwrap()).unwrap();
+        actual.save_png(&reference).unwrap();
+        eprintln!("wrote reference {}", reference.display());
+        return;
+    }
+
+    if !reference.exists() {
+        panic!("{}: there's no reference at {}, render one with BLESS=1", name, reference.display());
+    }
+
+    let expected = Image::load_png(&reference).unwrap();
+    let output_dir = manifest_path("target/golden");
+    std::fs::create_dir_all(&output_dir).unwrap();
+    let actual_path = output_dir.join(format!("{}.actual.png", name));
+
+    if (expected.width, expected.height) != (actual.width, actual.height) {
+        actual.save_png(&actual_path).unwrap();
+        panic!("{}: rendered {}x{} but the reference is {}x{}, see {}",
+               name, actual.width, actual.height, expected.width, expected.height, actual_path.display());
+    }
+
+    let (mismatched, diff) = compare(&expected, actual);
+    let total = (actual.width * actual.height) as usize;
+    if mismatched as f32 > total as f32 * ALLOWED_MISMATCH {
+        let diff_path = output_dir.join(format!("{}.diff.png", name));
+        actual.save_png(&actual_path).unwrap();
+        diff.save_png(&diff_path).unwrap();
+        panic!("{}: {} of {} pixels differ from the reference, see {} and {}",
+               name, mismatched, total, actual_path.display(), diff_path.display());
+    }
+}
+
+fn render_fixture(name: &str, mesh_path: &str, camera_eye: glam::Vec3) {
+    let mut renderer = headless_renderer();
+
+    let mut scene = Scene::new();
+    scene.camera.eye = camera_eye;
+    let mesh = renderer.load_mesh(manifest_path(mesh_path).to_str().unwrap());
+    scene.insert(mesh);
+
+    let image = renderer.capture_frame(&mut scene).unwrap();
+    check_golden(name, &image);
+}
+
+#[test]
+#[ignore = "needs a software adapter, run with --ignored"]
+fn golden_box() {
+    render_fixture("box", "res/Box.gltf", glam::Vec3::new(2.0, 2.0, 3.0));
+}
+
+#[test]
+#[ignore = "needs a software adapter, run with --ignored"]
+fn golden_monkey() {
+    render_fixture("monkey", "res/monkey.gltf", glam::Vec3::new(0.0, 0.0, 4.0));
+}